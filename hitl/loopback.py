@@ -75,7 +75,7 @@ async def test_loopback(stabilizer, telemetry_queue, set_point, gain=1, channel=
     latest_values = json.loads((await telemetry_queue.__anext__()).payload)
     print(f"Latest telemtry: {latest_values}")
 
-    assert abs(latest_values["adcs"][channel] - set_point) < tolerance
+    assert abs(latest_values["adcs"][channel]["mean"] - set_point) < tolerance
     print("PASS")
     print("")
 
@@ -121,8 +121,9 @@ def main():
                 await tele.subscribe(f"{prefix}/telemetry")
 
                 # Disable IIR holds and configure the telemetry rate.
-                await stabilizer.set("/allow_hold", False)
-                await stabilizer.set("/force_hold", False)
+                for channel in range(2):
+                    await stabilizer.set(f"/hold/{channel}/source", "Software")
+                    await stabilizer.set(f"/hold/{channel}/force", False)
                 await stabilizer.set("/telemetry_period", 1)
 
                 # Test loopback with a static 1V output of the DACs.
@@ -427,16 +427,11 @@ def _main():
                         "max": stabilizer.voltage_to_machine_units(args.y_max),
                     },
                 )
-            if args.iir_cascade_length == 1:
-                await interface.set(
-                    f"/iir_ch/{args.channel}/1",
-                    {
-                        "ba": [1, 0, 0, 0, 0],
-                        "u": stabilizer.voltage_to_machine_units(args.y_offset),
-                        "min": stabilizer.voltage_to_machine_units(args.y_min),
-                        "max": stabilizer.voltage_to_machine_units(args.y_max),
-                    },
-                )
+            # Only process the configured sections of the cascade.
+            await interface.set(
+                f"/active_sections/{args.channel}",
+                args.iir_cascade_length,
+            )
             await interface.set(
                 path="/cpu_dac1",
                 value=args.cpu_dac1,
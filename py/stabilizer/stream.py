@@ -90,6 +90,93 @@ class AdcDac:
         ]
 
 
+class Fls:
+    """FLS (fiber length stabilization) streaming data format"""
+
+    format_id = 2
+
+    # Per channel: demodulated I/Q, unwrapped phase, DDS FTW and POW
+    dtype = np.dtype(
+        [("iq", "<i4", 2), ("phase", "<i8"), ("ftw", "<u4"), ("pow", "<u4")]
+    )
+
+    def __init__(self, header, body):
+        self.header = header
+        self.body = body
+
+    def size(self):
+        """Return the data size of the frame in bytes"""
+        return len(self.body)
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        data = np.frombuffer(self.body, self.dtype)
+        # channel, batch
+        return data.reshape(self.header.batches, 2).T
+
+    def to_si(self):
+        """Convert the raw data to normalized units"""
+        data = self.to_mu()
+        return {
+            "iq": (data["iq"][..., 0] + 1j * data["iq"][..., 1]) / (1 << 31),
+            # In turns
+            "phase": data["phase"] / (1 << 32),
+            # Relative to the DDS system clock
+            "frequency": data["ftw"] / (1 << 32),
+            # In turns
+            "phase_offset": data["pow"] / (1 << 14),
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data["phase"][0], scale=1 / (1 << 32), label="Phase0"),
+            Trace(data["phase"][1], scale=1 / (1 << 32), label="Phase1"),
+        ]
+
+
+class Lockin:
+    """Lockin demodulation streaming data format"""
+
+    format_id = 3
+
+    def __init__(self, header, body):
+        self.header = header
+        self.body = body
+
+    def size(self):
+        """Return the data size of the frame in bytes"""
+        return len(self.body)
+
+    def to_mu(self):
+        """Return the raw data in machine units"""
+        data = np.frombuffer(self.body, "<i4")
+        # batch, field
+        return data.reshape(self.header.batches, 6).T
+
+    def to_si(self):
+        """Convert the raw data to normalized units"""
+        data = self.to_mu()
+        return {
+            "iq": (data[0:4:2] + 1j * data[1:4:2]) / (1 << 31),
+            # Relative to the sample rate
+            "frequency": data[4] / (1 << 32),
+            # In turns
+            "phase": data[5] / (1 << 32),
+        }
+
+    def to_traces(self):
+        """Convert the raw data to labelled Trace instances"""
+        data = self.to_mu()
+        return [
+            Trace(data[0], scale=1 / (1 << 31), label="I0"),
+            Trace(data[1], scale=1 / (1 << 31), label="Q0"),
+            Trace(data[2], scale=1 / (1 << 31), label="I1"),
+            Trace(data[3], scale=1 / (1 << 31), label="Q1"),
+        ]
+
+
 class StabilizerStream(asyncio.DatagramProtocol):
     """Stabilizer streaming receiver protocol"""
 
@@ -99,6 +186,8 @@ class StabilizerStream(asyncio.DatagramProtocol):
     header = namedtuple("Header", "magic format_id batches sequence")
     parsers = {
         AdcDac.format_id: AdcDac,
+        Fls.format_id: Fls,
+        Lockin.format_id: Lockin,
     }
 
     @classmethod
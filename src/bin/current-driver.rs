@@ -0,0 +1,1006 @@
+//! # Current Driver
+//!
+//! The `current-driver` application operates Stabilizer with the current-sense frontend as a
+//! laser diode current controller.
+//!
+//! DAC0 programs an external voltage-controlled current source driving the diode. The diode
+//! current is measured through the current-sense frontend on ADC0. The frontend offset DAC shifts
+//! the current-sense input into the ADC range. ADC1 is the modulation input. Its signal is added
+//! to the programmed current. The internal DAC output (`cpu_dac1`) is the interlock output. It is
+//! at full scale while the interlock is clear and at zero while it is tripped, e.g. to drive the
+//! enable input of the current source or a shorting relay across the diode.
+//!
+//! ## Features
+//! * Current setpoint in milliampere
+//! * Slew rate limited ramps on enable, disable and setpoint changes
+//! * Hard current limit
+//! * Compliance monitoring of the current source
+//! * External interlock input on DI0 or DI1
+//! * Latching interlock with a hardware interlock output
+//! * Modulation input
+//! * Telemetry in physical units
+//! * Input/Output data streaming
+//!
+//! ## Interlock
+//! The interlock trips if any of the following occurs:
+//! * The measured current exceeds the current limit.
+//! * The measured current deviates from the programmed current by more than the compliance
+//!   tolerance for longer than the compliance time. This happens when the current source runs out
+//!   of compliance voltage, e.g. due to an open or degraded diode.
+//! * The external interlock input is asserted.
+//!
+//! A tripped interlock immediately sets the programmed current to zero and clears the interlock
+//! output. It stays tripped until it is reset through `reset_interlock`. The output then ramps to
+//! the setpoint again if enabled.
+//!
+//! ## Settings
+//! Refer to the [CurrentDriver] structure for documentation of run-time configurable settings
+//! for this application.
+//!
+//! ## Telemetry
+//! Refer to [Telemetry] for information about telemetry reported by this application.
+//!
+//! ## Livestreaming
+//! This application streams raw ADC and DAC data over UDP. Refer to
+//! [stabilizer::net::data_stream](../stabilizer/net/data_stream/index.html) for more information.
+#![no_std]
+#![no_main]
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{fence, Ordering};
+use serde::{Deserialize, Serialize};
+
+use rtic_monotonics::Monotonic;
+
+use fugit::ExtU32;
+use mutex_trait::prelude::*;
+
+use stabilizer::{
+    hardware::{
+        self,
+        adc::{Adc0Input, Adc1Input, AdcCode},
+        afe::Gain,
+        dac::{Dac0Output, Dac1Output, DacCode},
+        hal,
+        timers::SamplingTimer,
+        CpuDacOutput1, DigitalInput0, DigitalInput1, GpioDacSpi,
+        SerialTerminal, SystemTimer, Systick, UsbDevice, AFE0, AFE1,
+    },
+    net::{
+        data_stream::{FrameGenerator, StreamFormat, StreamTarget},
+        miniconf::Tree,
+        telemetry::{Events, Statistics, TelemetryBuffer},
+        NetworkState, NetworkUsers,
+    },
+    settings::{NetSettings, SamplingSettings},
+};
+
+use hal::traits::DacOut;
+
+/// The internal DAC output code while the interlock is clear.
+const INTERLOCK_CLEAR: u16 = 4095;
+
+#[derive(Clone, Debug, Tree)]
+pub struct Settings {
+    #[tree(depth = 2)]
+    pub current_driver: CurrentDriver,
+
+    #[tree(depth = 1)]
+    pub net: NetSettings,
+
+    #[tree(depth = 1)]
+    pub sampling: SamplingSettings,
+}
+
+impl stabilizer::settings::AppSettings for Settings {
+    fn new(net: NetSettings) -> Self {
+        Self {
+            net,
+            current_driver: CurrentDriver::default(),
+            sampling: SamplingSettings::default(),
+        }
+    }
+
+    fn net(&self) -> &NetSettings {
+        &self.net
+    }
+
+    fn sampling(&self) -> &SamplingSettings {
+        &self.sampling
+    }
+}
+
+impl serial_settings::Settings<3> for Settings {
+    fn reset(&mut self) {
+        *self = Self {
+            current_driver: CurrentDriver::default(),
+            net: NetSettings::new(self.net.mac),
+            sampling: SamplingSettings::default(),
+        }
+    }
+}
+
+/// The digital input used as the external interlock.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum InterlockInput {
+    /// No external interlock.
+    None,
+    /// Digital input DI0.
+    Di0,
+    /// Digital input DI1.
+    Di1,
+}
+
+/// External interlock configuration.
+///
+/// The input is sampled once per batch.
+///
+/// # Miniconf
+/// `{"input": "Di0", "invert": false}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct InterlockConfig {
+    /// The external interlock input. See [InterlockInput].
+    pub input: InterlockInput,
+
+    /// Assert the interlock while the input is low instead of high.
+    pub invert: bool,
+}
+
+impl Default for InterlockConfig {
+    fn default() -> Self {
+        Self {
+            input: InterlockInput::None,
+            invert: false,
+        }
+    }
+}
+
+impl InterlockConfig {
+    /// Whether the external interlock is asserted.
+    ///
+    /// # Args
+    /// * `inputs` - The states of DI0 and DI1.
+    pub fn asserted(&self, inputs: [bool; 2]) -> bool {
+        let input = match self.input {
+            InterlockInput::None => return false,
+            InterlockInput::Di0 => inputs[0],
+            InterlockInput::Di1 => inputs[1],
+        };
+        input != self.invert
+    }
+}
+
+/// Compliance monitoring configuration.
+///
+/// # Miniconf
+/// `{"tolerance": 5.0, "time": 0.001}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct ComplianceConfig {
+    /// The maximum deviation of the measured current from the programmed current in milliampere.
+    pub tolerance: f32,
+
+    /// The time in seconds the deviation has to persist to trip the interlock. It should exceed
+    /// the settling time of the current source and the latency of a batch.
+    pub time: f32,
+}
+
+impl Default for ComplianceConfig {
+    fn default() -> Self {
+        Self {
+            tolerance: 5.,
+            time: 1e-3,
+        }
+    }
+}
+
+/// Configuration derived from the [CurrentDriver] settings in hardware units.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Config {
+    /// The current setpoint in milliampere.
+    setpoint: f32,
+    /// The maximum change of the programmed current per sample in milliampere.
+    step: f32,
+    /// The current limit in milliampere.
+    max_current: f32,
+    /// The measured current per ADC0 LSB in milliampere.
+    sense: f32,
+    /// The measured current offset in milliampere.
+    sense_offset: f32,
+    /// The modulation current per ADC1 LSB in milliampere.
+    modulation: f32,
+    /// The DAC0 LSB per milliampere.
+    drive: f32,
+    /// The compliance tolerance in milliampere.
+    tolerance: f32,
+    /// The number of samples beyond the compliance tolerance tripping the interlock.
+    compliance_samples: u32,
+}
+
+#[derive(Clone, Debug, Tree)]
+pub struct CurrentDriver {
+    /// Configure the Analog Front End (AFE) gain.
+    ///
+    /// # Path
+    /// `afe/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// Any of the variants of [Gain] enclosed in double quotes.
+    #[tree(depth = 1)]
+    afe: [Gain; 2],
+
+    /// Configure the current-sense frontend offset DAC.
+    ///
+    /// # Path
+    /// `frontend_offset`
+    ///
+    /// # Value
+    /// Any value between 0 and 65535.
+    frontend_offset: u16,
+
+    /// Enable the current output.
+    ///
+    /// # Path
+    /// `enable`
+    ///
+    /// # Value
+    /// "true" or "false". The programmed current ramps to the setpoint when enabled and to zero
+    /// when disabled.
+    enable: bool,
+
+    /// Configure the current setpoint.
+    ///
+    /// # Path
+    /// `setpoint`
+    ///
+    /// # Value
+    /// The current in milliampere, between zero and the current limit.
+    setpoint: f32,
+
+    /// Configure the slew rate.
+    ///
+    /// # Path
+    /// `slew_rate`
+    ///
+    /// # Value
+    /// The maximum rate of change of the programmed current in milliampere per second. Any
+    /// positive value.
+    slew_rate: f32,
+
+    /// Configure the current limit.
+    ///
+    /// # Path
+    /// `max_current`
+    ///
+    /// # Value
+    /// The current in milliampere above which the interlock trips. Any positive value. The
+    /// programmed current including the modulation is also clamped to it.
+    max_current: f32,
+
+    /// Configure the compliance monitoring.
+    ///
+    /// # Path
+    /// `compliance`
+    ///
+    /// # Value
+    /// See [ComplianceConfig]
+    #[tree(depth = 1)]
+    compliance: ComplianceConfig,
+
+    /// Configure the external interlock.
+    ///
+    /// # Path
+    /// `interlock`
+    ///
+    /// # Value
+    /// See [InterlockConfig]
+    #[tree(depth = 1)]
+    interlock: InterlockConfig,
+
+    /// Reset a tripped interlock.
+    ///
+    /// # Path
+    /// `reset_interlock`
+    ///
+    /// # Value
+    /// "true" resets the interlock. The value is cleared once the reset has been applied. The
+    /// interlock trips again immediately if the cause persists.
+    reset_interlock: bool,
+
+    /// Configure the current source transconductance.
+    ///
+    /// # Path
+    /// `transconductance`
+    ///
+    /// # Value
+    /// The current source output current per DAC0 output voltage in milliampere per volt. Any
+    /// non-zero value.
+    transconductance: f32,
+
+    /// Configure the current-sense gain.
+    ///
+    /// # Path
+    /// `sense_gain`
+    ///
+    /// # Value
+    /// The measured current per current-sense voltage at the ADC0 input in milliampere per volt.
+    sense_gain: f32,
+
+    /// Configure the current-sense offset.
+    ///
+    /// # Path
+    /// `sense_offset`
+    ///
+    /// # Value
+    /// The measured current in milliampere at zero output current, e.g. due to the frontend
+    /// offset. It is subtracted from the measured current.
+    sense_offset: f32,
+
+    /// Configure the modulation gain.
+    ///
+    /// # Path
+    /// `modulation_gain`
+    ///
+    /// # Value
+    /// The modulation current per voltage at the ADC1 input in milliampere per volt. Zero
+    /// disables the modulation. The modulation is only applied while the programmed current is
+    /// non-zero.
+    modulation_gain: f32,
+
+    /// Specifies the telemetry output period in seconds.
+    ///
+    /// # Path
+    /// `telemetry_period`
+    ///
+    /// # Value
+    /// Any non-zero value less than 65536.
+    telemetry_period: u16,
+
+    /// Specifies the target for data livestreaming.
+    ///
+    /// # Path
+    /// `stream_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    stream_target: StreamTarget,
+}
+
+impl Default for CurrentDriver {
+    fn default() -> Self {
+        Self {
+            afe: [Gain::G1; 2],
+            frontend_offset: 0,
+            enable: false,
+            setpoint: 0.,
+            slew_rate: 1e3,
+            max_current: 100.,
+            compliance: ComplianceConfig::default(),
+            interlock: InterlockConfig::default(),
+            reset_interlock: false,
+            transconductance: 10.,
+            sense_gain: 10.,
+            sense_offset: 0.,
+            modulation_gain: 0.,
+            // The default telemetry period in seconds.
+            telemetry_period: 10,
+
+            stream_target: StreamTarget::default(),
+        }
+    }
+}
+
+impl CurrentDriver {
+    /// Update the derived [Config] from the settings.
+    ///
+    /// Invalid settings are logged and leave the respective configuration unchanged.
+    ///
+    /// # Args
+    /// * `config` - The configuration to update.
+    /// * `sample_period` - The time in seconds between samples.
+    fn configure(&self, config: &mut Config, sample_period: f32) {
+        if self.max_current > 0. {
+            config.max_current = self.max_current;
+        } else {
+            log::error!("Invalid current limit: {}", self.max_current);
+        }
+
+        if (0. ..=config.max_current).contains(&self.setpoint) {
+            config.setpoint = self.setpoint;
+        } else {
+            log::error!(
+                "Invalid current setpoint: {} (limit {})",
+                self.setpoint,
+                config.max_current
+            );
+        }
+        // A lowered current limit also limits the previous setpoint.
+        config.setpoint = config.setpoint.min(config.max_current);
+
+        if self.slew_rate > 0. {
+            config.step = self.slew_rate * sample_period;
+        } else {
+            log::error!("Invalid slew rate: {}", self.slew_rate);
+        }
+
+        if self.transconductance.is_normal() {
+            config.drive = 1. / (self.transconductance * DacCode::VOLT_PER_LSB);
+        } else {
+            log::error!("Invalid transconductance: {}", self.transconductance);
+        }
+
+        config.sense = self.sense_gain * AdcCode::VOLT_PER_LSB
+            / self.afe[0].as_multiplier();
+        config.sense_offset = self.sense_offset;
+        config.modulation = self.modulation_gain * AdcCode::VOLT_PER_LSB
+            / self.afe[1].as_multiplier();
+
+        if self.compliance.tolerance > 0. && self.compliance.time >= 0. {
+            config.tolerance = self.compliance.tolerance;
+            // Note(as): The conversion saturates.
+            config.compliance_samples =
+                (self.compliance.time / sample_period) as u32;
+        } else {
+            log::error!(
+                "Invalid compliance configuration: {:?}",
+                self.compliance
+            );
+        }
+    }
+}
+
+/// The cause of a tripped interlock.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum Trip {
+    /// The measured current exceeded the current limit.
+    Current,
+    /// The measured current deviated from the programmed current for too long.
+    Compliance,
+    /// The external interlock input was asserted.
+    External,
+}
+
+/// The state of the current output.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize)]
+pub enum DriverState {
+    /// The programmed current is zero.
+    #[default]
+    Off,
+    /// The programmed current is ramping.
+    Ramping,
+    /// The programmed current is at the setpoint.
+    On,
+    /// The interlock is tripped.
+    Interlocked,
+}
+
+/// Run-time state of the current driver.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Driver {
+    // The programmed current without modulation in milliampere.
+    programmed: f32,
+    // The most recent programmed current including modulation in milliampere.
+    output: f32,
+    // The most recent measured current in milliampere.
+    current: f32,
+    // The most recent modulation current in milliampere.
+    modulation: f32,
+    // The number of consecutive samples beyond the compliance tolerance.
+    excursion: u32,
+    // The output state.
+    state: DriverState,
+    // The cause of the tripped interlock.
+    trip: Option<Trip>,
+}
+
+impl Driver {
+    /// Trip the interlock.
+    ///
+    /// The first cause is retained until the interlock is reset.
+    ///
+    /// # Args
+    /// * `trip` - The cause.
+    pub fn trip(&mut self, trip: Trip) {
+        self.trip.get_or_insert(trip);
+        self.programmed = 0.;
+        self.output = 0.;
+        self.state = DriverState::Interlocked;
+    }
+
+    /// Reset the interlock.
+    pub fn reset(&mut self) {
+        self.trip = None;
+        self.excursion = 0;
+        self.state = DriverState::Off;
+    }
+
+    /// Whether the interlock is tripped.
+    pub fn tripped(&self) -> bool {
+        self.trip.is_some()
+    }
+
+    /// Process a sample.
+    ///
+    /// # Args
+    /// * `config` - The driver configuration.
+    /// * `enable` - Whether the current output is enabled.
+    /// * `current` - The measured current in ADC0 LSB.
+    /// * `modulation` - The modulation input in ADC1 LSB.
+    ///
+    /// # Returns
+    /// The current source control voltage in DAC0 LSB.
+    #[inline]
+    pub fn update(
+        &mut self,
+        config: &Config,
+        enable: bool,
+        current: i16,
+        modulation: i16,
+    ) -> f32 {
+        self.current = current as f32 * config.sense - config.sense_offset;
+        self.modulation = modulation as f32 * config.modulation;
+
+        if self.current.abs() > config.max_current {
+            self.trip(Trip::Current);
+        }
+
+        if (self.current - self.output).abs() > config.tolerance {
+            self.excursion = self.excursion.saturating_add(1);
+            if self.excursion > config.compliance_samples {
+                self.trip(Trip::Compliance);
+            }
+        } else {
+            self.excursion = 0;
+        }
+
+        if self.tripped() {
+            return 0.;
+        }
+
+        let target = if enable { config.setpoint } else { 0. };
+        self.programmed +=
+            (target - self.programmed).clamp(-config.step, config.step);
+        self.state = if self.programmed != target {
+            DriverState::Ramping
+        } else if target > 0. {
+            DriverState::On
+        } else {
+            DriverState::Off
+        };
+
+        self.output = if self.programmed > 0. {
+            (self.programmed + self.modulation).clamp(0., config.max_current)
+        } else {
+            0.
+        };
+        self.output * config.drive
+    }
+
+    /// Collect the driver telemetry.
+    ///
+    /// # Args
+    /// * `config` - The driver configuration.
+    pub fn telemetry(&self, config: &Config) -> DriverTelemetry {
+        DriverTelemetry {
+            state: self.state,
+            trip: self.trip,
+            current: self.current,
+            programmed: self.programmed,
+            modulation: self.modulation,
+            drive: self.output * config.drive * DacCode::VOLT_PER_LSB,
+        }
+    }
+}
+
+/// Telemetry of the current driver.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct DriverTelemetry {
+    /// The output state.
+    state: DriverState,
+
+    /// The cause of the tripped interlock or `None` if the interlock is clear.
+    trip: Option<Trip>,
+
+    /// The most recent measured current in milliampere.
+    current: f32,
+
+    /// The programmed current without modulation in milliampere.
+    programmed: f32,
+
+    /// The most recent modulation current in milliampere.
+    modulation: f32,
+
+    /// The most recent current source control voltage at DAC0 in volts.
+    drive: f32,
+}
+
+/// Telemetry reported by the current driver application.
+///
+/// This extends the common [stabilizer::net::telemetry::Telemetry] by application-specific
+/// fields.
+#[derive(Serialize)]
+pub struct Telemetry {
+    /// Input voltage statistics over the telemetry period.
+    adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    dacs: [Statistics; 2],
+
+    /// Event counters of each channel over the telemetry period.
+    events: [Events; 2],
+
+    /// Most recent digital input assertion state.
+    digital_inputs: [bool; 2],
+
+    /// The CPU temperature in degrees Celsius.
+    cpu_temp: f32,
+
+    /// The current driver telemetry.
+    driver: DriverTelemetry,
+}
+
+impl Telemetry {
+    fn new(
+        telemetry: stabilizer::net::telemetry::Telemetry,
+        driver: DriverTelemetry,
+    ) -> Self {
+        Self {
+            adcs: telemetry.adcs,
+            dacs: telemetry.dacs,
+            events: telemetry.events,
+            digital_inputs: telemetry.digital_inputs,
+            cpu_temp: telemetry.cpu_temp,
+            driver,
+        }
+    }
+}
+
+#[rtic::app(device = stabilizer::hardware::hal::stm32, peripherals = true, dispatchers=[DCMI, JPEG, SDMMC])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        usb: UsbDevice,
+        network: NetworkUsers<CurrentDriver, 2>,
+        settings: Settings,
+        active_settings: CurrentDriver,
+        config: Config,
+        telemetry: TelemetryBuffer,
+        driver: Driver,
+    }
+
+    #[local]
+    struct Local {
+        usb_terminal: SerialTerminal<Settings, 3>,
+        sampling_timer: SamplingTimer,
+        sampling: SamplingSettings,
+        digital_inputs: (DigitalInput0, DigitalInput1),
+        afes: (AFE0, AFE1),
+        adcs: (Adc0Input, Adc1Input),
+        dacs: (Dac0Output, Dac1Output),
+        gpio_dac_spi: Option<GpioDacSpi>,
+        cpu_dac1: CpuDacOutput1,
+        generator: FrameGenerator,
+        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+    }
+
+    #[init]
+    fn init(c: init::Context) -> (Shared, Local) {
+        let clock = SystemTimer::new(|| Systick::now().ticks());
+
+        // Configure the microcontroller
+        let mut stabilizer =
+            hardware::setup::setup::<Settings, 3>(c.core, c.device, clock);
+
+        let mut network = NetworkUsers::new(
+            stabilizer.net.stack,
+            stabilizer.net.phy,
+            clock,
+            env!("CARGO_BIN_NAME"),
+            &stabilizer.settings.net,
+            stabilizer.metadata,
+        );
+
+        let generator = network.configure_streaming(StreamFormat::AdcDacData);
+
+        // The interlock output indicates a trip until the first batch has been processed.
+        stabilizer.cpu_dac1.set_value(0);
+
+        let shared = Shared {
+            network,
+            usb: stabilizer.usb,
+            telemetry: TelemetryBuffer::default(),
+            driver: Driver::default(),
+            active_settings: stabilizer.settings.current_driver.clone(),
+            config: Config::default(),
+            settings: stabilizer.settings,
+        };
+
+        let mut local = Local {
+            usb_terminal: stabilizer.usb_serial,
+            sampling_timer: stabilizer.adc_dac_timer,
+            sampling: stabilizer.sampling,
+            digital_inputs: stabilizer.digital_inputs,
+            afes: stabilizer.afes,
+            adcs: stabilizer.adcs,
+            dacs: stabilizer.dacs,
+            gpio_dac_spi: stabilizer.gpio_dac_spi,
+            cpu_dac1: stabilizer.cpu_dac1,
+            generator,
+            cpu_temp_sensor: stabilizer.temperature_sensor,
+        };
+
+        // Enable ADC/DAC events
+        local.adcs.0.start();
+        local.adcs.1.start();
+        local.dacs.0.start();
+        local.dacs.1.start();
+
+        // Spawn a settings and telemetry update for default settings.
+        settings_update::spawn().unwrap();
+        telemetry::spawn().unwrap();
+        ethernet_link::spawn().unwrap();
+        start::spawn().unwrap();
+        usb::spawn().unwrap();
+
+        (shared, local)
+    }
+
+    #[task(priority = 1, local=[sampling_timer])]
+    async fn start(c: start::Context) {
+        Systick::delay(100.millis()).await;
+        // Start sampling ADCs and DACs.
+        c.local.sampling_timer.start();
+    }
+
+    /// Main DSP processing routine.
+    ///
+    /// See `dual-iir` for general notes on processing time and timing.
+    ///
+    /// The external interlock input is sampled once per batch. Each sample of the measured
+    /// current is checked against the current limit and the compliance tolerance before the
+    /// programmed current is ramped and the modulation is added. DAC1 is unused and held at
+    /// zero.
+    #[task(binds=DMA1_STR4, shared=[active_settings, config, telemetry, driver], local=[digital_inputs, adcs, dacs, cpu_dac1, generator], priority=3)]
+    #[link_section = ".itcm.process"]
+    fn process(c: process::Context) {
+        let process::SharedResources {
+            active_settings,
+            config,
+            telemetry,
+            driver,
+            ..
+        } = c.shared;
+
+        let process::LocalResources {
+            digital_inputs,
+            adcs: (adc0, adc1),
+            dacs: (dac0, dac1),
+            cpu_dac1,
+            generator,
+            ..
+        } = c.local;
+
+        (active_settings, config, telemetry, driver).lock(
+            |settings, config, telemetry, driver| {
+                let digital_inputs =
+                    [digital_inputs.0.is_high(), digital_inputs.1.is_high()];
+                telemetry.digital_inputs = digital_inputs;
+
+                if settings.interlock.asserted(digital_inputs) {
+                    driver.trip(Trip::External);
+                }
+
+                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                    let adc_samples = [adc0, adc1];
+                    let dac_samples = [dac0, dac1];
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+
+                    for sample in 0..adc_samples[0].len() {
+                        let y = driver.update(
+                            config,
+                            settings.enable,
+                            adc_samples[0][sample] as i16,
+                            adc_samples[1][sample] as i16,
+                        );
+
+                        let clipped =
+                            !(i16::MIN as f32..=i16::MAX as f32).contains(&y);
+                        if clipped {
+                            let events = &mut telemetry.events[0];
+                            events.dac_clip = events.dac_clip.saturating_add(1);
+                        }
+
+                        // Note(unsafe): The value is clamped to the i16 range.
+                        let y: i16 = unsafe {
+                            y.clamp(i16::MIN as _, i16::MAX as _)
+                                .to_int_unchecked()
+                        };
+
+                        dac_samples[0][sample] = DacCode::from(y).0;
+                        dac_samples[1][sample] = DacCode::from(0i16).0;
+                    }
+
+                    cpu_dac1.set_value(if driver.tripped() {
+                        0
+                    } else {
+                        INTERLOCK_CLEAR
+                    });
+
+                    // Stream the data.
+                    let n = adc_samples[0].len() * core::mem::size_of::<i16>();
+                    generator.add(|buf| {
+                        for (data, buf) in adc_samples
+                            .iter()
+                            .chain(dac_samples.iter())
+                            .zip(buf.chunks_exact_mut(n))
+                        {
+                            let data = unsafe {
+                                core::slice::from_raw_parts(
+                                    data.as_ptr() as *const MaybeUninit<u8>,
+                                    n,
+                                )
+                            };
+                            buf.copy_from_slice(data)
+                        }
+                        n * 4
+                    });
+
+                    // Update telemetry measurements.
+                    telemetry.add(
+                        [adc_samples[0], adc_samples[1]],
+                        [dac_samples[0], dac_samples[1]],
+                    );
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+                });
+            },
+        );
+    }
+
+    #[idle(shared=[settings, network, usb])]
+    fn idle(mut c: idle::Context) -> ! {
+        loop {
+            match (&mut c.shared.network, &mut c.shared.settings)
+                .lock(|net, settings| net.update(&mut settings.current_driver))
+            {
+                NetworkState::SettingsChanged => {
+                    settings_update::spawn().unwrap()
+                }
+                NetworkState::Updated => {}
+                NetworkState::NoChange => {
+                    // We can't sleep if USB is not in suspend.
+                    if c.shared.usb.lock(|usb| {
+                        usb.state()
+                            == usb_device::device::UsbDeviceState::Suspend
+                    }) {
+                        cortex_m::asm::wfi();
+                    }
+                }
+            }
+        }
+    }
+
+    #[task(priority = 1, local=[afes, sampling, gpio_dac_spi], shared=[network, settings, active_settings, config, driver])]
+    async fn settings_update(mut c: settings_update::Context) {
+        let sample_period = c.local.sampling.sample_period();
+        let mut config = c.shared.config.lock(|config| *config);
+
+        c.shared.settings.lock(|settings| {
+            let current_driver = &mut settings.current_driver;
+            current_driver.configure(&mut config, sample_period);
+
+            c.local.afes.0.set_gain(current_driver.afe[0]);
+            c.local.afes.1.set_gain(current_driver.afe[1]);
+
+            match c.local.gpio_dac_spi {
+                Some(spi) => {
+                    if let Err(err) =
+                        spi.write(&[current_driver.frontend_offset])
+                    {
+                        log::error!(
+                            "Failed to update frontend offset DAC: {:?}",
+                            err
+                        );
+                    }
+                }
+                None => log::error!("Frontend offset DAC unavailable"),
+            }
+
+            c.shared
+                .network
+                .lock(|net| net.direct_stream(current_driver.stream_target));
+
+            (&mut c.shared.active_settings, &mut c.shared.config).lock(
+                |current, current_config| {
+                    *current = current_driver.clone();
+                    *current_config = config;
+                },
+            );
+
+            if current_driver.reset_interlock {
+                current_driver.reset_interlock = false;
+                c.shared.driver.lock(|driver| {
+                    if let Some(trip) = driver.trip {
+                        log::info!("Resetting interlock tripped by {:?}", trip);
+                    }
+                    driver.reset();
+                });
+            }
+        });
+    }
+
+    #[task(priority = 1, local=[cpu_temp_sensor], shared=[network, settings, config, telemetry, driver])]
+    async fn telemetry(mut c: telemetry::Context) {
+        loop {
+            let telemetry: TelemetryBuffer =
+                c.shared.telemetry.lock(core::mem::take);
+
+            let (gains, telemetry_period) =
+                c.shared.settings.lock(|settings| {
+                    (
+                        settings.current_driver.afe,
+                        settings.current_driver.telemetry_period,
+                    )
+                });
+            let config = c.shared.config.lock(|config| *config);
+
+            let driver =
+                c.shared.driver.lock(|driver| driver.telemetry(&config));
+
+            c.shared.network.lock(|net| {
+                net.telemetry.publish(&Telemetry::new(
+                    telemetry.finalize(
+                        gains[0],
+                        gains[1],
+                        c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                    ),
+                    driver,
+                ))
+            });
+
+            // Schedule the telemetry task in the future.
+            Systick::delay((telemetry_period as u32).secs()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[usb, settings], local=[usb_terminal])]
+    async fn usb(mut c: usb::Context) {
+        loop {
+            // Handle the USB serial terminal.
+            c.shared.usb.lock(|usb| {
+                usb.poll(&mut [c
+                    .local
+                    .usb_terminal
+                    .interface_mut()
+                    .inner_mut()]);
+            });
+
+            c.shared.settings.lock(|settings| {
+                if c.local.usb_terminal.poll(settings).unwrap() {
+                    settings_update::spawn().unwrap()
+                }
+            });
+
+            Systick::delay(10.millis()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[network])]
+    async fn ethernet_link(mut c: ethernet_link::Context) {
+        loop {
+            c.shared.network.lock(|net| net.processor.handle_link());
+            Systick::delay(1.secs()).await;
+        }
+    }
+
+    #[task(binds = ETH, priority = 1)]
+    fn eth(_: eth::Context) {
+        unsafe { hal::ethernet::interrupt_handler() }
+    }
+}
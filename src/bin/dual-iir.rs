@@ -14,6 +14,9 @@
 //! * Generic biquad (second order) IIR filter
 //! * Anti-windup
 //! * Derivative kick avoidance
+//! * Automatic lock acquisition
+//! * Swept-sine network analyzer
+//! * Triggered transient capture
 //!
 //! ## Settings
 //! Refer to the [DualIir] structure for documentation of run-time configurable settings for this
@@ -40,42 +43,56 @@ use mutex_trait::prelude::*;
 use idsp::iir;
 
 use stabilizer::{
+    filter_design, fixed_iir,
     hardware::{
         self,
         adc::{Adc0Input, Adc1Input, AdcCode},
         afe::Gain,
+        capture::{
+            Capture, Condition, Link as CaptureLink, Request, Slope, Source,
+        },
         dac::{Dac0Output, Dac1Output, DacCode},
         hal,
-        signal_generator::{self, SignalGenerator},
+        network_analyzer::{Link, Measurement, NetworkAnalyzer},
+        signal_generator::{self, Signal, SignalGenerator},
         timers::SamplingTimer,
-        CpuDacOutput1, DigitalInput0, DigitalInput1, GpioDacSpi,
-        SerialTerminal, SystemTimer, Systick, UsbDevice, AFE0, AFE1,
+        CpuDacOutput1, DigitalInput0, DigitalInput1, EemDigitalInput0,
+        EemDigitalInput1, GpioDacSpi, SerialTerminal, SystemTimer, Systick,
+        UsbDevice, AFE0, AFE1,
     },
     net::{
         data_stream::{FrameGenerator, StreamFormat, StreamTarget},
         miniconf::Tree,
-        telemetry::TelemetryBuffer,
+        telemetry::{Events, Statistics, TelemetryBuffer},
         NetworkState, NetworkUsers,
     },
-    settings::NetSettings,
+    settings::{NetSettings, SamplingSettings},
 };
 
 use hal::traits::DacOut;
 
 const SCALE: f32 = i16::MAX as _;
 
-// The number of cascaded IIR biquads per channel. Select 1 or 2!
-const IIR_CASCADE_LENGTH: usize = 2;
+// The maximum number of cascaded IIR biquads per channel. The number of sections that are actually
+// processed is configured at run-time through `DualIir::active_sections`.
+const IIR_CASCADE_LENGTH: usize = 4;
 
-// The number of samples in each batch process
-const BATCH_SIZE: usize = 8;
+// The number of fractional bits of the fixed-point mixing matrix weights.
+const MATRIX_SHIFT: u32 = 16;
 
-// The logarithm of the number of 100MHz timer ticks between each sample. With a value of 2^7 =
-// 128, there is 1.28uS per sample, corresponding to a sampling frequency of 781.25 KHz.
-const SAMPLE_TICKS_LOG2: u8 = 7;
-const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
-const SAMPLE_PERIOD: f32 =
-    SAMPLE_TICKS as f32 * hardware::design_parameters::TIMER_PERIOD;
+// The settling time of the frontend after a frontend offset DAC update during auto-zero.
+const AUTO_ZERO_SETTLE_MS: u32 = 1;
+
+// The margin in milliseconds added to the expected network analyzer measurement duration before a
+// measurement times out.
+const NETWORK_ANALYZER_MARGIN_MS: u32 = 100;
+
+// The number of samples per channel in each published capture chunk. The serialized chunk must fit
+// into the telemetry MQTT buffer.
+const CAPTURE_CHUNK: usize = 64;
+
+// The number of attempts to publish a capture chunk before the readout is aborted.
+const CAPTURE_PUBLISH_ATTEMPTS: u32 = 100;
 
 #[derive(Clone, Debug, Tree)]
 pub struct Settings {
@@ -84,6 +101,9 @@ pub struct Settings {
 
     #[tree(depth = 1)]
     pub net: NetSettings,
+
+    #[tree(depth = 1)]
+    pub sampling: SamplingSettings,
 }
 
 impl stabilizer::settings::AppSettings for Settings {
@@ -91,12 +111,17 @@ impl stabilizer::settings::AppSettings for Settings {
         Self {
             net,
             dual_iir: DualIir::default(),
+            sampling: SamplingSettings::default(),
         }
     }
 
     fn net(&self) -> &NetSettings {
         &self.net
     }
+
+    fn sampling(&self) -> &SamplingSettings {
+        &self.sampling
+    }
 }
 
 impl serial_settings::Settings<4> for Settings {
@@ -104,6 +129,7 @@ impl serial_settings::Settings<4> for Settings {
         *self = Self {
             dual_iir: DualIir::default(),
             net: NetSettings::new(self.net.mac),
+            sampling: SamplingSettings::default(),
         }
     }
 }
@@ -129,8 +155,20 @@ pub struct DualIir {
     ///
     /// # Value
     /// Any value between 0 and 4095.
+    ///
+    /// When the offload integrator is enabled, this is the initial value of the integrator.
     cpu_dac1: u16,
 
+    /// Configure the offload integrator driving the internal DAC output.
+    ///
+    /// # Path
+    /// `offload`
+    ///
+    /// # Value
+    /// See [OffloadConfig]
+    #[tree(depth = 1)]
+    offload: OffloadConfig,
+
     ///Configure the current_sense frontend offset dac.
     ///
     /// # Path
@@ -140,97 +178,1608 @@ pub struct DualIir {
     /// Any value between 0 and 65535.
     frontend_offset: u16,
 
+    /// Configure the frontend offset auto-zero.
+    ///
+    /// # Path
+    /// `auto_zero`
+    ///
+    /// # Value
+    /// See [AutoZero]
+    #[tree(depth = 1)]
+    auto_zero: AutoZero,
+
     /// Configure the IIR filter parameters.
     ///
     /// # Path
     /// `iir_ch/<n>/<m>`
     ///
     /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
-    /// * `<m>` specifies which cascade to configure. `<m>` := [0, 1], depending on [IIR_CASCADE_LENGTH]
+    /// * `<m>` specifies which cascade to configure. `<m>` := [0, 3], see [IIR_CASCADE_LENGTH]
     ///
     /// See [iir::Biquad]
     #[tree(depth = 2)]
     iir_ch: [[iir::Biquad<f32>; IIR_CASCADE_LENGTH]; 2],
 
-    /// Specified true if DI1 should be used as a "hold" input.
-    ///
-    /// # Path
-    /// `allow_hold`
-    ///
-    /// # Value
-    /// "true" or "false"
-    allow_hold: bool,
+    /// Configure IIR filter sections from physical units.
+    ///
+    /// # Path
+    /// `iir_design/<n>/<m>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    /// * `<m>` specifies which cascade to configure. `<m>` := [0, 3], see [IIR_CASCADE_LENGTH]
+    ///
+    /// # Value
+    /// `null` or a [filter_design::Design]. When not `null`, the design is compiled and
+    /// replaces `iir_ch/<n>/<m>` on each settings update. Invalid designs are logged and
+    /// leave the section unchanged.
+    #[tree(depth = 2)]
+    iir_design: [[Option<filter_design::Design>; IIR_CASCADE_LENGTH]; 2],
+
+    /// Configure the number of active IIR cascade sections.
+    ///
+    /// # Path
+    /// `active_sections/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The number of leading sections of `iir_ch/<n>` that are processed, between 0 and
+    /// [IIR_CASCADE_LENGTH]. Inactive sections are skipped and their state is cleared.
+    #[tree(depth = 1)]
+    active_sections: [usize; 2],
+
+    /// Configure the input mixing matrix from the ADC inputs to the IIR channel inputs.
+    ///
+    /// # Path
+    /// `input_matrix/<n>/<m>`
+    ///
+    /// * `<n>` specifies the IIR channel. `<n>` := [0, 1]
+    /// * `<m>` specifies the ADC input. `<m>` := [0, 1]
+    ///
+    /// # Value
+    /// The weight of ADC `<m>` in the input of IIR channel `<n>`.
+    #[tree(depth = 2)]
+    input_matrix: [[f32; 2]; 2],
+
+    /// Configure the output mixing matrix from the IIR channel outputs to the DAC outputs.
+    ///
+    /// # Path
+    /// `output_matrix/<n>/<m>`
+    ///
+    /// * `<n>` specifies the DAC output. `<n>` := [0, 1]
+    /// * `<m>` specifies the IIR channel. `<m>` := [0, 1]
+    ///
+    /// # Value
+    /// The weight of IIR channel `<m>` in the output of DAC `<n>`. The mixed output is clamped
+    /// to the DAC range before the signal generator is added.
+    #[tree(depth = 2)]
+    output_matrix: [[f32; 2]; 2],
+
+    /// Enable bumpless transfer of IIR filter updates.
+    ///
+    /// # Path
+    /// `bumpless_transfer`
+    ///
+    /// # Value
+    /// "true" or "false"
+    ///
+    /// When enabled, the output history of each active biquad section is adjusted on a
+    /// settings update such that the first output of the new filter matches the output the
+    /// previous filter would have produced. Filters without a DC path through the feedback
+    /// coefficients (`a1 + a2 == 0`) are updated without adjustment.
+    bumpless_transfer: bool,
+
+    /// Enable fixed-point processing of the IIR cascades.
+    ///
+    /// # Path
+    /// `fixed_point`
+    ///
+    /// # Value
+    /// "true" or "false"
+    ///
+    /// When enabled, the sections of `iir_ch` are converted to fixed-point on each settings
+    /// update and the channels are processed with integer arithmetic from the ADC to the DAC
+    /// codes, including mixing, setpoint, lock acquisition and hold, see [fixed_iir]. The
+    /// processing is bit-exact and deterministic. Sections with coefficients of magnitude 32 or
+    /// above cannot be converted. If an active section cannot be converted, the settings update
+    /// is rejected and logged. Bumpless transfer only applies to floating point processing.
+    /// Switching between floating point and fixed-point restarts setpoint and hold ramps.
+    fixed_point: bool,
+
+    /// Configure the automatic lock acquisition.
+    ///
+    /// # Path
+    /// `lock/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [LockConfig]
+    #[tree(depth = 2)]
+    lock: [LockConfig; 2],
+
+    /// Configure the IIR channel setpoint.
+    ///
+    /// # Path
+    /// `setpoint/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [SetpointConfig]
+    #[tree(depth = 2)]
+    setpoint: [SetpointConfig; 2],
+
+    /// Configure the IIR channel hold.
+    ///
+    /// # Path
+    /// `hold/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [HoldConfig]
+    #[tree(depth = 2)]
+    hold: [HoldConfig; 2],
+
+    /// Specifies the telemetry output period in seconds.
+    ///
+    /// # Path
+    /// `telemetry_period`
+    ///
+    /// # Value
+    /// Any non-zero value less than 65536.
+    telemetry_period: u16,
+
+    /// Specifies the target for data livestreaming.
+    ///
+    /// # Path
+    /// `stream_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    stream_target: StreamTarget,
+
+    /// Specifies the config for signal generators to add on to DAC0/DAC1 outputs.
+    ///
+    /// # Path
+    /// `signal_generator/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [signal_generator::BasicConfig#miniconf]
+    #[tree(depth = 2)]
+    signal_generator: [signal_generator::BasicConfig; 2],
+
+    /// Configure the network analyzer.
+    ///
+    /// # Path
+    /// `network_analyzer`
+    ///
+    /// # Value
+    /// See [NetworkAnalyzerConfig]
+    #[tree(depth = 1)]
+    network_analyzer: NetworkAnalyzerConfig,
+
+    /// Configure the transient capture.
+    ///
+    /// # Path
+    /// `capture`
+    ///
+    /// # Value
+    /// See [CaptureConfig]
+    #[tree(depth = 1)]
+    capture: CaptureConfig,
+}
+
+impl Default for DualIir {
+    fn default() -> Self {
+        let mut i = iir::Biquad::IDENTITY;
+        i.set_min(-SCALE);
+        i.set_max(SCALE);
+        Self {
+            // Analog frontend programmable gain amplifier gains (G1, G2, G5, G10)
+            afe: [Gain::G1, Gain::G1],
+            // CPU DAC1 output
+            cpu_dac1: 0,
+            // The internal DAC output is static.
+            offload: OffloadConfig::default(),
+            // Frontend offset DAC
+            frontend_offset: 0,
+            auto_zero: AutoZero::default(),
+            // IIR filter tap gains are an array `[b0, b1, b2, a1, a2]` such that the
+            // new output is computed as `y0 = a1*y1 + a2*y2 + b0*x0 + b1*x1 + b2*x2`.
+            // The array is `iir_state[channel-index][cascade-index][coeff-index]`.
+            // The IIR coefficients can be mapped to other transfer function
+            // representations, for example as described in https://arxiv.org/abs/1508.06319
+            iir_ch: [[i; IIR_CASCADE_LENGTH]; 2],
+            // Sections are configured from raw coefficients.
+            iir_design: [[None; IIR_CASCADE_LENGTH]; 2],
+            // Process the first two sections of each cascade.
+            active_sections: [2; 2],
+            // ADC n feeds IIR channel n feeds DAC n.
+            input_matrix: [[1., 0.], [0., 1.]],
+            output_matrix: [[1., 0.], [0., 1.]],
+            // Coefficient updates are applied as-is.
+            bumpless_transfer: false,
+            // The cascades are processed in floating point.
+            fixed_point: false,
+            // The channels are always locked.
+            lock: [LockConfig::default(); 2],
+            // The channels are locked to zero.
+            setpoint: [SetpointConfig::default(); 2],
+
+            // The channels are never held.
+            hold: [HoldConfig::default(); 2],
+            // The default telemetry period in seconds.
+            telemetry_period: 10,
+
+            signal_generator: [signal_generator::BasicConfig::default(); 2],
+
+            stream_target: StreamTarget::default(),
+
+            network_analyzer: NetworkAnalyzerConfig::default(),
+
+            capture: CaptureConfig::default(),
+        }
+    }
+}
+
+/// A signal that can be demodulated by the network analyzer or recorded by the capture.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Probe {
+    /// The network analyzer excitation.
+    Excitation,
+    /// The ADC0 input.
+    Adc0,
+    /// The ADC1 input.
+    Adc1,
+    /// The DAC0 output.
+    Dac0,
+    /// The DAC1 output.
+    Dac1,
+}
+
+impl Probe {
+    /// Select the probed signal code.
+    #[inline]
+    fn select(self, excitation: i16, adc: [i16; 2], dac: [i16; 2]) -> i16 {
+        match self {
+            Probe::Excitation => excitation,
+            Probe::Adc0 => adc[0],
+            Probe::Adc1 => adc[1],
+            Probe::Dac0 => dac[0],
+            Probe::Dac1 => dac[1],
+        }
+    }
+
+    /// The probed signal in volts per code.
+    ///
+    /// ADC inputs are referred to the input of the AFE.
+    fn volts_per_lsb(self, afe: &[Gain; 2]) -> f32 {
+        match self {
+            Probe::Excitation | Probe::Dac0 | Probe::Dac1 => {
+                DacCode::VOLT_PER_LSB
+            }
+            Probe::Adc0 => AdcCode::VOLT_PER_LSB / afe[0].as_multiplier(),
+            Probe::Adc1 => AdcCode::VOLT_PER_LSB / afe[1].as_multiplier(),
+        }
+    }
+}
+
+/// Network analyzer configuration.
+///
+/// A sine excitation is added to a DAC output while stepping its frequency logarithmically from
+/// `start` to `stop`. At each frequency, the `reference` and `response` signals are demodulated
+/// and the transfer function point from the reference to the response is published on the
+/// `<prefix>/network_analyzer` MQTT topic. See [stabilizer::hardware::network_analyzer::Point].
+///
+/// # Miniconf
+/// `{"channel": 0, "reference": "Excitation", "response": "Adc0", "start": 10.0,
+/// "stop": 100000.0, "points": 100, "amplitude": 0.1, "settle_time": 0.01,
+/// "integration_time": 0.1, "trigger": false}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct NetworkAnalyzerConfig {
+    /// The DAC output channel to add the excitation to.
+    pub channel: usize,
+
+    /// The reference signal. See [Probe].
+    pub reference: Probe,
+
+    /// The response signal. See [Probe].
+    pub response: Probe,
+
+    /// The start frequency in Hertz.
+    pub start: f32,
+
+    /// The stop frequency in Hertz.
+    pub stop: f32,
+
+    /// The number of frequency points.
+    pub points: u32,
+
+    /// The excitation amplitude in volts.
+    pub amplitude: f32,
+
+    /// The settling time in seconds before each point is measured.
+    pub settle_time: f32,
+
+    /// The minimum integration time in seconds of each point.
+    pub integration_time: f32,
+
+    /// Set to start a sweep. It is cleared once the sweep has been started.
+    pub trigger: bool,
+}
+
+impl Default for NetworkAnalyzerConfig {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            reference: Probe::Excitation,
+            response: Probe::Adc0,
+            start: 10.0,
+            stop: 1.0e5,
+            points: 100,
+            amplitude: 0.1,
+            settle_time: 0.01,
+            integration_time: 0.1,
+            trigger: false,
+        }
+    }
+}
+
+/// Transient capture configuration.
+///
+/// Once armed, two signals are recorded into an on-chip ring buffer. After the trigger fires and
+/// the post-trigger samples have been recorded, the capture is published in chunks on the
+/// `<prefix>/capture` MQTT topic. See [CaptureChunk]. Chunks that can not be sent are retried. The
+/// capture is retained until the next capture has completed and can be published again with
+/// `readout`. A capture that has not completed within `timeout` is aborted.
+///
+/// # Miniconf
+/// `{"probe": ["Adc0", "Dac0"], "source": "Channel0", "condition": "Edge", "slope": "Rising",
+/// "level": 0.0, "pre_trigger": 1024, "length": 4096, "timeout": 10.0, "arm": false,
+/// "force": false, "readout": false}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct CaptureConfig {
+    /// The signals recorded on capture channel 0 and 1. See [Probe]. They are applied when the
+    /// capture is armed.
+    pub probe: [Probe; 2],
+
+    /// The trigger source. See [Source].
+    pub source: Source,
+
+    /// The trigger condition. See [Condition].
+    pub condition: Condition,
+
+    /// The trigger slope. See [Slope].
+    pub slope: Slope,
+
+    /// The trigger level in volts for capture channel sources.
+    pub level: f32,
+
+    /// The number of samples recorded before the trigger.
+    pub pre_trigger: u32,
+
+    /// The total number of samples recorded per channel, up to 16384.
+    pub length: u32,
+
+    /// The time in seconds after which an armed capture that has not completed is aborted. Any
+    /// positive value.
+    pub timeout: f32,
+
+    /// Set to arm a capture. It is cleared once the capture has been armed.
+    pub arm: bool,
+
+    /// Set to trigger the armed capture as soon as the pre-trigger samples have been recorded. It
+    /// is cleared once applied.
+    pub force: bool,
+
+    /// Set to publish the most recent capture again. It is cleared once the readout has been
+    /// started.
+    pub readout: bool,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> Self {
+        Self {
+            probe: [Probe::Adc0, Probe::Dac0],
+            source: Source::Software,
+            condition: Condition::Edge,
+            slope: Slope::Rising,
+            level: 0.,
+            pre_trigger: 1024,
+            length: 4096,
+            timeout: 10.,
+            arm: false,
+            force: false,
+            readout: false,
+        }
+    }
+}
+
+/// A chunk of a transient capture as published on the `<prefix>/capture` MQTT topic.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct CaptureChunk<'a> {
+    /// The capture sequence number. It is incremented with every completed capture.
+    pub capture: u32,
+
+    /// The index of the chunk.
+    pub index: u32,
+
+    /// The number of chunks of the capture.
+    pub chunks: u32,
+
+    /// The index of the first sample of the chunk relative to the trigger sample.
+    pub offset: i32,
+
+    /// The sample period in seconds.
+    pub period: f32,
+
+    /// The recorded signals.
+    pub probe: [Probe; 2],
+
+    /// The scale of the recorded codes in volts per LSB.
+    pub scale: [f32; 2],
+
+    /// The recorded codes of each channel.
+    pub data: [&'a [i16]; 2],
+}
+
+/// The parameters of the most recently completed capture.
+#[derive(Copy, Clone, Debug)]
+pub struct CaptureInfo {
+    sequence: u32,
+    probe: [Probe; 2],
+    scale: [f32; 2],
+    period: f32,
+}
+
+/// Offload integrator configuration.
+///
+/// The offload integrator integrates the mean output of a DAC channel and drives the internal DAC
+/// output (`cpu_dac1`) with the result. This offloads the slow part of the actuation from the fast
+/// DAC output onto a slow actuator with a larger range.
+///
+/// # Miniconf
+/// `{"enable": true, "channel": 0, "gain": 100.0, "min": 0, "max": 4095}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct OffloadConfig {
+    /// Enable the offload integrator. When disabled, `cpu_dac1` is applied statically.
+    pub enable: bool,
+
+    /// The DAC output channel to offload.
+    pub channel: usize,
+
+    /// The integrator gain in internal DAC LSB per second and volt of the DAC output mean. The
+    /// sign has to match the relative polarity of the two actuators.
+    pub gain: f32,
+
+    /// The minimum internal DAC output code. It is limited to `max`.
+    pub min: u16,
+
+    /// The maximum internal DAC output code. At most 4095.
+    pub max: u16,
+}
+
+impl Default for OffloadConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            channel: 0,
+            gain: 0.0,
+            min: 0,
+            max: 4095,
+        }
+    }
+}
+
+/// Run-time offload integrator.
+#[derive(Copy, Clone)]
+pub struct Offload {
+    // The integrator state in internal DAC LSB. `None` while disabled.
+    value: Option<f32>,
+    // The time between samples in seconds.
+    sample_period: f32,
+}
+
+impl Offload {
+    /// Construct a disabled offload integrator.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    pub fn new(sample_period: f32) -> Self {
+        Self {
+            value: None,
+            sample_period,
+        }
+    }
+
+    /// Update the integrator with a batch of DAC output codes.
+    ///
+    /// # Args
+    /// * `config` - The offload integrator configuration.
+    /// * `initial` - The internal DAC code to use when disabled and to start the integrator from.
+    /// * `samples` - The batch of DAC output codes of the configured channel, if it exists.
+    ///
+    /// # Returns
+    /// The internal DAC code.
+    #[inline]
+    pub fn update(
+        &mut self,
+        config: &OffloadConfig,
+        initial: u16,
+        samples: Option<&[u16]>,
+    ) -> u16 {
+        let Some(samples) = samples.filter(|_| config.enable) else {
+            self.value = None;
+            return initial;
+        };
+
+        let sum: i32 = samples
+            .iter()
+            .map(|&code| i16::from(DacCode(code)) as i32)
+            .sum();
+        let mean = sum as f32 * DacCode::VOLT_PER_LSB / samples.len() as f32;
+        let period = samples.len() as f32 * self.sample_period;
+        let max = config.max.min(4095) as f32;
+        // An inverted range must not panic in the DSP routine.
+        let min = (config.min as f32).min(max);
+        let value = self.value.get_or_insert(initial as f32);
+        *value = (*value + config.gain * mean * period).clamp(min, max);
+        *value as u16
+    }
+}
+
+/// Frontend offset auto-zero configuration.
+///
+/// The auto-zero searches the frontend offset DAC code for which the mean ADC input is zero.
+/// The offset DAC is bisected after verifying that its full range brackets zero. The search
+/// terminates once the magnitude of the mean input is within the tolerance. The converged code is
+/// written to `frontend_offset` and reported in telemetry together with the residual mean input.
+/// The search is aborted if no ADC samples are accumulated. Settings updates do not write the
+/// offset DAC while the search is running.
+///
+/// The auto-zero can also be started with the `auto-zero` platform command on the USB terminal.
+///
+/// # Miniconf
+/// `{"channel": 0, "window": 0.01, "tolerance": 0.001, "trigger": false}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct AutoZero {
+    /// The ADC input channel to zero.
+    pub channel: usize,
+
+    /// The averaging window of the ADC input in seconds for each offset DAC code.
+    pub window: f32,
+
+    /// The tolerance of the mean ADC input in volts.
+    pub tolerance: f32,
+
+    /// Set to start the auto-zero. It is cleared once the auto-zero has been started.
+    pub trigger: bool,
+}
+
+impl Default for AutoZero {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            window: 0.01,
+            tolerance: 1.0e-3,
+            trigger: false,
+        }
+    }
+}
+
+/// The result of a frontend offset auto-zero.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct AutoZeroResult {
+    /// The converged frontend offset DAC code.
+    pub code: u16,
+
+    /// The residual mean input in volts.
+    pub residual: f32,
+}
+
+/// Accumulator of the ADC input means.
+#[derive(Copy, Clone, Default)]
+pub struct AdcMean {
+    active: bool,
+    sum: [i64; 2],
+    count: u32,
+}
+
+impl AdcMean {
+    /// Clear the accumulator and start accumulating.
+    pub fn start(&mut self) {
+        *self = Self {
+            active: true,
+            ..Default::default()
+        };
+    }
+
+    /// Add a batch of ADC input sums if active.
+    pub fn add(&mut self, sum: [i32; 2], count: u32) {
+        if self.active {
+            self.sum[0] += sum[0] as i64;
+            self.sum[1] += sum[1] as i64;
+            self.count += count;
+        }
+    }
+
+    /// Stop accumulating.
+    ///
+    /// # Returns
+    /// The mean ADC input codes if any samples have been accumulated.
+    pub fn finish(&mut self) -> Option<[f32; 2]> {
+        self.active = false;
+        (self.count > 0).then(|| {
+            [
+                self.sum[0] as f32 / self.count as f32,
+                self.sum[1] as f32 / self.count as f32,
+            ]
+        })
+    }
+}
+
+/// The input asserting an IIR channel hold.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HoldSource {
+    /// Software only. See [HoldConfig::force].
+    Software,
+    /// Digital input DI0.
+    Di0,
+    /// Digital input DI1.
+    Di1,
+    /// EEM LVDS input 4.
+    Lvds4,
+    /// EEM LVDS input 5.
+    Lvds5,
+}
+
+/// The IIR channel output behaviour during hold.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum HoldBehaviour {
+    /// Hold the last output.
+    Last,
+    /// Output zero. The filter state is cleared.
+    Zero,
+    /// Ramp linearly from the last output to the preset value.
+    Ramp,
+}
+
+/// IIR channel hold configuration.
+///
+/// The IIR channel is held while the hold source input is asserted or the hold is forced. The
+/// inputs are sampled once per batch.
+///
+/// # Miniconf
+/// `{"source": "Di1", "invert": false, "force": false, "behaviour": "Ramp", "preset": 0.0,
+/// "ramp_time": 0.01}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct HoldConfig {
+    /// The input asserting the hold. See [HoldSource].
+    pub source: HoldSource,
+
+    /// Assert the hold while the source input is low instead of high.
+    pub invert: bool,
+
+    /// Force the hold regardless of the source input.
+    pub force: bool,
+
+    /// The output behaviour during hold. See [HoldBehaviour].
+    pub behaviour: HoldBehaviour,
+
+    /// The ramp target of the IIR channel output in volts, referred to the DAC output.
+    pub preset: f32,
+
+    /// The duration of the ramp from the last output to the preset value in seconds.
+    pub ramp_time: f32,
+}
+
+impl Default for HoldConfig {
+    fn default() -> Self {
+        Self {
+            source: HoldSource::Software,
+            invert: false,
+            force: false,
+            behaviour: HoldBehaviour::Last,
+            preset: 0.0,
+            ramp_time: 0.0,
+        }
+    }
+}
+
+impl HoldConfig {
+    /// Whether the hold is asserted.
+    ///
+    /// # Args
+    /// * `inputs` - The states of DI0, DI1, LVDS4 and LVDS5.
+    pub fn asserted(&self, inputs: [bool; 4]) -> bool {
+        let input = match self.source {
+            HoldSource::Software => return self.force,
+            HoldSource::Di0 => inputs[0],
+            HoldSource::Di1 => inputs[1],
+            HoldSource::Lvds4 => inputs[2],
+            HoldSource::Lvds5 => inputs[3],
+        };
+        self.force || (input != self.invert)
+    }
+}
+
+/// Setpoint configuration of an IIR channel.
+///
+/// The setpoint is subtracted from the IIR channel input. The difference is the error signal
+/// that is processed by the lock acquisition and the IIR cascade.
+///
+/// # Miniconf
+/// `{"value": 0.0, "ramp_rate": null}`
+#[derive(Copy, Clone, Debug, Default, Tree, Serialize, Deserialize)]
+pub struct SetpointConfig {
+    /// The setpoint in volts, referred to the ADC input of the same channel.
+    pub value: f32,
+
+    /// The maximum rate of change of the setpoint in volts per second. `null` applies setpoint
+    /// changes immediately.
+    pub ramp_rate: Option<f32>,
+}
+
+/// Run-time setpoint state of an IIR channel.
+#[derive(Copy, Clone)]
+pub struct Setpoint<T> {
+    // The current setpoint in ADC codes.
+    value: T,
+    // The time between samples in seconds.
+    sample_period: f32,
+}
+
+impl<T: Sample> Setpoint<T> {
+    /// Construct a zero setpoint.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    pub fn new(sample_period: f32) -> Self {
+        Self {
+            value: T::ZERO,
+            sample_period,
+        }
+    }
+
+    /// Compute the setpoint target and the maximum step per sample.
+    ///
+    /// # Args
+    /// * `config` - The setpoint configuration.
+    /// * `gain` - The AFE gain of the channel input.
+    ///
+    /// # Returns
+    /// The target and the step in ADC codes. The step is `None` if changes apply immediately.
+    pub fn target(
+        &self,
+        config: &SetpointConfig,
+        gain: Gain,
+    ) -> (T, Option<T>) {
+        let lsb_per_volt = gain.as_multiplier() * AdcCode::LSB_PER_VOLT;
+        (
+            T::from_codes(config.value * lsb_per_volt),
+            config.ramp_rate.map(|rate| {
+                T::from_codes((rate * lsb_per_volt * self.sample_period).abs())
+            }),
+        )
+    }
+
+    /// Advance the setpoint towards its target by one sample.
+    ///
+    /// # Args
+    /// * `target` - The setpoint target. See [Setpoint::target].
+    /// * `step` - The maximum step.
+    ///
+    /// # Returns
+    /// The current setpoint in ADC codes.
+    #[inline]
+    pub fn update(&mut self, target: T, step: Option<T>) -> T {
+        self.value = match step {
+            Some(step) => {
+                let delta = target.saturating_sub(self.value);
+                let delta = if delta > step {
+                    step
+                } else if delta < T::ZERO.saturating_sub(step) {
+                    T::ZERO.saturating_sub(step)
+                } else {
+                    delta
+                };
+                self.value.saturating_add(delta)
+            }
+            None => target,
+        };
+        self.value
+    }
+}
+
+/// Run-time hold state of an IIR channel.
+#[derive(Copy, Clone)]
+pub struct Hold<T> {
+    // The current output, the increment per sample and the target while ramping.
+    ramp: Option<(T, T, T)>,
+    // The most recent IIR cascade output.
+    last: T,
+    // The time between samples in seconds.
+    sample_period: f32,
+}
+
+impl<T: Sample> Hold<T> {
+    /// Construct a released hold.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    pub fn new(sample_period: f32) -> Self {
+        Self {
+            ramp: None,
+            last: T::ZERO,
+            sample_period,
+        }
+    }
+
+    /// Process a sample through the IIR cascade of a channel.
+    ///
+    /// # Args
+    /// * `config` - The hold configuration.
+    /// * `hold` - Whether the hold is asserted.
+    /// * `cascade` - The IIR cascade and its state.
+    /// * `events` - The event counters of the channel.
+    /// * `x` - The IIR channel input.
+    ///
+    /// # Returns
+    /// The IIR cascade output.
+    #[inline]
+    pub fn update(
+        &mut self,
+        config: &HoldConfig,
+        hold: bool,
+        cascade: &mut impl Cascade<Sample = T>,
+        events: &mut Events,
+        x: T,
+    ) -> T {
+        let last = self.last;
+        self.last = if !hold {
+            self.ramp = None;
+            cascade.update(x, events)
+        } else {
+            match config.behaviour {
+                // Hold the output of each section. The input history is updated to avoid a
+                // derivative kick when the hold is released.
+                HoldBehaviour::Last => cascade.hold(x).unwrap_or(last),
+                HoldBehaviour::Zero => {
+                    cascade.clear();
+                    T::ZERO
+                }
+                HoldBehaviour::Ramp => {
+                    let sample_period = self.sample_period;
+                    let (value, step, target) =
+                        self.ramp.get_or_insert_with(|| {
+                            let target = T::from_codes(
+                                config.preset * DacCode::LSB_PER_VOLT,
+                            );
+                            let n = (config.ramp_time / sample_period).max(1.);
+                            (
+                                last,
+                                target.saturating_sub(last).scale(1. / n),
+                                target,
+                            )
+                        });
+                    *value = if target.saturating_sub(*value).saturating_abs()
+                        > step.saturating_abs()
+                    {
+                        value.saturating_add(*step)
+                    } else {
+                        *target
+                    };
+
+                    // Continue from the ramp output when the hold is released.
+                    cascade.set_output(*value);
+                    *value
+                }
+            }
+        };
+        self.last
+    }
+}
+
+/// A signal sample of a channel processing path.
+///
+/// Samples are either floating point codes (`f32`) or fixed-point codes (`i32`, see
+/// [fixed_iir]). Conversions from floating point are only used outside of the per-sample path.
+pub trait Sample: Copy + PartialOrd {
+    /// The zero sample.
+    const ZERO: Self;
+
+    /// Convert from floating point codes.
+    fn from_codes(x: f32) -> Self;
+
+    /// Convert from an integer code.
+    fn from_code(code: i16) -> Self;
+
+    /// Select the representation of a value that is kept both in floating point and fixed-point.
+    fn select(codes: f32, fixed: i32) -> Self;
+
+    /// Saturating addition.
+    fn saturating_add(self, other: Self) -> Self;
+
+    /// Saturating subtraction.
+    fn saturating_sub(self, other: Self) -> Self;
+
+    /// Saturating absolute value.
+    fn saturating_abs(self) -> Self;
+
+    /// Scale by a floating point factor.
+    fn scale(self, factor: f32) -> Self;
+}
+
+impl Sample for f32 {
+    const ZERO: Self = 0.;
+
+    fn from_codes(x: f32) -> Self {
+        x
+    }
+
+    #[inline]
+    fn from_code(code: i16) -> Self {
+        code as f32
+    }
+
+    #[inline]
+    fn select(codes: f32, _fixed: i32) -> Self {
+        codes
+    }
+
+    #[inline]
+    fn saturating_add(self, other: Self) -> Self {
+        self + other
+    }
+
+    #[inline]
+    fn saturating_sub(self, other: Self) -> Self {
+        self - other
+    }
+
+    #[inline]
+    fn saturating_abs(self) -> Self {
+        self.abs()
+    }
+
+    fn scale(self, factor: f32) -> Self {
+        self * factor
+    }
+}
+
+impl Sample for i32 {
+    const ZERO: Self = 0;
+
+    fn from_codes(x: f32) -> Self {
+        fixed_iir::to_fixed(x)
+    }
+
+    #[inline]
+    fn from_code(code: i16) -> Self {
+        fixed_iir::from_code(code)
+    }
+
+    #[inline]
+    fn select(_codes: f32, fixed: i32) -> Self {
+        fixed
+    }
+
+    #[inline]
+    fn saturating_add(self, other: Self) -> Self {
+        i32::saturating_add(self, other)
+    }
+
+    #[inline]
+    fn saturating_sub(self, other: Self) -> Self {
+        i32::saturating_sub(self, other)
+    }
+
+    #[inline]
+    fn saturating_abs(self) -> Self {
+        i32::saturating_abs(self)
+    }
+
+    fn scale(self, factor: f32) -> Self {
+        (self as f32 * factor) as i32
+    }
+}
+
+/// A cascade of biquad sections together with its state.
+pub trait Cascade {
+    /// The signal sample type.
+    type Sample: Sample;
+
+    /// Process a sample through the active sections and count saturation and windup events.
+    fn update(&mut self, x: Self::Sample, events: &mut Events) -> Self::Sample;
+
+    /// Hold the output of each active section while updating its input history.
+    ///
+    /// # Returns
+    /// The held output or `None` if no sections are active.
+    fn hold(&mut self, x: Self::Sample) -> Option<Self::Sample>;
+
+    /// Clear the state of all sections.
+    fn clear(&mut self);
+
+    /// Set the output history of the last active section.
+    fn set_output(&mut self, y: Self::Sample);
+}
+
+/// Count the saturation and windup events of a sample.
+fn count_events(events: &mut Events, saturated: bool, windup: bool) {
+    if saturated {
+        events.saturation = events.saturation.saturating_add(1);
+    }
+    if windup {
+        events.windup = events.windup.saturating_add(1);
+    }
+}
+
+/// The output of a floating point section before it is clamped to its limits.
+#[inline]
+fn accumulate(ch: &iir::Biquad<f32>, xy: &[f32; 4], x0: f32) -> f32 {
+    let ba = ch.ba();
+    ch.u() + ba[0] * x0 + ba[1] * xy[0] + ba[2] * xy[1]
+        - ba[3] * xy[2]
+        - ba[4] * xy[3]
+}
+
+/// A floating point IIR cascade.
+pub struct FloatCascade<'a> {
+    /// The biquad sections.
+    pub iir: &'a [iir::Biquad<f32>],
+    /// The state of each section.
+    pub state: &'a mut [[f32; 4]],
+    /// The number of active sections.
+    pub active: usize,
+}
+
+impl Cascade for FloatCascade<'_> {
+    type Sample = f32;
+
+    #[inline]
+    fn update(&mut self, x: f32, events: &mut Events) -> f32 {
+        let mut saturated = false;
+        let mut windup = false;
+        let y = self
+            .iir
+            .iter()
+            .zip(self.state.iter_mut())
+            .take(self.active)
+            .fold(x, |yi, (ch, state)| {
+                let unclamped = accumulate(ch, state, yi);
+                let y = ch.update(state, yi);
+                if unclamped < ch.min() || unclamped > ch.max() {
+                    saturated = true;
+                    // A section with a pole at DC integrates. The clamped output is its
+                    // anti-windup.
+                    let ba = ch.ba();
+                    windup |= (1. + ba[3] + ba[4]).abs() < f32::EPSILON;
+                }
+                y
+            });
+        count_events(events, saturated, windup);
+        y
+    }
+
+    #[inline]
+    fn hold(&mut self, x: f32) -> Option<f32> {
+        (self.active > 0).then(|| {
+            self.state
+                .iter_mut()
+                .take(self.active)
+                .fold(x, |yi, state| iir::Biquad::<f32>::HOLD.update(state, yi))
+        })
+    }
+
+    fn clear(&mut self) {
+        self.state.fill([0.; 4]);
+    }
+
+    fn set_output(&mut self, y: f32) {
+        if let Some(state) = self.state.iter_mut().take(self.active).last() {
+            state[2] = y;
+            state[3] = y;
+        }
+    }
+}
+
+/// A fixed-point IIR cascade. See [fixed_iir].
+pub struct FixedCascade<'a> {
+    /// The biquad sections.
+    pub iir: &'a [fixed_iir::Biquad],
+    /// The state of each section.
+    pub state: &'a mut [[i32; 4]],
+    /// The number of active sections.
+    pub active: usize,
+}
+
+impl Cascade for FixedCascade<'_> {
+    type Sample = i32;
+
+    #[inline]
+    fn update(&mut self, x: i32, events: &mut Events) -> i32 {
+        let mut saturated = false;
+        let mut windup = false;
+        let y = self
+            .iir
+            .iter()
+            .zip(self.state.iter_mut())
+            .take(self.active)
+            .fold(x, |yi, (ch, state)| {
+                let (y, clipped) = ch.update_clip(state, yi);
+                if clipped {
+                    saturated = true;
+                    windup |= ch.is_integrator();
+                }
+                y
+            });
+        count_events(events, saturated, windup);
+        y
+    }
+
+    #[inline]
+    fn hold(&mut self, x: i32) -> Option<i32> {
+        (self.active > 0).then(|| {
+            self.state
+                .iter_mut()
+                .take(self.active)
+                .fold(x, |yi, state| fixed_iir::Biquad::HOLD.update(state, yi))
+        })
+    }
+
+    fn clear(&mut self) {
+        self.state.fill([0; 4]);
+    }
+
+    fn set_output(&mut self, y: i32) {
+        if let Some(state) = self.state.iter_mut().take(self.active).last() {
+            state[2] = y;
+            state[3] = y;
+        }
+    }
+}
+
+/// The threshold crossing direction detected during lock acquisition.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Edge {
+    /// The input crosses the threshold from below.
+    Rising,
+    /// The input crosses the threshold from above.
+    Falling,
+}
+
+/// Automatic lock acquisition configuration of a channel.
+///
+/// # Miniconf
+/// `{"enable": true, "sweep_frequency": 10.0, "sweep_amplitude": 1.0, "threshold": 0.1,
+/// "edge": "Rising", "detect_time": 0.001, "error_window": 0.5, "output_window": 8.0}`
+///
+/// Input levels (`threshold`, `error_window`) are in volts of the error signal (the IIR channel
+/// input less the setpoint, see [SetpointConfig]), referred to the ADC input of the same channel.
+/// Output levels (`sweep_amplitude`, `output_window`) are in
+/// volts at the IIR channel output, referred to the DAC output.
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct LockConfig {
+    /// Enable the lock acquisition state machine. When disabled, the channel is always locked.
+    pub enable: bool,
+
+    /// The frequency of the triangle sweep in Hertz.
+    pub sweep_frequency: f32,
+
+    /// The amplitude of the triangle sweep in volts.
+    pub sweep_amplitude: f32,
+
+    /// The detection threshold of the IIR channel input in volts.
+    pub threshold: f32,
+
+    /// The threshold crossing direction that is detected. See [Edge].
+    pub edge: Edge,
+
+    /// The time in seconds the input has to remain beyond the threshold to engage the lock.
+    pub detect_time: f32,
+
+    /// The lock is lost when the magnitude of the IIR channel input exceeds this value in volts.
+    pub error_window: f32,
+
+    /// The lock is lost when the magnitude of the IIR channel output exceeds this value in volts.
+    pub output_window: f32,
+}
+
+impl Default for LockConfig {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            sweep_frequency: 10.0,
+            sweep_amplitude: 1.0,
+            threshold: 0.0,
+            edge: Edge::Rising,
+            detect_time: 1.0e-3,
+            error_window: AdcCode::FULL_SCALE,
+            output_window: DacCode::FULL_SCALE,
+        }
+    }
+}
+
+/// Lock acquisition state of a channel.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+pub enum LockState {
+    /// The output is swept with a triangle waveform while the input is watched for a threshold
+    /// crossing.
+    Sweep,
+    /// The sweep is paused and the input is verified to remain beyond the threshold.
+    Detect,
+    /// The IIR cascade is engaged at the output offset where the crossing was detected.
+    Locked,
+}
+
+/// Run-time lock acquisition state machine of a channel.
+///
+/// All levels are in IIR channel units (ADC and DAC codes).
+pub struct Lock {
+    state: LockState,
+    losses: u32,
+    sweep: SignalGenerator,
+    // The IIR channel output offset at which the lock is engaged.
+    offset: i16,
+    // Whether the input has been observed before the threshold during the sweep.
+    armed: bool,
+    // The number of samples spent in the detect state.
+    count: u32,
+    enable: bool,
+    edge: Edge,
+    threshold: f32,
+    detect_samples: u32,
+    error_window: f32,
+    output_window: f32,
+    // The levels in fixed-point codes.
+    threshold_fixed: i32,
+    error_window_fixed: i32,
+    output_window_fixed: i32,
+}
+
+impl Default for Lock {
+    fn default() -> Self {
+        Self {
+            state: LockState::Locked,
+            losses: 0,
+            sweep: SignalGenerator::new(signal_generator::Config::default()),
+            offset: 0,
+            armed: false,
+            count: 0,
+            enable: false,
+            edge: Edge::Rising,
+            threshold: 0.,
+            detect_samples: 0,
+            error_window: f32::INFINITY,
+            output_window: f32::INFINITY,
+            threshold_fixed: 0,
+            error_window_fixed: i32::MAX,
+            output_window_fixed: i32::MAX,
+        }
+    }
+}
+
+impl Lock {
+    /// Apply a new configuration.
+    ///
+    /// # Args
+    /// * `config` - The lock acquisition configuration.
+    /// * `gain` - The AFE gain of the channel input.
+    /// * `sample_period` - The time in seconds between samples.
+    pub fn configure(
+        &mut self,
+        config: &LockConfig,
+        gain: Gain,
+        sample_period: f32,
+    ) -> Result<(), signal_generator::Error> {
+        let sweep = signal_generator::BasicConfig {
+            signal: Signal::Triangle,
+            frequency: config.sweep_frequency,
+            amplitude: config.sweep_amplitude,
+            ..Default::default()
+        }
+        .try_into_config(sample_period, DacCode::FULL_SCALE)?;
+        self.sweep.update_waveform(sweep);
+
+        if !config.enable {
+            self.state = LockState::Locked;
+            self.offset = 0;
+        } else if !self.enable {
+            self.start_sweep();
+        }
+
+        let lsb_per_volt = gain.as_multiplier() * AdcCode::LSB_PER_VOLT;
+        self.enable = config.enable;
+        self.edge = config.edge;
+        self.threshold = config.threshold * lsb_per_volt;
+        self.detect_samples = (config.detect_time / sample_period) as u32;
+        self.error_window = config.error_window * lsb_per_volt;
+        self.output_window = config.output_window * DacCode::LSB_PER_VOLT;
+        self.threshold_fixed = fixed_iir::to_fixed(self.threshold);
+        self.error_window_fixed = fixed_iir::to_fixed(self.error_window);
+        self.output_window_fixed = fixed_iir::to_fixed(self.output_window);
+        Ok(())
+    }
+
+    /// The current state.
+    pub fn state(&self) -> LockState {
+        self.state
+    }
+
+    /// The number of lock losses.
+    pub fn losses(&self) -> u32 {
+        self.losses
+    }
 
-    /// Specified true if "hold" should be forced regardless of DI1 state and hold allowance.
-    ///
-    /// # Path
-    /// `force_hold`
-    ///
-    /// # Value
-    /// "true" or "false"
-    force_hold: bool,
+    fn start_sweep(&mut self) {
+        self.state = LockState::Sweep;
+        self.armed = false;
+    }
 
-    /// Specifies the telemetry output period in seconds.
-    ///
-    /// # Path
-    /// `telemetry_period`
-    ///
-    /// # Value
-    /// Any non-zero value less than 65536.
-    telemetry_period: u16,
+    fn beyond<T: Sample>(&self, x: T) -> bool {
+        let threshold = T::select(self.threshold, self.threshold_fixed);
+        match self.edge {
+            Edge::Rising => x > threshold,
+            Edge::Falling => x < threshold,
+        }
+    }
 
-    /// Specifies the target for data livestreaming.
+    /// Advance the state machine by one sample.
     ///
-    /// # Path
-    /// `stream_target`
+    /// # Args
+    /// * `x` - The IIR channel input.
+    /// * `filter` - Computes the IIR cascade output. Only called while locked.
     ///
-    /// # Value
-    /// See [StreamTarget#miniconf]
-    stream_target: StreamTarget,
+    /// # Returns
+    /// The IIR channel output.
+    #[inline]
+    pub fn update<T: Sample>(
+        &mut self,
+        x: T,
+        filter: impl FnOnce(T) -> T,
+    ) -> T {
+        match self.state {
+            LockState::Sweep => {
+                self.offset = self.sweep.next().unwrap();
+                if !self.beyond(x) {
+                    self.armed = true;
+                } else if self.armed {
+                    self.state = LockState::Detect;
+                    self.count = 0;
+                }
+                T::from_code(self.offset)
+            }
+            LockState::Detect => {
+                if !self.beyond(x) {
+                    self.start_sweep();
+                } else if self.count >= self.detect_samples {
+                    self.state = LockState::Locked;
+                } else {
+                    self.count += 1;
+                }
+                T::from_code(self.offset)
+            }
+            LockState::Locked => {
+                let y = T::from_code(self.offset).saturating_add(filter(x));
+                let error_window =
+                    T::select(self.error_window, self.error_window_fixed);
+                let output_window =
+                    T::select(self.output_window, self.output_window_fixed);
+                if self.enable
+                    && (x.saturating_abs() > error_window
+                        || y.saturating_abs() > output_window)
+                {
+                    self.losses = self.losses.wrapping_add(1);
+                    self.start_sweep();
+                }
+                y
+            }
+        }
+    }
+}
 
-    /// Specifies the config for signal generators to add on to DAC0/DAC1 outputs.
-    ///
-    /// # Path
-    /// `signal_generator/<n>`
-    ///
-    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
-    ///
-    /// # Value
-    /// See [signal_generator::BasicConfig#miniconf]
-    #[tree(depth = 2)]
-    signal_generator: [signal_generator::BasicConfig; 2],
+/// Telemetry reported by the dual-iir application.
+///
+/// This extends the common [stabilizer::net::telemetry::Telemetry] by application-specific
+/// fields.
+#[derive(Serialize)]
+pub struct Telemetry {
+    /// Input voltage statistics over the telemetry period.
+    adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    dacs: [Statistics; 2],
+
+    /// Event counters of each channel over the telemetry period.
+    events: [Events; 2],
+
+    /// Most recent digital input assertion state.
+    digital_inputs: [bool; 2],
+
+    /// The CPU temperature in degrees Celsius.
+    cpu_temp: f32,
+
+    /// The lock acquisition state of each channel.
+    lock_state: [LockState; 2],
+
+    /// The number of lock losses of each channel since startup.
+    lock_losses: [u32; 2],
+
+    /// The result of the most recent frontend offset auto-zero.
+    auto_zero: Option<AutoZeroResult>,
 }
 
-impl Default for DualIir {
-    fn default() -> Self {
-        let mut i = iir::Biquad::IDENTITY;
-        i.set_min(-SCALE);
-        i.set_max(SCALE);
+impl Telemetry {
+    fn new(
+        telemetry: stabilizer::net::telemetry::Telemetry,
+        lock_state: [LockState; 2],
+        lock_losses: [u32; 2],
+        auto_zero: Option<AutoZeroResult>,
+    ) -> Self {
         Self {
-            // Analog frontend programmable gain amplifier gains (G1, G2, G5, G10)
-            afe: [Gain::G1, Gain::G1],
-            // CPU DAC1 output
-            cpu_dac1: 0,
-            // Frontend offset DAC
-            frontend_offset: 0,
-            // IIR filter tap gains are an array `[b0, b1, b2, a1, a2]` such that the
-            // new output is computed as `y0 = a1*y1 + a2*y2 + b0*x0 + b1*x1 + b2*x2`.
-            // The array is `iir_state[channel-index][cascade-index][coeff-index]`.
-            // The IIR coefficients can be mapped to other transfer function
-            // representations, for example as described in https://arxiv.org/abs/1508.06319
-            iir_ch: [[i; IIR_CASCADE_LENGTH]; 2],
+            adcs: telemetry.adcs,
+            dacs: telemetry.dacs,
+            events: telemetry.events,
+            digital_inputs: telemetry.digital_inputs,
+            cpu_temp: telemetry.cpu_temp,
+            lock_state,
+            lock_losses,
+            auto_zero,
+        }
+    }
+}
 
-            // Permit the DI1 digital input to suppress filter output updates.
-            allow_hold: false,
-            // Force suppress filter output updates.
-            force_hold: false,
-            // The default telemetry period in seconds.
-            telemetry_period: 10,
+/// Process a sample through the lock acquisition, the hold and the IIR cascade of a channel.
+///
+/// # Args
+/// * `lock` - The lock acquisition.
+/// * `hold_state` - The run-time hold state.
+/// * `config` - The hold configuration.
+/// * `hold` - Whether the hold is asserted.
+/// * `cascade` - The IIR cascade and its state.
+/// * `events` - The event counters of the channel.
+/// * `x` - The IIR channel input.
+///
+/// # Returns
+/// The IIR channel output.
+#[inline]
+fn process_channel<T: Sample>(
+    lock: &mut Lock,
+    hold_state: &mut Hold<T>,
+    config: &HoldConfig,
+    hold: bool,
+    cascade: &mut impl Cascade<Sample = T>,
+    events: &mut Events,
+    x: T,
+) -> T {
+    let y =
+        lock.update(x, |x| hold_state.update(config, hold, cascade, events, x));
+
+    // Engage the cascade from rest once the lock is acquired.
+    if lock.state() != LockState::Locked {
+        cascade.clear();
+    }
+    y
+}
 
-            signal_generator: [signal_generator::BasicConfig::default(); 2],
+/// Adjust the biquad state for a bumpless switch from the `old` to the `new` filter.
+///
+/// The next outputs of both filters are predicted assuming that the input remains at its latest
+/// value. The output history is then shifted such that the `new` filter reproduces the output of
+/// the `old` filter. The shifted history decays with the dynamics of the `new` filter.
+fn bumpless_transfer(
+    old: &iir::Biquad<f32>,
+    new: &iir::Biquad<f32>,
+    state: &mut [f32; 4],
+) {
+    let y_old = old.update(&mut { *state }, state[0]);
+    let y_new = new.update(&mut { *state }, state[0]);
+    // Shifting `y1` and `y2` by `d` shifts the next output by `-(a1 + a2) * d`.
+    let a = new.ba()[3] + new.ba()[4];
+    if a.abs() > f32::EPSILON {
+        let d = (y_new - y_old) / a;
+        state[2] += d;
+        state[3] += d;
+    }
+}
 
-            stream_target: StreamTarget::default(),
+/// Apply a 2x2 mixing matrix: `y[i] = m[i][0] * x[0] + m[i][1] * x[1]`.
+#[inline]
+fn mix(m: &[[f32; 2]; 2], x: [f32; 2]) -> [f32; 2] {
+    [
+        m[0][0] * x[0] + m[0][1] * x[1],
+        m[1][0] * x[0] + m[1][1] * x[1],
+    ]
+}
+
+/// Convert a mixing matrix to fixed-point with [MATRIX_SHIFT] fractional bits. The weights
+/// saturate.
+fn fixed_matrix(m: &[[f32; 2]; 2]) -> [[i32; 2]; 2] {
+    m.map(|row| row.map(|w| (w * (1 << MATRIX_SHIFT) as f32) as i32))
+}
+
+/// Apply a fixed-point 2x2 mixing matrix. See [mix] and [fixed_matrix]. The result saturates.
+#[inline]
+fn mix_fixed(m: &[[i32; 2]; 2], x: [i32; 2]) -> [i32; 2] {
+    m.map(|row| {
+        let y = (row[0] as i64 * x[0] as i64 + row[1] as i64 * x[1] as i64)
+            >> MATRIX_SHIFT;
+        y.clamp(i32::MIN as _, i32::MAX as _) as i32
+    })
+}
+
+/// The fixed-point conversion of the IIR sections and the mixing matrices of [DualIir].
+///
+/// # Note
+/// The conversion is derived from the settings on each settings update while `fixed_point` is
+/// enabled. It is not part of the settings.
+#[derive(Copy, Clone, Debug)]
+pub struct FixedIir {
+    /// The fixed-point IIR sections `[channel][section]`.
+    pub iir_ch: [[fixed_iir::Biquad; IIR_CASCADE_LENGTH]; 2],
+    /// The fixed-point input mixing matrix.
+    pub input_matrix: [[i32; 2]; 2],
+    /// The fixed-point output mixing matrix.
+    pub output_matrix: [[i32; 2]; 2],
+}
+
+impl Default for FixedIir {
+    fn default() -> Self {
+        Self {
+            iir_ch: [[fixed_iir::Biquad::default(); IIR_CASCADE_LENGTH]; 2],
+            input_matrix: fixed_matrix(&[[1., 0.], [0., 1.]]),
+            output_matrix: fixed_matrix(&[[1., 0.], [0., 1.]]),
+        }
+    }
+}
+
+impl FixedIir {
+    /// Convert the IIR sections and the mixing matrices to fixed-point.
+    ///
+    /// # Args
+    /// * `settings` - The settings providing the mixing matrices and the active sections.
+    /// * `iir_ch` - The floating point IIR sections to convert.
+    ///
+    /// # Returns
+    /// The conversion or `None` if an active section cannot be converted. Inactive sections are
+    /// not converted.
+    pub fn new(
+        settings: &DualIir,
+        iir_ch: &[[iir::Biquad<f32>; IIR_CASCADE_LENGTH]; 2],
+    ) -> Option<Self> {
+        let mut converted = true;
+        let mut fixed = Self {
+            input_matrix: fixed_matrix(&settings.input_matrix),
+            output_matrix: fixed_matrix(&settings.output_matrix),
+            ..Default::default()
+        };
+        for (channel, (iirs, fixed)) in
+            iir_ch.iter().zip(fixed.iir_ch.iter_mut()).enumerate()
+        {
+            let active = settings.active_sections[channel];
+            for (section, (iir, fixed)) in
+                iirs.iter().zip(fixed.iter_mut()).enumerate().take(active)
+            {
+                match fixed_iir::Biquad::try_from(iir) {
+                    Ok(biquad) => *fixed = biquad,
+                    Err(err) => {
+                        log::error!(
+                            "Failed to convert IIR section {}/{} to fixed-point: {:?}",
+                            channel,
+                            section,
+                            err
+                        );
+                        converted = false;
+                    }
+                }
+            }
         }
+        converted.then_some(fixed)
     }
 }
 
@@ -248,6 +1797,16 @@ mod app {
         active_settings: DualIir,
         telemetry: TelemetryBuffer,
         signal_generator: [SignalGenerator; 2],
+        iir_state: [[[f32; 4]; IIR_CASCADE_LENGTH]; 2],
+        locks: [Lock; 2],
+        gpio_dac_spi: Option<GpioDacSpi>,
+        adc_mean: AdcMean,
+        auto_zero_result: Option<AutoZeroResult>,
+        auto_zero_active: bool,
+        analyzer_link: Link,
+        capture_link: CaptureLink<[Probe; 2]>,
+        sampling: SamplingSettings,
+        iir_fixed: FixedIir,
     }
 
     #[local]
@@ -255,14 +1814,22 @@ mod app {
         usb_terminal: SerialTerminal<Settings, 4>,
         sampling_timer: SamplingTimer,
         digital_inputs: (DigitalInput0, DigitalInput1),
+        eem_inputs: (EemDigitalInput0, EemDigitalInput1),
+        holds: [Hold<f32>; 2],
+        fixed_holds: [Hold<i32>; 2],
+        setpoints: [Setpoint<f32>; 2],
+        fixed_setpoints: [Setpoint<i32>; 2],
+        iir_fixed_state: [[[i32; 4]; IIR_CASCADE_LENGTH]; 2],
+        analyzer: NetworkAnalyzer,
+        recorder: Capture<[Probe; 2]>,
+        capture_info: Option<CaptureInfo>,
         afes: (AFE0, AFE1),
         adcs: (Adc0Input, Adc1Input),
         dacs: (Dac0Output, Dac1Output),
-        iir_state: [[[f32; 4]; IIR_CASCADE_LENGTH]; 2],
         generator: FrameGenerator,
+        offload: Offload,
         cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
         cpu_dac1: CpuDacOutput1,
-        gpio_dac_spi: GpioDacSpi,
     }
 
     #[init]
@@ -270,13 +1837,9 @@ mod app {
         let clock = SystemTimer::new(|| Systick::now().ticks());
 
         // Configure the microcontroller
-        let stabilizer = hardware::setup::setup::<Settings, 4>(
-            c.core,
-            c.device,
-            clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
-        );
+        let mut stabilizer =
+            hardware::setup::setup::<Settings, 4>(c.core, c.device, clock);
+        let sample_period = stabilizer.sampling.sample_period();
 
         let mut network = NetworkUsers::new(
             stabilizer.net.stack,
@@ -289,23 +1852,42 @@ mod app {
 
         let generator = network.configure_streaming(StreamFormat::AdcDacData);
 
+        stabilizer.usb_serial.platform_mut().app_commands = &["auto-zero"];
+
         let shared = Shared {
             usb: stabilizer.usb,
             network,
-            active_settings: stabilizer.settings.dual_iir.clone(),
+            // The fixed-point conversion is only available after the first settings update.
+            active_settings: DualIir {
+                fixed_point: false,
+                ..stabilizer.settings.dual_iir.clone()
+            },
             telemetry: TelemetryBuffer::default(),
-            signal_generator: [
+            // The stored settings may not be valid for the applied sampling configuration.
+            signal_generator: core::array::from_fn(|i| {
                 SignalGenerator::new(
-                    stabilizer.settings.dual_iir.signal_generator[0]
-                        .try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE)
-                        .unwrap(),
-                ),
-                SignalGenerator::new(
-                    stabilizer.settings.dual_iir.signal_generator[1]
-                        .try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE)
-                        .unwrap(),
-                ),
-            ],
+                    stabilizer.settings.dual_iir.signal_generator[i]
+                        .try_into_config(sample_period, DacCode::FULL_SCALE)
+                        .unwrap_or_else(|err| {
+                            log::error!(
+                                "Invalid signal generation on DAC{}: {:?}. Disabling.",
+                                i,
+                                err
+                            );
+                            Default::default()
+                        }),
+                )
+            }),
+            iir_state: [[[0.; 4]; IIR_CASCADE_LENGTH]; 2],
+            locks: [Lock::default(), Lock::default()],
+            gpio_dac_spi: stabilizer.gpio_dac_spi,
+            adc_mean: AdcMean::default(),
+            auto_zero_result: None,
+            auto_zero_active: false,
+            analyzer_link: Link::default(),
+            capture_link: CaptureLink::default(),
+            sampling: stabilizer.sampling,
+            iir_fixed: FixedIir::default(),
             settings: stabilizer.settings,
         };
 
@@ -313,14 +1895,22 @@ mod app {
             usb_terminal: stabilizer.usb_serial,
             sampling_timer: stabilizer.adc_dac_timer,
             digital_inputs: stabilizer.digital_inputs,
+            eem_inputs: (stabilizer.eem_gpio.lvds4, stabilizer.eem_gpio.lvds5),
+            holds: [Hold::new(sample_period); 2],
+            fixed_holds: [Hold::new(sample_period); 2],
+            setpoints: [Setpoint::new(sample_period); 2],
+            fixed_setpoints: [Setpoint::new(sample_period); 2],
+            iir_fixed_state: [[[0; 4]; IIR_CASCADE_LENGTH]; 2],
+            analyzer: NetworkAnalyzer::default(),
+            recorder: Capture::take().unwrap(),
+            capture_info: None,
             afes: stabilizer.afes,
             adcs: stabilizer.adcs,
             dacs: stabilizer.dacs,
-            iir_state: [[[0.; 4]; IIR_CASCADE_LENGTH]; 2],
             generator,
+            offload: Offload::new(sample_period),
             cpu_temp_sensor: stabilizer.temperature_sensor,
             cpu_dac1: stabilizer.cpu_dac1,
-            gpio_dac_spi: stabilizer.gpio_dac_spi,
         };
 
         // Enable ADC/DAC events
@@ -329,11 +1919,6 @@ mod app {
         local.dacs.0.start();
         local.dacs.1.start();
 
-        // // Test the SPI DAC
-        // if let Err(err) = local.gpio_dac_spi.write(&[0x0000]) {
-        //     log::error!("Failed to write to the DAC: {:?}", err);
-        // }
-
         // Spawn a settings update for default settings.
         settings_update::spawn().unwrap();
         telemetry::spawn().unwrap();
@@ -367,107 +1952,319 @@ mod app {
     ///
     /// Because the ADC and DAC operate at the same rate, these two constraints actually implement
     /// the same time bounds, meeting one also means the other is also met.
-    #[task(binds=DMA1_STR4, local=[digital_inputs, adcs, dacs, iir_state, generator], shared=[active_settings, signal_generator, telemetry], priority=3)]
+    #[task(binds=DMA1_STR4, local=[digital_inputs, eem_inputs, holds, fixed_holds, setpoints, fixed_setpoints, iir_fixed_state, analyzer, recorder, adcs, dacs, generator, offload, cpu_dac1], shared=[active_settings, signal_generator, telemetry, iir_state, locks, iir_fixed, adc_mean, analyzer_link, capture_link], priority=3)]
     #[link_section = ".itcm.process"]
     fn process(c: process::Context) {
         let process::SharedResources {
-            active_settings,
+            active_settings: settings,
             telemetry,
             signal_generator,
+            iir_state,
+            locks,
+            mut iir_fixed,
+            mut adc_mean,
+            mut analyzer_link,
+            mut capture_link,
             ..
         } = c.shared;
 
         let process::LocalResources {
             digital_inputs,
+            eem_inputs,
+            holds,
+            fixed_holds,
+            setpoints,
+            fixed_setpoints,
+            iir_fixed_state,
+            analyzer,
+            recorder,
             adcs: (adc0, adc1),
             dacs: (dac0, dac1),
-            iir_state,
             generator,
+            offload,
+            cpu_dac1,
             ..
         } = c.local;
 
-        (active_settings, telemetry, signal_generator).lock(
-            |settings, telemetry, signal_generator| {
+        let mut adc_sum = [0; 2];
+        let mut batch_size = 0;
+
+        (settings, telemetry, signal_generator, iir_state, locks).lock(
+            |settings, telemetry, signal_generator, iir_state, locks| {
                 let digital_inputs =
                     [digital_inputs.0.is_high(), digital_inputs.1.is_high()];
                 telemetry.digital_inputs = digital_inputs;
 
-                let hold = settings.force_hold
-                    || (digital_inputs[1] && settings.allow_hold);
-
-                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                    let adc_samples = [adc0, adc1];
-                    let dac_samples = [dac0, dac1];
-
-                    // Preserve instruction and data ordering w.r.t. DMA flag access.
-                    fence(Ordering::SeqCst);
-
-                    for channel in 0..adc_samples.len() {
-                        adc_samples[channel]
-                            .iter()
-                            .zip(dac_samples[channel].iter_mut())
-                            .zip(&mut signal_generator[channel])
-                            .map(|((ai, di), signal)| {
-                                let x = f32::from(*ai as i16);
-                                let y = settings.iir_ch[channel]
-                                    .iter()
-                                    .zip(iir_state[channel].iter_mut())
-                                    .fold(x, |yi, (ch, state)| {
-                                        let filter = if hold {
-                                            &iir::Biquad::HOLD
-                                        } else {
-                                            ch
-                                        };
-
-                                        filter.update(state, yi)
-                                    });
-
-                                // Note(unsafe): The filter limits must ensure that the value is in range.
-                                // The truncation introduces 1/2 LSB distortion.
-                                let y: i16 = unsafe { y.to_int_unchecked() };
-
-                                let y = y.saturating_add(signal);
+                let inputs = [
+                    digital_inputs[0],
+                    digital_inputs[1],
+                    eem_inputs.0.is_high(),
+                    eem_inputs.1.is_high(),
+                ];
+                let hold = [
+                    settings.hold[0].asserted(inputs),
+                    settings.hold[1].asserted(inputs),
+                ];
+
+                iir_fixed.lock(|iir_fixed| {
+                    (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                        let adc_samples = [adc0, adc1];
+                        let dac_samples = [dac0, dac1];
+                        batch_size = adc_samples[0].len();
+
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
+
+                        // Keep the state of inactive sections and of the unused arithmetic cleared
+                        // so that they start from rest when they are activated.
+                        let (float_active, fixed_active) =
+                            if settings.fixed_point {
+                                ([0; 2], settings.active_sections)
+                            } else {
+                                (settings.active_sections, [0; 2])
+                            };
+                        for (state, active) in
+                            iir_state.iter_mut().zip(float_active)
+                        {
+                            for state in state.iter_mut().skip(active) {
+                                *state = [0.; 4];
+                            }
+                        }
+                        for (state, active) in
+                            iir_fixed_state.iter_mut().zip(fixed_active)
+                        {
+                            for state in state.iter_mut().skip(active) {
+                                *state = [0; 4];
+                            }
+                        }
+
+                        // The setpoint targets are updated once per batch.
+                        let setpoint: [_; 2] =
+                            core::array::from_fn(|channel| {
+                                setpoints[channel].target(
+                                    &settings.setpoint[channel],
+                                    settings.afe[channel],
+                                )
+                            });
+                        let fixed_setpoint: [_; 2] =
+                            core::array::from_fn(|channel| {
+                                fixed_setpoints[channel].target(
+                                    &settings.setpoint[channel],
+                                    settings.afe[channel],
+                                )
+                            });
+
+                        for sample in 0..adc_samples[0].len() {
+                            let adc = [
+                                adc_samples[0][sample] as i16,
+                                adc_samples[1][sample] as i16,
+                            ];
+
+                            // Mix the ADC inputs into the IIR channel inputs, process the channels
+                            // and mix their outputs into the DAC outputs. The fixed-point path keeps
+                            // the samples in integers from the ADC to the DAC codes.
+                            let (y, clipped) = if settings.fixed_point {
+                                let x = mix_fixed(
+                                    &iir_fixed.input_matrix,
+                                    adc.map(fixed_iir::from_code),
+                                );
+
+                                let mut y = [0; 2];
+                                for channel in 0..y.len() {
+                                    // Subtract the setpoint to obtain the error signal.
+                                    let (target, step) =
+                                        fixed_setpoint[channel];
+                                    let x = x[channel].saturating_sub(
+                                        fixed_setpoints[channel]
+                                            .update(target, step),
+                                    );
+                                    y[channel] = process_channel(
+                                        &mut locks[channel],
+                                        &mut fixed_holds[channel],
+                                        &settings.hold[channel],
+                                        hold[channel],
+                                        &mut FixedCascade {
+                                            iir: &iir_fixed.iir_ch[channel],
+                                            state: &mut iir_fixed_state
+                                                [channel],
+                                            active: settings.active_sections
+                                                [channel],
+                                        },
+                                        &mut telemetry.events[channel],
+                                        x,
+                                    );
+                                }
+
+                                let y = mix_fixed(&iir_fixed.output_matrix, y)
+                                    .map(fixed_iir::to_code);
+                                (
+                                    y.map(|y| {
+                                        y.clamp(i16::MIN as _, i16::MAX as _)
+                                            as i16
+                                    }),
+                                    y.map(|y| {
+                                        !(i16::MIN as i32..=i16::MAX as i32)
+                                            .contains(&y)
+                                    }),
+                                )
+                            } else {
+                                let x = mix(
+                                    &settings.input_matrix,
+                                    adc.map(f32::from),
+                                );
+
+                                let mut y = [0.; 2];
+                                for channel in 0..y.len() {
+                                    // Subtract the setpoint to obtain the error signal.
+                                    let (target, step) = setpoint[channel];
+                                    let x = x[channel]
+                                        - setpoints[channel]
+                                            .update(target, step);
+                                    y[channel] = process_channel(
+                                        &mut locks[channel],
+                                        &mut holds[channel],
+                                        &settings.hold[channel],
+                                        hold[channel],
+                                        &mut FloatCascade {
+                                            iir: &settings.iir_ch[channel],
+                                            state: &mut iir_state[channel],
+                                            active: settings.active_sections
+                                                [channel],
+                                        },
+                                        &mut telemetry.events[channel],
+                                        x,
+                                    );
+                                }
+
+                                let y = mix(&settings.output_matrix, y);
+                                (
+                                    // Note(unsafe): The value is clamped to the i16 range.
+                                    // The truncation introduces 1/2 LSB distortion.
+                                    y.map(|y| unsafe {
+                                        y.clamp(i16::MIN as _, i16::MAX as _)
+                                            .to_int_unchecked::<i16>()
+                                    }),
+                                    y.map(|y| {
+                                        !(i16::MIN as f32..=i16::MAX as f32)
+                                            .contains(&y)
+                                    }),
+                                )
+                            };
+
+                            // Add the network analyzer excitation to the selected DAC output.
+                            let excitation = analyzer.excitation();
+                            let mut injection = [0; 2];
+                            if let Some(value) = injection
+                                .get_mut(settings.network_analyzer.channel)
+                            {
+                                *value = excitation;
+                            }
+
+                            for (channel, (y, mut clipped)) in
+                                y.into_iter().zip(clipped).enumerate()
+                            {
+                                let y = y as i32
+                                    + signal_generator[channel].next().unwrap()
+                                        as i32
+                                    + injection[channel] as i32;
+                                let code = y.clamp(i16::MIN as _, i16::MAX as _)
+                                    as i16;
+                                clipped |= code as i32 != y;
+
+                                if clipped {
+                                    let events = &mut telemetry.events[channel];
+                                    events.dac_clip =
+                                        events.dac_clip.saturating_add(1);
+                                }
 
                                 // Convert to DAC code
-                                *di = DacCode::from(y).0;
-                            })
-                            .last();
-                    }
+                                dac_samples[channel][sample] =
+                                    DacCode::from(code).0;
+                            }
+
+                            if analyzer.is_active() || recorder.is_active() {
+                                let dac = [
+                                    i16::from(DacCode(dac_samples[0][sample])),
+                                    i16::from(DacCode(dac_samples[1][sample])),
+                                ];
+                                if analyzer.is_active() {
+                                    let config = &settings.network_analyzer;
+                                    analyzer.demodulate(
+                                        config
+                                            .reference
+                                            .select(excitation, adc, dac),
+                                        config
+                                            .response
+                                            .select(excitation, adc, dac),
+                                    );
+                                }
+                                if let Some(probe) = recorder.probe() {
+                                    recorder.record(
+                                        [
+                                            probe[0]
+                                                .select(excitation, adc, dac),
+                                            probe[1]
+                                                .select(excitation, adc, dac),
+                                        ],
+                                        digital_inputs,
+                                    );
+                                }
+                            }
+                        }
 
-                    // Stream the data.
-                    const N: usize = BATCH_SIZE * core::mem::size_of::<i16>();
-                    generator.add(|buf| {
-                        for (data, buf) in adc_samples
-                            .iter()
-                            .chain(dac_samples.iter())
-                            .zip(buf.chunks_exact_mut(N))
+                        // Offload the DAC output onto the internal DAC.
+                        cpu_dac1.set_value(
+                            offload.update(
+                                &settings.offload,
+                                settings.cpu_dac1,
+                                dac_samples
+                                    .get(settings.offload.channel)
+                                    .map(|samples| &samples[..]),
+                            ),
+                        );
+
+                        // Accumulate the ADC inputs for the frontend offset auto-zero.
+                        for (sum, samples) in
+                            adc_sum.iter_mut().zip(adc_samples.iter())
                         {
-                            let data = unsafe {
-                                core::slice::from_raw_parts(
-                                    data.as_ptr() as *const MaybeUninit<u8>,
-                                    N,
-                                )
-                            };
-                            buf.copy_from_slice(data)
+                            *sum =
+                                samples.iter().map(|&x| x as i16 as i32).sum();
                         }
-                        N * 4
+
+                        // Stream the data.
+                        let n = batch_size * core::mem::size_of::<i16>();
+                        generator.add(|buf| {
+                            for (data, buf) in adc_samples
+                                .iter()
+                                .chain(dac_samples.iter())
+                                .zip(buf.chunks_exact_mut(n))
+                            {
+                                let data = unsafe {
+                                    core::slice::from_raw_parts(
+                                        data.as_ptr() as *const MaybeUninit<u8>,
+                                        n,
+                                    )
+                                };
+                                buf.copy_from_slice(data)
+                            }
+                            n * 4
+                        });
+                        // Update telemetry measurements.
+                        telemetry.add(
+                            [adc_samples[0], adc_samples[1]],
+                            [dac_samples[0], dac_samples[1]],
+                        );
+
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
                     });
-                    // Update telemetry measurements.
-                    telemetry.adcs = [
-                        AdcCode(adc_samples[0][0]),
-                        AdcCode(adc_samples[1][0]),
-                    ];
-
-                    telemetry.dacs = [
-                        DacCode(dac_samples[0][0]),
-                        DacCode(dac_samples[1][0]),
-                    ];
-
-                    // Preserve instruction and data ordering w.r.t. DMA flag access.
-                    fence(Ordering::SeqCst);
                 });
             },
         );
+
+        adc_mean.lock(|mean| mean.add(adc_sum, batch_size as _));
+        analyzer_link.lock(|link| link.exchange(analyzer));
+        capture_link.lock(|link| link.exchange(recorder));
     }
 
     #[idle(shared=[network, settings, usb])]
@@ -493,17 +2290,86 @@ mod app {
         }
     }
 
-    #[task(priority = 1, local=[afes, cpu_dac1, gpio_dac_spi], shared=[network, settings, active_settings, signal_generator])]
+    #[task(priority = 1, local=[afes], shared=[network, settings, active_settings, signal_generator, iir_state, locks, iir_fixed, gpio_dac_spi, auto_zero_active, sampling, capture_link])]
     async fn settings_update(mut c: settings_update::Context) {
+        let sample_period =
+            c.shared.sampling.lock(|sampling| sampling.sample_period());
+
         c.shared.settings.lock(|settings| {
+            // Compile the filter designs into their IIR sections
+            let dual_iir = &settings.dual_iir;
+            let mut iir_ch = dual_iir.iir_ch;
+            for (channel, (designs, iirs)) in
+                dual_iir.iir_design.iter().zip(iir_ch.iter_mut()).enumerate()
+            {
+                for (section, (design, iir)) in
+                    designs.iter().zip(iirs.iter_mut()).enumerate()
+                {
+                    let Some(design) = design else {
+                        continue;
+                    };
+                    match design.biquad(sample_period, DacCode::LSB_PER_VOLT) {
+                        Ok(biquad) => *iir = biquad,
+                        Err(err) => log::error!(
+                            "Failed to design IIR section {}/{}: {:?}",
+                            channel,
+                            section,
+                            err
+                        ),
+                    }
+                }
+            }
+
+            // Convert the IIR sections to fixed-point. The fixed-point cascade must not run
+            // stale sections, so the update is rejected as a whole before any of it is applied.
+            let iir_fixed = if dual_iir.fixed_point {
+                let Some(iir_fixed) = FixedIir::new(dual_iir, &iir_ch) else {
+                    log::error!("Fixed-point settings update rejected");
+                    return;
+                };
+                Some(iir_fixed)
+            } else {
+                None
+            };
+            settings.dual_iir.iir_ch = iir_ch;
+
+            if settings.dual_iir.auto_zero.trigger {
+                settings.dual_iir.auto_zero.trigger = false;
+                if auto_zero::spawn().is_err() {
+                    log::warn!("Auto-zero already in progress");
+                }
+            }
+
+            if settings.dual_iir.network_analyzer.trigger {
+                settings.dual_iir.network_analyzer.trigger = false;
+                if network_analyzer::spawn().is_err() {
+                    log::warn!("Network analyzer sweep already in progress");
+                }
+            }
+
+            let config = &mut settings.dual_iir.capture;
+            if config.arm || config.readout {
+                let arm = config.arm;
+                config.arm = false;
+                config.readout = false;
+                if capture::spawn(arm).is_err() {
+                    log::warn!("Capture already in progress");
+                }
+            }
+            if config.force {
+                config.force = false;
+                c.shared.capture_link.lock(|link| link.force = true);
+            }
+
             c.local.afes.0.set_gain(settings.dual_iir.afe[0]);
             c.local.afes.1.set_gain(settings.dual_iir.afe[1]);
-            c.local.cpu_dac1.set_value(settings.dual_iir.cpu_dac1);
-            if let Err(err) = c
-                .local
-                .gpio_dac_spi
-                .write(&[settings.dual_iir.frontend_offset])
-            {
+            // The auto-zero owns the offset DAC while it is running.
+            if c.shared.auto_zero_active.lock(|active| *active) {
+                log::warn!("Frontend offset not applied during auto-zero");
+            } else if let Some(Err(err)) = c.shared.gpio_dac_spi.lock(|spi| {
+                spi.as_mut()
+                    .map(|spi| spi.write(&[settings.dual_iir.frontend_offset]))
+            }) {
                 log::error!("Failed to update frontend offset DAC: {:?}", err);
             }
 
@@ -511,7 +2377,7 @@ mod app {
             for (i, &config) in
                 settings.dual_iir.signal_generator.iter().enumerate()
             {
-                match config.try_into_config(SAMPLE_PERIOD, DacCode::FULL_SCALE)
+                match config.try_into_config(sample_period, DacCode::FULL_SCALE)
                 {
                     Ok(config) => {
                         c.shared.signal_generator.lock(|generator| {
@@ -526,14 +2392,425 @@ mod app {
                 }
             }
 
+            // Update the lock acquisition
+            c.shared.locks.lock(|locks| {
+                for (i, lock) in locks.iter_mut().enumerate() {
+                    if let Err(err) = lock.configure(
+                        &settings.dual_iir.lock[i],
+                        settings.dual_iir.afe[i],
+                        sample_period,
+                    ) {
+                        log::error!(
+                            "Failed to update lock acquisition on channel {}: {:?}",
+                            i,
+                            err
+                        );
+                    }
+                }
+            });
+
             c.shared
                 .network
                 .lock(|net| net.direct_stream(settings.dual_iir.stream_target));
 
+            (
+                &mut c.shared.active_settings,
+                &mut c.shared.iir_state,
+                &mut c.shared.iir_fixed,
+            )
+                .lock(|current, iir_state, fixed| {
+                    if settings.dual_iir.bumpless_transfer {
+                        for (channel, state) in iir_state.iter_mut().enumerate()
+                        {
+                            // Only sections that remain active carry state across the update.
+                            let active = current.active_sections[channel].min(
+                                settings.dual_iir.active_sections[channel],
+                            );
+                            for ((old, new), state) in current.iir_ch[channel]
+                                .iter()
+                                .zip(settings.dual_iir.iir_ch[channel].iter())
+                                .zip(state.iter_mut())
+                                .take(active)
+                            {
+                                bumpless_transfer(old, new, state);
+                            }
+                        }
+                    }
+                    if let Some(iir_fixed) = iir_fixed {
+                        *fixed = iir_fixed;
+                    }
+                    *current = settings.dual_iir.clone();
+                });
+        });
+    }
+
+    /// Apply a frontend offset DAC code and measure the resulting mean ADC input.
+    ///
+    /// # Returns
+    /// The mean ADC input codes over the window or `None` if no samples have been accumulated.
+    async fn measure_offset(
+        c: &mut auto_zero::Context<'_>,
+        code: u16,
+        window: u32,
+    ) -> Option<[f32; 2]> {
+        if let Some(Err(err)) = c
+            .shared
+            .gpio_dac_spi
+            .lock(|spi| spi.as_mut().map(|spi| spi.write(&[code])))
+        {
+            log::error!("Failed to update frontend offset DAC: {:?}", err);
+        }
+
+        // Allow the frontend to settle before accumulating.
+        Systick::delay(AUTO_ZERO_SETTLE_MS.millis()).await;
+        c.shared.adc_mean.lock(|mean| mean.start());
+        Systick::delay(window.millis()).await;
+        c.shared.adc_mean.lock(|mean| mean.finish())
+    }
+
+    #[task(priority = 1, shared=[settings, gpio_dac_spi, adc_mean, auto_zero_result, auto_zero_active])]
+    async fn auto_zero(mut c: auto_zero::Context) {
+        c.shared.auto_zero_active.lock(|active| *active = true);
+        search_offset(&mut c).await;
+        c.shared.auto_zero_active.lock(|active| *active = false);
+    }
+
+    /// Search the frontend offset DAC code that zeroes the mean ADC input. See [AutoZero].
+    async fn search_offset(c: &mut auto_zero::Context<'_>) {
+        let (config, afe, offset) = c.shared.settings.lock(|settings| {
+            (
+                settings.dual_iir.auto_zero,
+                settings.dual_iir.afe,
+                settings.dual_iir.frontend_offset,
+            )
+        });
+
+        let Some(gain) = afe.get(config.channel) else {
+            log::error!("Invalid auto-zero channel: {}", config.channel);
+            return;
+        };
+
+        if c.shared.gpio_dac_spi.lock(|spi| spi.is_none()) {
+            log::error!("Auto-zero requires the frontend offset DAC");
+            return;
+        }
+
+        let channel = config.channel;
+        let volts_per_lsb = AdcCode::VOLT_PER_LSB / gain.as_multiplier();
+        let window = ((config.window * 1.0e3) as u32).max(1);
+
+        // The mean inputs at both ends of the offset DAC range have to bracket zero.
+        let (mut lo, mut hi) = (0, u16::MAX);
+        let (Some(lo_mean), Some(hi_mean)) = (
+            measure_offset(c, lo, window).await,
+            measure_offset(c, hi, window).await,
+        ) else {
+            log::error!("Auto-zero aborted: no ADC samples");
+            c.shared
+                .gpio_dac_spi
+                .lock(|spi| spi.as_mut().map(|spi| spi.write(&[offset]).ok()));
+            return;
+        };
+        let (lo_mean, hi_mean) = (lo_mean[channel], hi_mean[channel]);
+        if (lo_mean < 0.) == (hi_mean < 0.) {
+            log::error!(
+                "Auto-zero failed: mean input {} V to {} V does not cross zero",
+                lo_mean * volts_per_lsb,
+                hi_mean * volts_per_lsb
+            );
             c.shared
-                .active_settings
-                .lock(|current| *current = settings.dual_iir.clone());
+                .gpio_dac_spi
+                .lock(|spi| spi.as_mut().map(|spi| spi.write(&[offset]).ok()));
+            return;
+        }
+
+        let (mut code, mut residual) = if lo_mean.abs() < hi_mean.abs() {
+            (lo, lo_mean)
+        } else {
+            (hi, hi_mean)
+        };
+
+        while (residual * volts_per_lsb).abs() > config.tolerance && hi - lo > 1
+        {
+            let mid = lo + (hi - lo) / 2;
+            let Some(mean) = measure_offset(c, mid, window).await else {
+                log::error!("Auto-zero aborted: no ADC samples");
+                c.shared.gpio_dac_spi.lock(|spi| {
+                    spi.as_mut().map(|spi| spi.write(&[offset]).ok())
+                });
+                return;
+            };
+            let mean = mean[channel];
+            if mean.abs() < residual.abs() {
+                (code, residual) = (mid, mean);
+            }
+
+            if (mean < 0.) == (lo_mean < 0.) {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+
+        let residual = residual * volts_per_lsb;
+        if residual.abs() > config.tolerance {
+            log::warn!(
+                "Auto-zero did not converge: frontend offset {}, residual {} V",
+                code,
+                residual
+            );
+        } else {
+            log::info!(
+                "Auto-zero converged: frontend offset {}, residual {} V",
+                code,
+                residual
+            );
+        }
+
+        c.shared
+            .gpio_dac_spi
+            .lock(|spi| spi.as_mut().map(|spi| spi.write(&[code]).ok()));
+        c.shared
+            .settings
+            .lock(|settings| settings.dual_iir.frontend_offset = code);
+        c.shared
+            .auto_zero_result
+            .lock(|result| *result = Some(AutoZeroResult { code, residual }));
+    }
+
+    #[task(priority = 1, shared=[network, settings, analyzer_link, sampling])]
+    async fn network_analyzer(mut c: network_analyzer::Context) {
+        let sample_period =
+            c.shared.sampling.lock(|sampling| sampling.sample_period());
+
+        let (config, afe) = c.shared.settings.lock(|settings| {
+            (settings.dual_iir.network_analyzer, settings.dual_iir.afe)
         });
+
+        let amplitude = config.amplitude * DacCode::LSB_PER_VOLT;
+        if !(0. ..=i16::MAX as f32).contains(&amplitude) {
+            log::error!(
+                "Invalid network analyzer amplitude: {} V",
+                config.amplitude
+            );
+            return;
+        }
+
+        let reference_scale = config.reference.volts_per_lsb(&afe);
+        let response_scale = config.response.volts_per_lsb(&afe);
+
+        for index in 0..config.points {
+            let frequency = if config.points > 1 {
+                config.start
+                    * libm::powf(
+                        config.stop / config.start,
+                        index as f32 / (config.points - 1) as f32,
+                    )
+            } else {
+                config.start
+            };
+
+            let Some(measurement) = Measurement::new(
+                frequency,
+                amplitude as i16,
+                config.settle_time,
+                config.integration_time,
+                sample_period,
+            ) else {
+                log::error!(
+                    "Invalid network analyzer frequency: {} Hz",
+                    frequency
+                );
+                return;
+            };
+
+            c.shared.analyzer_link.lock(|link| {
+                link.request = Some(measurement);
+                link.result = None;
+            });
+
+            // Allow twice the expected measurement duration plus a margin for the hand-over.
+            let duration =
+                measurement.settle.saturating_add(measurement.samples) as f32
+                    * sample_period;
+            let timeout = ((2e3 * duration) as u32)
+                .saturating_add(NETWORK_ANALYZER_MARGIN_MS);
+            let mut elapsed = 0;
+            let result = loop {
+                Systick::delay(1.millis()).await;
+                if let Some(result) =
+                    c.shared.analyzer_link.lock(|link| link.result.take())
+                {
+                    break result;
+                }
+                elapsed += 1;
+                if elapsed > timeout {
+                    c.shared.analyzer_link.lock(|link| link.request = None);
+                    log::error!(
+                        "Network analyzer timed out at {} Hz after {} ms",
+                        frequency,
+                        elapsed
+                    );
+                    return;
+                }
+            };
+
+            let point =
+                result.point(frequency, reference_scale, response_scale);
+            c.shared.network.lock(|net| {
+                net.telemetry.publish_to("network_analyzer", &point)
+            });
+        }
+    }
+
+    #[task(priority = 1, local=[capture_info], shared=[network, settings, capture_link, sampling])]
+    async fn capture(mut c: capture::Context, arm: bool) {
+        if arm {
+            let period =
+                c.shared.sampling.lock(|sampling| sampling.sample_period());
+
+            let (config, afe) = c.shared.settings.lock(|settings| {
+                (settings.dual_iir.capture, settings.dual_iir.afe)
+            });
+
+            let scale = config.probe.map(|probe| probe.volts_per_lsb(&afe));
+            let level = match config.source {
+                Source::Channel0 => config.level / scale[0],
+                Source::Channel1 => config.level / scale[1],
+                _ => 0.,
+            };
+            if !(i16::MIN as f32..=i16::MAX as f32).contains(&level) {
+                log::error!(
+                    "Invalid capture trigger level: {} V",
+                    config.level
+                );
+                return;
+            }
+
+            if !(config.timeout > 0. && config.timeout.is_finite()) {
+                log::error!("Invalid capture timeout: {} s", config.timeout);
+                return;
+            }
+
+            let Some(request) = Request {
+                probe: config.probe,
+                source: config.source,
+                condition: config.condition,
+                slope: config.slope,
+                level: level as i16,
+                pre_trigger: config.pre_trigger as usize,
+                length: config.length as usize,
+            }
+            .validate() else {
+                log::error!(
+                    "Invalid capture length {} or pre-trigger depth {}",
+                    config.length,
+                    config.pre_trigger
+                );
+                return;
+            };
+
+            let sequence = c
+                .local
+                .capture_info
+                .map(|info| info.sequence.wrapping_add(1))
+                .unwrap_or_default();
+
+            // The previous record is retained until the new capture has completed.
+            c.shared.capture_link.lock(|link| {
+                link.request = Some(request);
+                link.completed = false;
+            });
+
+            // Note(as): The conversion saturates.
+            let timeout = (config.timeout * 1e3) as u32;
+            let mut elapsed = 0;
+            loop {
+                Systick::delay(10.millis()).await;
+                elapsed = elapsed.saturating_add(10u32);
+                let timed_out = elapsed > timeout;
+                let completed = c.shared.capture_link.lock(|link| {
+                    if !link.completed && timed_out {
+                        link.request = None;
+                        link.abort = true;
+                    }
+                    link.completed
+                });
+                if completed {
+                    break;
+                }
+                if timed_out {
+                    log::error!("Capture aborted after {} ms", elapsed);
+                    return;
+                }
+            }
+
+            *c.local.capture_info = Some(CaptureInfo {
+                sequence,
+                probe: config.probe,
+                scale,
+                period,
+            });
+        }
+
+        let Some(info) = *c.local.capture_info else {
+            log::warn!("No capture to read out");
+            return;
+        };
+
+        let Some((length, pre_trigger)) = c.shared.capture_link.lock(|link| {
+            link.record
+                .as_ref()
+                .map(|record| (record.len(), record.pre_trigger()))
+        }) else {
+            log::warn!("No capture to read out");
+            return;
+        };
+
+        let chunks = length.div_ceil(CAPTURE_CHUNK);
+        for index in 0..chunks {
+            let offset = index * CAPTURE_CHUNK;
+            let mut data = [[0; CAPTURE_CHUNK]; 2];
+            let [data0, data1] = &mut data;
+            let Some(count) = c.shared.capture_link.lock(|link| {
+                link.record.as_ref().map(|record| {
+                    record.copy(offset, [&mut data0[..], &mut data1[..]])
+                })
+            }) else {
+                return;
+            };
+
+            let chunk = CaptureChunk {
+                capture: info.sequence,
+                index: index as _,
+                chunks: chunks as _,
+                offset: offset as i32 - pre_trigger as i32,
+                period: info.period,
+                probe: info.probe,
+                scale: info.scale,
+                data: [&data[0][..count], &data[1][..count]],
+            };
+
+            // Retry until the chunk has been accepted, giving the network stack time to drain.
+            let mut attempts = 0;
+            while let Err(err) = c
+                .shared
+                .network
+                .lock(|net| net.telemetry.try_publish_to("capture", &chunk))
+            {
+                attempts += 1;
+                if attempts >= CAPTURE_PUBLISH_ATTEMPTS {
+                    log::error!(
+                        "Capture readout aborted at chunk {}/{}: {:?}",
+                        index,
+                        chunks,
+                        err
+                    );
+                    return;
+                }
+                Systick::delay(10.millis()).await;
+            }
+        }
     }
 
     // #[task(priority = 1, local=[cpu_dac1], shared=[network, settings])]
@@ -543,22 +2820,36 @@ mod app {
     //     });
     // }
 
-    #[task(priority = 1, shared=[network, settings, telemetry], local=[cpu_temp_sensor])]
+    #[task(priority = 1, shared=[network, settings, telemetry, locks, auto_zero_result], local=[cpu_temp_sensor])]
     async fn telemetry(mut c: telemetry::Context) {
         loop {
             let telemetry: TelemetryBuffer =
-                c.shared.telemetry.lock(|telemetry| *telemetry);
+                c.shared.telemetry.lock(core::mem::take);
 
             let (gains, telemetry_period) =
                 c.shared.settings.lock(|settings| {
                     (settings.dual_iir.afe, settings.dual_iir.telemetry_period)
                 });
 
+            let (lock_state, lock_losses) = c.shared.locks.lock(|locks| {
+                (
+                    [locks[0].state(), locks[1].state()],
+                    [locks[0].losses(), locks[1].losses()],
+                )
+            });
+
+            let auto_zero = c.shared.auto_zero_result.lock(|result| *result);
+
             c.shared.network.lock(|net| {
-                net.telemetry.publish(&telemetry.finalize(
-                    gains[0],
-                    gains[1],
-                    c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                net.telemetry.publish(&Telemetry::new(
+                    telemetry.finalize(
+                        gains[0],
+                        gains[1],
+                        c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                    ),
+                    lock_state,
+                    lock_losses,
+                    auto_zero,
                 ))
             });
 
@@ -584,6 +2875,14 @@ mod app {
                 }
             });
 
+            if let Some("auto-zero") =
+                c.local.usb_terminal.platform_mut().take_command()
+            {
+                if auto_zero::spawn().is_err() {
+                    log::warn!("Auto-zero already in progress");
+                }
+            }
+
             Systick::delay(10.millis()).await;
         }
     }
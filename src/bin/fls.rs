@@ -0,0 +1,1110 @@
+//! # FLS
+//!
+//! The `fls` (fiber length stabilization) application tracks the phase of two RF beat notes and
+//! feeds back on the frequency and phase of the Pounder DDS outputs to stabilize them, e.g. to
+//! cancel the phase noise of an optical fiber link with an acousto-optic modulator (AOM).
+//!
+//! The beat notes are applied to the Pounder IN0/IN1 inputs where they are mixed with the Pounder
+//! DDS IN0/IN1 local oscillators. The mixer IF outputs are sampled by Stabilizer ADC0/ADC1. The
+//! Pounder OUT0/OUT1 DDS outputs are the actuators, e.g. driving the AOMs.
+//!
+//! ## Features
+//! * Two independent channels
+//! * Configurable Pounder DDS local oscillators and attenuators
+//! * Digital IF demodulation and low-pass filtering
+//! * Phase unwrapping over many turns
+//! * Frequency feedback through a configurable IIR filter and direct phase feedback, both
+//!   updated on the DDS outputs once per batch
+//! * Feedback hold on loss of the beat note
+//! * Demodulated phase monitor on DAC0/DAC1
+//! * Phase and feedback data streaming via UDP
+//!
+//! ## Settings
+//! Refer to the [Fls] structure for documentation of run-time configurable settings for this
+//! application.
+//!
+//! ## Telemetry
+//! Refer to [Telemetry] for information about telemetry reported by this application.
+//!
+//! ## Livestreaming
+//! This application streams the tracking and feedback data of each batch over UDP in the
+//! [StreamFormat::Fls] format. Each batch contains, for channel 0 and then channel 1, as
+//! little-endian values:
+//! * `i32`: The in-phase component of the demodulated beat note (full scale `i32`)
+//! * `i32`: The quadrature component of the demodulated beat note (full scale `i32`)
+//! * `i64`: The unwrapped phase (`1 << 32` is a full turn)
+//! * `u32`: The DDS output frequency tuning word (`1 << 32` is the DDS system clock)
+//! * `u32`: The DDS output phase offset word (`1 << 14` is a full turn)
+//!
+//! ```
+//! <I0> <Q0> <phase0> <ftw0> <pow0> <I1> <Q1> <phase1> <ftw1> <pow1>
+//! ```
+//!
+//! Refer to [stabilizer::net::data_stream](../stabilizer/net/data_stream/index.html) for more
+//! information.
+#![no_std]
+#![no_main]
+
+use core::sync::atomic::{fence, Ordering};
+
+use rtic_monotonics::Monotonic;
+
+use fugit::ExtU32;
+use mutex_trait::prelude::*;
+
+use idsp::{iir, Accu, Complex, ComplexExt, Filter, Lowpass};
+
+use stabilizer::{
+    hardware::{
+        self,
+        adc::{Adc0Input, Adc1Input, AdcCode},
+        afe::Gain,
+        dac::{Dac0Output, Dac1Output, DacCode},
+        design_parameters, hal,
+        pounder::{
+            attenuators::AttenuatorInterface, dds_output::DdsOutput,
+            rf_power::PowerMeasurementInterface, Channel, PounderDevices,
+        },
+        timers::SamplingTimer,
+        DigitalInput0, DigitalInput1, SerialTerminal, SystemTimer, Systick,
+        UsbDevice, AFE0, AFE1,
+    },
+    net::{
+        data_stream::{FrameGenerator, StreamFormat, StreamTarget},
+        miniconf::Tree,
+        serde::Serialize,
+        telemetry::{Events, Statistics, TelemetryBuffer},
+        NetworkState, NetworkUsers,
+    },
+    settings::{NetSettings, SamplingSettings},
+};
+
+/// The Pounder input channels of the FLS channels.
+const INPUTS: [Channel; 2] = [Channel::In0, Channel::In1];
+
+/// The Pounder output channels of the FLS channels.
+const OUTPUTS: [Channel; 2] = [Channel::Out0, Channel::Out1];
+
+#[derive(Clone, Debug, Tree)]
+pub struct Settings {
+    #[tree(depth = 2)]
+    pub fls: Fls,
+
+    #[tree(depth = 1)]
+    pub net: NetSettings,
+
+    #[tree(depth = 1)]
+    pub sampling: SamplingSettings,
+}
+
+impl stabilizer::settings::AppSettings for Settings {
+    fn new(net: NetSettings) -> Self {
+        Self {
+            net,
+            fls: Fls::default(),
+            sampling: SamplingSettings::default(),
+        }
+    }
+
+    fn net(&self) -> &NetSettings {
+        &self.net
+    }
+
+    fn sampling(&self) -> &SamplingSettings {
+        &self.sampling
+    }
+}
+
+impl serial_settings::Settings<3> for Settings {
+    fn reset(&mut self) {
+        *self = Self {
+            fls: Fls::default(),
+            net: NetSettings::new(self.net.mac),
+            sampling: SamplingSettings::default(),
+        }
+    }
+}
+
+/// Configuration derived from the [Fls] settings in hardware units.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Config {
+    /// The demodulation phase increment per sample.
+    demodulation: [i32; 2],
+    /// The demodulation lowpass gains.
+    lowpass: [<Lowpass<2> as Filter>::Config; 2],
+    /// The feedback hold amplitude threshold relative to full scale.
+    min_amplitude: [f32; 2],
+    /// The unwrapped phase setpoint.
+    setpoint: [i64; 2],
+    /// The DDS local oscillator frequency tuning words.
+    lo_ftw: [u32; 2],
+    /// The DDS output frequency tuning words without feedback.
+    output_ftw: [u32; 2],
+    /// The DDS output phase offset words without feedback.
+    output_pow: [u16; 2],
+    /// The DDS output amplitude control words.
+    output_acr: [u32; 2],
+}
+
+#[derive(Clone, Debug, Tree)]
+pub struct Fls {
+    /// Configure the Analog Front End (AFE) gain.
+    ///
+    /// # Path
+    /// `afe/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// Any of the variants of [Gain] enclosed in double quotes.
+    #[tree(depth = 1)]
+    afe: [Gain; 2],
+
+    /// Configure the Pounder input attenuation.
+    ///
+    /// # Path
+    /// `input_attenuation/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The attenuation of the IN0/IN1 beat note in dB, between 0 and 31.5.
+    #[tree(depth = 1)]
+    input_attenuation: [f32; 2],
+
+    /// Configure the Pounder output attenuation.
+    ///
+    /// # Path
+    /// `output_attenuation/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The attenuation of the OUT0/OUT1 DDS output in dB, between 0 and 31.5.
+    #[tree(depth = 1)]
+    output_attenuation: [f32; 2],
+
+    /// Configure the local oscillator frequency.
+    ///
+    /// # Path
+    /// `lo_frequency/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The frequency of the IN0/IN1 DDS local oscillator in Hertz, below half the DDS system
+    /// clock.
+    #[tree(depth = 1)]
+    lo_frequency: [f32; 2],
+
+    /// Configure the demodulation frequency.
+    ///
+    /// # Path
+    /// `demodulation_frequency/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The frequency of the mixer IF sampled by ADC0/ADC1 in Hertz, below half the sample rate.
+    /// If the frequency is not valid for the sampling configuration applied at boot, it is reset
+    /// to an eighth of the sample rate.
+    #[tree(depth = 1)]
+    demodulation_frequency: [f32; 2],
+
+    /// Configure the demodulation lowpass.
+    ///
+    /// # Path
+    /// `lowpass_tc/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The time constant in seconds of the second order (quality factor one) demodulation lowpass.
+    /// It must be at least two sample periods.
+    #[tree(depth = 1)]
+    lowpass_tc: [f32; 2],
+
+    /// Configure the feedback hold threshold.
+    ///
+    /// # Path
+    /// `min_amplitude/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The demodulated beat note amplitude in volts at the ADC below which the feedback is held.
+    #[tree(depth = 1)]
+    min_amplitude: [f32; 2],
+
+    /// Enable the feedback.
+    ///
+    /// # Path
+    /// `feedback/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// "true" or "false". While disabled, the feedback is cleared and the unwrapped phase is
+    /// reset to the current phase within half a turn.
+    #[tree(depth = 1)]
+    feedback: [bool; 2],
+
+    /// Configure the phase setpoint.
+    ///
+    /// # Path
+    /// `phase_setpoint/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The unwrapped phase setpoint in turns.
+    #[tree(depth = 1)]
+    phase_setpoint: [f32; 2],
+
+    /// Configure the frequency feedback filter.
+    ///
+    /// # Path
+    /// `iir/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [iir::Biquad]. The filter operates once per batch on the phase error in turns and
+    /// outputs the DDS output frequency offset in Hertz.
+    #[tree(depth = 1)]
+    iir: [iir::Biquad<f32>; 2],
+
+    /// Configure the phase feedback gain.
+    ///
+    /// # Path
+    /// `phase_gain/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The DDS output phase offset in turns per turn of phase error.
+    #[tree(depth = 1)]
+    phase_gain: [f32; 2],
+
+    /// Configure the output frequency.
+    ///
+    /// # Path
+    /// `output_frequency/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The frequency of the OUT0/OUT1 DDS output without feedback in Hertz, below half the DDS
+    /// system clock.
+    #[tree(depth = 1)]
+    output_frequency: [f32; 2],
+
+    /// Configure the output phase.
+    ///
+    /// # Path
+    /// `output_phase/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The phase offset of the OUT0/OUT1 DDS output without feedback in turns.
+    #[tree(depth = 1)]
+    output_phase: [f32; 2],
+
+    /// Configure the output amplitude.
+    ///
+    /// # Path
+    /// `output_amplitude/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The amplitude of the OUT0/OUT1 DDS output relative to full scale, between 0 and 1.
+    #[tree(depth = 1)]
+    output_amplitude: [f32; 2],
+
+    /// Specifies the telemetry output period in seconds.
+    ///
+    /// # Path
+    /// `telemetry_period`
+    ///
+    /// # Value
+    /// Any non-zero value less than 65536.
+    telemetry_period: u16,
+
+    /// Specifies the target for data livestreaming.
+    ///
+    /// # Path
+    /// `stream_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    stream_target: StreamTarget,
+}
+
+impl Default for Fls {
+    fn default() -> Self {
+        // A proportional frequency feedback of 1 Hz per turn, limited to 100 kHz.
+        let mut iir = iir::Biquad::IDENTITY;
+        iir.set_min(-100e3);
+        iir.set_max(100e3);
+        Self {
+            afe: [Gain::G1; 2],
+            input_attenuation: [0.; 2],
+            // Maximum attenuation until configured.
+            output_attenuation: [31.5; 2],
+            lo_frequency: [80e6; 2],
+            demodulation_frequency: [Self::default_demodulation_frequency(
+                SamplingSettings::default().sample_period(),
+            ); 2],
+            lowpass_tc: [10e-6; 2],
+            min_amplitude: [0.01; 2],
+            feedback: [false; 2],
+            phase_setpoint: [0.; 2],
+            iir: [iir; 2],
+            phase_gain: [0.; 2],
+            output_frequency: [80e6; 2],
+            output_phase: [0.; 2],
+            output_amplitude: [1.; 2],
+            // The default telemetry period in seconds.
+            telemetry_period: 10,
+
+            stream_target: StreamTarget::default(),
+        }
+    }
+}
+
+/// Compute a DDS frequency tuning word.
+///
+/// # Args
+/// * `frequency` - The frequency in Hertz.
+///
+/// # Returns
+/// The tuning word or `None` if the frequency is negative or not below half the DDS system clock.
+fn frequency_tuning_word(frequency: f32) -> Option<u32> {
+    let ftw = frequency
+        * ((1u64 << 32) as f32
+            / design_parameters::DDS_SYSTEM_CLK.to_Hz() as f32);
+    (0. ..(1u32 << 31) as f32)
+        .contains(&ftw)
+        .then_some(ftw as u32)
+}
+
+/// Compute a DDS phase offset word.
+///
+/// # Args
+/// * `phase` - The phase in turns.
+fn phase_offset_word(phase: f32) -> u16 {
+    // Note(as): The phase wraps to a turn. The conversion saturates.
+    (phase * (1 << 14) as f32) as i32 as u16 & 0x3FFF
+}
+
+/// Compute a DDS amplitude control word.
+///
+/// # Args
+/// * `amplitude` - The amplitude relative to full scale.
+///
+/// # Returns
+/// The control word or `None` if the amplitude is not between 0 and 1.
+fn amplitude_control_word(amplitude: f32) -> Option<u32> {
+    if !(0.0..=1.0).contains(&amplitude) {
+        return None;
+    }
+    let scale = (amplitude * (1 << 10) as f32) as u32;
+    // The amplitude multiplier is disabled at full scale.
+    Some(if scale < 1 << 10 {
+        (1 << 12) | scale
+    } else {
+        0
+    })
+}
+
+impl Fls {
+    /// The default demodulation frequency: an eighth of the sample rate.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    fn default_demodulation_frequency(sample_period: f32) -> f32 {
+        0.125 / sample_period
+    }
+
+    /// Update the derived [Config] from the settings.
+    ///
+    /// Invalid settings are logged and leave the respective configuration unchanged.
+    ///
+    /// # Args
+    /// * `config` - The configuration to update.
+    /// * `sample_period` - The time in seconds between samples.
+    fn configure(&self, config: &mut Config, sample_period: f32) {
+        for i in 0..2 {
+            let demodulation = self.demodulation_frequency[i] * sample_period;
+            if (0. ..0.5).contains(&demodulation) {
+                config.demodulation[i] =
+                    (demodulation * (1u64 << 32) as f32) as i32;
+            } else {
+                log::error!(
+                    "Invalid demodulation frequency on channel {}: {}",
+                    i,
+                    self.demodulation_frequency[i]
+                );
+            }
+
+            // The corner angular frequency in units of the sample rate.
+            let k = sample_period / self.lowpass_tc[i];
+            if k > 0. && k <= 0.5 {
+                // Note(as): The conversions saturate.
+                config.lowpass[i] = [
+                    (k * k * (1u64 << 31) as f32) as i32,
+                    (-k * (1u64 << 32) as f32) as i32,
+                ];
+            } else {
+                log::error!(
+                    "Invalid lowpass time constant on channel {}: {}",
+                    i,
+                    self.lowpass_tc[i]
+                );
+            }
+
+            // The demodulated amplitude is relative to the ADC full scale.
+            config.min_amplitude[i] = self.min_amplitude[i]
+                * self.afe[i].as_multiplier()
+                / (AdcCode::VOLT_PER_LSB * (1 << 15) as f32);
+
+            // Note(as): The conversion saturates.
+            config.setpoint[i] =
+                (self.phase_setpoint[i] as f64 * (1u64 << 32) as f64) as i64;
+
+            match frequency_tuning_word(self.lo_frequency[i]) {
+                Some(ftw) => config.lo_ftw[i] = ftw,
+                None => log::error!(
+                    "Invalid LO frequency on channel {}: {}",
+                    i,
+                    self.lo_frequency[i]
+                ),
+            }
+
+            match frequency_tuning_word(self.output_frequency[i]) {
+                Some(ftw) => config.output_ftw[i] = ftw,
+                None => log::error!(
+                    "Invalid output frequency on channel {}: {}",
+                    i,
+                    self.output_frequency[i]
+                ),
+            }
+
+            config.output_pow[i] = phase_offset_word(self.output_phase[i]);
+
+            match amplitude_control_word(self.output_amplitude[i]) {
+                Some(acr) => config.output_acr[i] = acr,
+                None => log::error!(
+                    "Invalid output amplitude on channel {}: {}",
+                    i,
+                    self.output_amplitude[i]
+                ),
+            }
+        }
+    }
+}
+
+/// Phase tracking and feedback state of an FLS channel.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Tracker {
+    // The most recent demodulated beat note, full scale `i32`.
+    iq: Complex<i32>,
+    // The most recent wrapped phase, `1 << 32` per turn.
+    wrapped: i32,
+    // The unwrapped phase, `1 << 32` per turn.
+    phase: i64,
+    // The frequency feedback in Hertz.
+    frequency_offset: f32,
+    // The phase feedback in turns.
+    phase_offset: f32,
+    // The number of batches with the feedback held since the last telemetry.
+    holds: u32,
+}
+
+/// Telemetry of an FLS channel.
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct ChannelTelemetry {
+    /// The most recent demodulated beat note amplitude at the ADC in volts.
+    amplitude: f32,
+
+    /// The most recent unwrapped phase in turns.
+    phase: f64,
+
+    /// The most recent DDS output frequency feedback in Hertz.
+    frequency_offset: f32,
+
+    /// The most recent DDS output phase feedback in turns.
+    phase_offset: f32,
+
+    /// The number of batches with the feedback held due to a low beat note amplitude over the
+    /// telemetry period.
+    holds: u32,
+
+    /// The Pounder input power in dBm.
+    input_power: f32,
+}
+
+impl Tracker {
+    /// Track the phase of a demodulated beat note.
+    ///
+    /// The phase is unwrapped correctly as long as it changes by less than half a turn per batch.
+    ///
+    /// # Args
+    /// * `iq` - The demodulated beat note.
+    ///
+    /// # Returns
+    /// The unwrapped phase, `1 << 32` per turn.
+    pub fn update(&mut self, iq: Complex<i32>) -> i64 {
+        let wrapped = iq.arg();
+        self.phase += wrapped.wrapping_sub(self.wrapped) as i64;
+        self.wrapped = wrapped;
+        self.iq = iq;
+        self.phase
+    }
+
+    /// Clear the feedback and the full turns of the unwrapped phase.
+    pub fn reset(&mut self) {
+        self.phase = self.wrapped as i64;
+        self.frequency_offset = 0.;
+        self.phase_offset = 0.;
+    }
+
+    /// The most recent demodulated beat note amplitude relative to full scale.
+    pub fn amplitude(&self) -> f32 {
+        let re = self.iq.re as f32;
+        let im = self.iq.im as f32;
+        libm::sqrtf(re * re + im * im) * (1. / (1u64 << 31) as f32)
+    }
+
+    /// Collect the channel telemetry and restart the hold counter.
+    ///
+    /// # Args
+    /// * `gain` - The AFE gain.
+    /// * `input_power` - The Pounder input power in dBm.
+    pub fn telemetry(
+        &mut self,
+        gain: Gain,
+        input_power: f32,
+    ) -> ChannelTelemetry {
+        ChannelTelemetry {
+            amplitude: self.amplitude()
+                * AdcCode::VOLT_PER_LSB
+                * (1 << 15) as f32
+                / gain.as_multiplier(),
+            phase: self.phase as f64 * (1. / (1u64 << 32) as f64),
+            frequency_offset: self.frequency_offset,
+            phase_offset: self.phase_offset,
+            holds: core::mem::take(&mut self.holds),
+            input_power,
+        }
+    }
+}
+
+/// Telemetry reported by the FLS application.
+///
+/// This extends the common [stabilizer::net::telemetry::Telemetry] by application-specific
+/// fields.
+#[derive(Serialize)]
+pub struct Telemetry {
+    /// Input voltage statistics over the telemetry period.
+    adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    dacs: [Statistics; 2],
+
+    /// Event counters of each channel over the telemetry period.
+    events: [Events; 2],
+
+    /// Most recent digital input assertion state.
+    digital_inputs: [bool; 2],
+
+    /// The CPU temperature in degrees Celsius.
+    cpu_temp: f32,
+
+    /// The Pounder temperature in degrees Celsius.
+    pounder_temp: f32,
+
+    /// The phase tracking and feedback telemetry of each channel.
+    channels: [ChannelTelemetry; 2],
+}
+
+impl Telemetry {
+    fn new(
+        telemetry: stabilizer::net::telemetry::Telemetry,
+        pounder_temp: f32,
+        channels: [ChannelTelemetry; 2],
+    ) -> Self {
+        Self {
+            adcs: telemetry.adcs,
+            dacs: telemetry.dacs,
+            events: telemetry.events,
+            digital_inputs: telemetry.digital_inputs,
+            cpu_temp: telemetry.cpu_temp,
+            pounder_temp,
+            channels,
+        }
+    }
+}
+
+#[rtic::app(device = stabilizer::hardware::hal::stm32, peripherals = true, dispatchers=[DCMI, JPEG, SDMMC])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        usb: UsbDevice,
+        network: NetworkUsers<Fls, 2>,
+        settings: Settings,
+        active_settings: Fls,
+        config: Config,
+        telemetry: TelemetryBuffer,
+        trackers: [Tracker; 2],
+        pounder: PounderDevices,
+    }
+
+    #[local]
+    struct Local {
+        usb_terminal: SerialTerminal<Settings, 3>,
+        sampling_timer: SamplingTimer,
+        sampling: SamplingSettings,
+        digital_inputs: (DigitalInput0, DigitalInput1),
+        afes: (AFE0, AFE1),
+        adcs: (Adc0Input, Adc1Input),
+        dacs: (Dac0Output, Dac1Output),
+        dds_output: DdsOutput,
+        // The DDS local oscillator frequency and output amplitude control words last written.
+        dds_static: Option<([u32; 2], [u32; 2])>,
+        lockin: [idsp::Lockin<Lowpass<2>>; 2],
+        demodulation_phase: [i32; 2],
+        iir_state: [[f32; 4]; 2],
+        generator: FrameGenerator,
+        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+    }
+
+    #[init]
+    fn init(c: init::Context) -> (Shared, Local) {
+        let clock = SystemTimer::new(|| Systick::now().ticks());
+
+        // Configure the microcontroller
+        let mut stabilizer =
+            hardware::setup::setup::<Settings, 3>(c.core, c.device, clock);
+
+        // The stored demodulation frequencies may not be valid for the applied sampling
+        // configuration.
+        let sample_period = stabilizer.sampling.sample_period();
+        for (i, frequency) in stabilizer
+            .settings
+            .fls
+            .demodulation_frequency
+            .iter_mut()
+            .enumerate()
+        {
+            if !(0. ..0.5).contains(&(*frequency * sample_period)) {
+                log::error!(
+                    "Invalid demodulation frequency on channel {}: {}. Using default.",
+                    i,
+                    frequency
+                );
+                *frequency = Fls::default_demodulation_frequency(sample_period);
+            }
+        }
+
+        let pounder = stabilizer
+            .pounder
+            .expect("The FLS application requires Pounder");
+
+        let mut network = NetworkUsers::new(
+            stabilizer.net.stack,
+            stabilizer.net.phy,
+            clock,
+            env!("CARGO_BIN_NAME"),
+            &stabilizer.settings.net,
+            stabilizer.metadata,
+        );
+
+        let generator = network.configure_streaming(StreamFormat::Fls);
+
+        let shared = Shared {
+            network,
+            usb: stabilizer.usb,
+            telemetry: TelemetryBuffer::default(),
+            trackers: Default::default(),
+            pounder: pounder.pounder,
+            active_settings: stabilizer.settings.fls.clone(),
+            config: Config::default(),
+            settings: stabilizer.settings,
+        };
+
+        let mut local = Local {
+            usb_terminal: stabilizer.usb_serial,
+            sampling_timer: stabilizer.adc_dac_timer,
+            sampling: stabilizer.sampling,
+            digital_inputs: stabilizer.digital_inputs,
+            afes: stabilizer.afes,
+            adcs: stabilizer.adcs,
+            dacs: stabilizer.dacs,
+            dds_output: pounder.dds_output,
+            dds_static: None,
+            lockin: Default::default(),
+            demodulation_phase: [0; 2],
+            iir_state: [[0.; 4]; 2],
+            generator,
+            cpu_temp_sensor: stabilizer.temperature_sensor,
+        };
+
+        // Enable ADC/DAC events
+        local.adcs.0.start();
+        local.adcs.1.start();
+        local.dacs.0.start();
+        local.dacs.1.start();
+
+        // Spawn a settings and telemetry update for default settings.
+        settings_update::spawn().unwrap();
+        telemetry::spawn().unwrap();
+        ethernet_link::spawn().unwrap();
+        start::spawn().unwrap();
+        usb::spawn().unwrap();
+
+        (shared, local)
+    }
+
+    #[task(priority = 1, local=[sampling_timer])]
+    async fn start(c: start::Context) {
+        Systick::delay(100.millis()).await;
+        // Start sampling ADCs and DACs.
+        c.local.sampling_timer.start();
+    }
+
+    /// Main DSP processing routine.
+    ///
+    /// See `dual-iir` for general notes on processing time and timing.
+    ///
+    /// Each ADC batch is demodulated at the IF and low-pass filtered. The phase of the decimated
+    /// beat note is unwrapped and compared to the setpoint. The phase error drives the frequency
+    /// feedback filter and the direct phase feedback. Both are applied to the Pounder DDS outputs
+    /// through a single profile write per batch. The wrapped phase is output on DAC0/DAC1 with
+    /// half a turn at full scale.
+    #[task(binds=DMA1_STR4, shared=[active_settings, config, telemetry, trackers], local=[adcs, dacs, dds_output, dds_static, lockin, demodulation_phase, iir_state, generator], priority=3)]
+    #[link_section = ".itcm.process"]
+    fn process(c: process::Context) {
+        let process::SharedResources {
+            active_settings,
+            config,
+            telemetry,
+            trackers,
+            ..
+        } = c.shared;
+
+        let process::LocalResources {
+            adcs: (adc0, adc1),
+            dacs: (dac0, dac1),
+            dds_output,
+            dds_static,
+            lockin,
+            demodulation_phase,
+            iir_state,
+            generator,
+            ..
+        } = c.local;
+
+        (active_settings, config, telemetry, trackers).lock(
+            |settings, config, telemetry, trackers| {
+                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                    let adc_samples = [adc0, adc1];
+                    let mut dac_samples = [dac0, dac1];
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+
+                    let mut dds = [(0u32, 0u16); 2];
+
+                    for i in 0..2 {
+                        let frequency = config.demodulation[i];
+                        let phase = demodulation_phase[i];
+                        let samples = &adc_samples[i];
+                        demodulation_phase[i] = phase.wrapping_add(
+                            frequency.wrapping_mul(samples.len() as i32),
+                        );
+
+                        let iq = samples
+                            .iter()
+                            .zip(Accu::new(phase, frequency))
+                            // Convert to signed, MSB align the ADC sample, demodulate the IF
+                            // (complex conjugate LO) and filter.
+                            .map(|(&sample, phase)| {
+                                let s = (sample as i16 as i32) << 16;
+                                lockin[i].update(
+                                    s,
+                                    phase.wrapping_neg(),
+                                    &config.lowpass[i],
+                                )
+                            })
+                            // Decimate
+                            .last()
+                            .unwrap()
+                            * 2; // Full scale assuming the 2f component is gone.
+
+                        let tracker = &mut trackers[i];
+                        let phase = tracker.update(iq);
+
+                        if !settings.feedback[i] {
+                            tracker.reset();
+                            iir_state[i] = [0.; 4];
+                        } else if tracker.amplitude() < config.min_amplitude[i]
+                        {
+                            // The phase is unreliable. Hold the feedback.
+                            tracker.holds = tracker.holds.saturating_add(1);
+                        } else {
+                            let error = phase.wrapping_sub(config.setpoint[i])
+                                as f32
+                                * (1. / (1u64 << 32) as f32);
+                            tracker.frequency_offset = settings.iir[i]
+                                .update(&mut iir_state[i], error);
+                            tracker.phase_offset =
+                                settings.phase_gain[i] * error;
+                        }
+
+                        // Note(as): The conversion saturates.
+                        let ftw_offset = (tracker.frequency_offset
+                            * ((1u64 << 32) as f32
+                                / design_parameters::DDS_SYSTEM_CLK.to_Hz()
+                                    as f32))
+                            as i32;
+                        dds[i] = (
+                            config.output_ftw[i]
+                                .wrapping_add(ftw_offset as u32),
+                            config.output_pow[i].wrapping_add(
+                                phase_offset_word(tracker.phase_offset),
+                            ) & 0x3FFF,
+                        );
+
+                        // Monitor the wrapped phase.
+                        let code =
+                            DacCode::from((tracker.wrapped >> 16) as i16).0;
+                        dac_samples[i].fill(code);
+                    }
+
+                    // The local oscillators and output amplitudes are only written when they
+                    // change. They take the place of the feedback for that batch.
+                    let dds_config = (config.lo_ftw, config.output_acr);
+                    let mut builder = dds_output.builder();
+                    if *dds_static != Some(dds_config) {
+                        for i in 0..2 {
+                            builder
+                                .update_channels(
+                                    INPUTS[i].into(),
+                                    Some(config.lo_ftw[i]),
+                                    None,
+                                    None,
+                                )
+                                .update_channels(
+                                    OUTPUTS[i].into(),
+                                    None,
+                                    None,
+                                    Some(config.output_acr[i]),
+                                );
+                        }
+                        *dds_static = Some(dds_config);
+                    } else {
+                        for (i, (ftw, pow)) in dds.iter().enumerate() {
+                            builder.update_channels(
+                                OUTPUTS[i].into(),
+                                Some(*ftw),
+                                Some(*pow),
+                                None,
+                            );
+                        }
+                    }
+                    builder.write();
+
+                    // Stream the tracking and feedback data.
+                    generator.add(|buf| {
+                        let mut len = 0;
+                        for (tracker, (ftw, pow)) in trackers.iter().zip(dds) {
+                            for value in [
+                                &tracker.iq.re.to_le_bytes()[..],
+                                &tracker.iq.im.to_le_bytes(),
+                                &tracker.phase.to_le_bytes(),
+                                &ftw.to_le_bytes(),
+                                &(pow as u32).to_le_bytes(),
+                            ] {
+                                for (byte, buf) in
+                                    value.iter().zip(&mut buf[len..])
+                                {
+                                    buf.write(*byte);
+                                }
+                                len += value.len();
+                            }
+                        }
+                        len
+                    });
+
+                    // Update telemetry measurements.
+                    telemetry.add(
+                        [adc_samples[0], adc_samples[1]],
+                        [dac_samples[0], dac_samples[1]],
+                    );
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+                });
+            },
+        );
+    }
+
+    #[idle(shared=[settings, network, usb])]
+    fn idle(mut c: idle::Context) -> ! {
+        loop {
+            match (&mut c.shared.network, &mut c.shared.settings)
+                .lock(|net, settings| net.update(&mut settings.fls))
+            {
+                NetworkState::SettingsChanged => {
+                    settings_update::spawn().unwrap()
+                }
+                NetworkState::Updated => {}
+                NetworkState::NoChange => {
+                    // We can't sleep if USB is not in suspend.
+                    if c.shared.usb.lock(|usb| {
+                        usb.state()
+                            == usb_device::device::UsbDeviceState::Suspend
+                    }) {
+                        cortex_m::asm::wfi();
+                    }
+                }
+            }
+        }
+    }
+
+    #[task(priority = 1, local=[afes, sampling], shared=[network, settings, active_settings, config, pounder])]
+    async fn settings_update(mut c: settings_update::Context) {
+        let sample_period = c.local.sampling.sample_period();
+        let mut config = c.shared.config.lock(|config| *config);
+
+        c.shared.settings.lock(|settings| {
+            settings.fls.configure(&mut config, sample_period);
+
+            c.local.afes.0.set_gain(settings.fls.afe[0]);
+            c.local.afes.1.set_gain(settings.fls.afe[1]);
+
+            c.shared.pounder.lock(|pounder| {
+                for (channels, attenuations) in [
+                    (INPUTS, settings.fls.input_attenuation),
+                    (OUTPUTS, settings.fls.output_attenuation),
+                ] {
+                    for (channel, attenuation) in
+                        channels.into_iter().zip(attenuations)
+                    {
+                        if let Err(err) =
+                            pounder.set_attenuation(channel, attenuation)
+                        {
+                            log::error!(
+                                "Failed to set {:?} attenuation to {}: {:?}",
+                                channel,
+                                attenuation,
+                                err
+                            );
+                        }
+                    }
+                }
+            });
+
+            c.shared
+                .network
+                .lock(|net| net.direct_stream(settings.fls.stream_target));
+
+            (&mut c.shared.active_settings, &mut c.shared.config).lock(
+                |current, current_config| {
+                    *current = settings.fls.clone();
+                    *current_config = config;
+                },
+            );
+        });
+    }
+
+    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry, trackers, pounder])]
+    async fn telemetry(mut c: telemetry::Context) {
+        loop {
+            let mut telemetry: TelemetryBuffer =
+                c.shared.telemetry.lock(core::mem::take);
+
+            telemetry.digital_inputs = [
+                c.local.digital_inputs.0.is_high(),
+                c.local.digital_inputs.1.is_high(),
+            ];
+
+            let (gains, telemetry_period) =
+                c.shared.settings.lock(|settings| {
+                    (settings.fls.afe, settings.fls.telemetry_period)
+                });
+
+            let (pounder_temp, input_power) =
+                c.shared.pounder.lock(|pounder| {
+                    (
+                        pounder.temperature().unwrap_or(f32::NAN),
+                        INPUTS.map(|channel| {
+                            pounder.measure_power(channel).unwrap_or(f32::NAN)
+                        }),
+                    )
+                });
+
+            let channels = c.shared.trackers.lock(|trackers| {
+                [
+                    trackers[0].telemetry(gains[0], input_power[0]),
+                    trackers[1].telemetry(gains[1], input_power[1]),
+                ]
+            });
+
+            c.shared.network.lock(|net| {
+                net.telemetry.publish(&Telemetry::new(
+                    telemetry.finalize(
+                        gains[0],
+                        gains[1],
+                        c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                    ),
+                    pounder_temp,
+                    channels,
+                ))
+            });
+
+            // Schedule the telemetry task in the future.
+            Systick::delay((telemetry_period as u32).secs()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[usb, settings], local=[usb_terminal])]
+    async fn usb(mut c: usb::Context) {
+        loop {
+            // Handle the USB serial terminal.
+            c.shared.usb.lock(|usb| {
+                usb.poll(&mut [c
+                    .local
+                    .usb_terminal
+                    .interface_mut()
+                    .inner_mut()]);
+            });
+
+            c.shared.settings.lock(|settings| {
+                if c.local.usb_terminal.poll(settings).unwrap() {
+                    settings_update::spawn().unwrap()
+                }
+            });
+
+            Systick::delay(10.millis()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[network])]
+    async fn ethernet_link(mut c: ethernet_link::Context) {
+        loop {
+            c.shared.network.lock(|net| net.processor.handle_link());
+            Systick::delay(1.secs()).await;
+        }
+    }
+
+    #[task(binds = ETH, priority = 1)]
+    fn eth(_: eth::Context) {
+        unsafe { hal::ethernet::interrupt_handler() }
+    }
+}
@@ -9,9 +9,14 @@
 //! * Supports internal and external reference sources:
 //!     1. Internal: Generate reference internally and output on one of the channel outputs
 //!     2. External: Reciprocal PLL, reference input applied to DI0.
+//! * External reference presence and PLL lock detection with an optional lock indicator on the
+//!   EEM LVDS6 output
 //! * Adjustable PLL and locking time constants
-//! * Adjustable phase offset and harmonic index
+//! * Two demodulators (ADC0 and ADC1) against the same reference
+//! * Adjustable phase offset, harmonic index and lowpass per demodulator
 //! * Run-time configurable output modes (in-phase, quadrature, magnitude, log2 power, phase, frequency)
+//! * Optional output IIR filters and modulation summing for modulation-based locks (e.g.
+//!   Pound-Drever-Hall)
 //! * Input/output data streamng via UDP
 //!
 //! ## Settings
@@ -22,13 +27,14 @@
 //! Refer to [Telemetry] for information about telemetry reported by this application.
 //!
 //! ## Livestreaming
-//! This application streams raw ADC and DAC data over UDP. Refer to
+//! This application streams either raw ADC and DAC data in the [StreamFormat::AdcDacData] format
+//! (default) or the demodulated data of each batch in the [StreamFormat::Lockin] format over UDP,
+//! selected by the `stream_data` setting. Refer to
 //! [stabilizer::net::data_stream](../stabilizer/net/data_stream/index.html) for more information.
 #![no_std]
 #![no_main]
 
 use core::{
-    convert::TryFrom,
     mem::MaybeUninit,
     sync::atomic::{fence, Ordering},
 };
@@ -38,49 +44,45 @@ use rtic_monotonics::Monotonic;
 use fugit::ExtU32;
 use mutex_trait::prelude::*;
 
-use idsp::{Accu, Complex, ComplexExt, Filter, Lowpass, Repeat, RPLL};
+use idsp::{iir, Accu, Complex, ComplexExt, Filter, Lowpass, Repeat, RPLL};
 
 use stabilizer::{
     hardware::{
         self,
-        adc::{Adc0Input, Adc1Input, AdcCode},
+        adc::{Adc0Input, Adc1Input},
         afe::Gain,
         dac::{Dac0Output, Dac1Output, DacCode},
         hal,
         input_stamper::InputStamper,
         signal_generator,
         timers::SamplingTimer,
-        DigitalInput0, DigitalInput1, SerialTerminal, SystemTimer, Systick,
-        UsbDevice, AFE0, AFE1,
+        DigitalInput0, DigitalInput1, EemDigitalOutput0, SerialTerminal,
+        SystemTimer, Systick, UsbDevice, AFE0, AFE1,
     },
     net::{
         data_stream::{FrameGenerator, StreamFormat, StreamTarget},
         miniconf::Tree,
         serde::{Deserialize, Serialize},
-        telemetry::TelemetryBuffer,
+        telemetry::{Events, Statistics, TelemetryBuffer},
         NetworkState, NetworkUsers,
     },
-    settings::NetSettings,
+    settings::{NetSettings, SamplingSettings},
 };
 
-// The logarithm of the number of samples in each batch process. This corresponds with 2^3 samples
-// per batch = 8 samples
-const BATCH_SIZE_LOG2: u32 = 3;
-const BATCH_SIZE: usize = 1 << BATCH_SIZE_LOG2;
-
-// The logarithm of the number of 100MHz timer ticks between each sample. This corresponds with a
-// sampling period of 2^7 = 128 ticks. At 100MHz, 10ns per tick, this corresponds to a sampling
-// period of 1.28 uS or 781.25 KHz.
-const SAMPLE_TICKS_LOG2: u32 = 7;
-const SAMPLE_TICKS: u32 = 1 << SAMPLE_TICKS_LOG2;
+// The number of cascaded output IIR biquads per DAC output. The number of sections that are
+// processed is configured at run-time through `Lockin::output_iir_sections`.
+const OUTPUT_IIR_LENGTH: usize = 2;
 
 #[derive(Clone, Debug, Tree)]
 pub struct Settings {
-    #[tree(depth = 2)]
+    #[tree(depth = 3)]
     pub lockin: Lockin,
 
     #[tree(depth = 1)]
     pub net: NetSettings,
+
+    #[tree(depth = 1)]
+    pub sampling: SamplingSettings,
 }
 
 impl stabilizer::settings::AppSettings for Settings {
@@ -88,19 +90,25 @@ impl stabilizer::settings::AppSettings for Settings {
         Self {
             net,
             lockin: Lockin::default(),
+            sampling: SamplingSettings::default(),
         }
     }
 
     fn net(&self) -> &NetSettings {
         &self.net
     }
+
+    fn sampling(&self) -> &SamplingSettings {
+        &self.sampling
+    }
 }
 
-impl serial_settings::Settings<3> for Settings {
+impl serial_settings::Settings<4> for Settings {
     fn reset(&mut self) {
         *self = Self {
             lockin: Lockin::default(),
             net: NetSettings::new(self.net.mac),
+            sampling: SamplingSettings::default(),
         }
     }
 }
@@ -123,6 +131,14 @@ enum Conf {
     Modulation,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum Demodulator {
+    /// The demodulator of ADC0
+    Adc0,
+    /// The demodulator of ADC1
+    Adc1,
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 enum LockinMode {
     /// Utilize an internally generated reference for demodulation
@@ -131,6 +147,14 @@ enum LockinMode {
     External,
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+enum StreamData {
+    /// Stream the demodulated data in the [StreamFormat::Lockin] format
+    Demodulated,
+    /// Stream the raw ADC and DAC samples in the [StreamFormat::AdcDacData] format
+    AdcDac,
+}
+
 #[derive(Clone, Debug, Tree)]
 pub struct Lockin {
     /// Configure the Analog Front End (AFE) gain.
@@ -162,36 +186,90 @@ pub struct Lockin {
     /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
     ///
     /// # Value
-    /// The PLL time constant exponent (1-31).
+    /// The PLL time constant exponent (1-31). Replaced by the realized value if `pll_bandwidth/<n>`
+    /// is not `null`.
     pll_tc: [u32; 2],
 
+    /// Specifies the PLL bandwidth.
+    ///
+    /// # Path
+    /// `pll_bandwidth/<n>`
+    ///
+    /// * `<n>` specifies which loop to configure. `<n>` := [0, 1] (frequency, phase)
+    ///
+    /// # Value
+    /// `null` or the loop bandwidth in Hertz. When not `null`, the bandwidth is quantized to the
+    /// closest time constant exponent and replaces `pll_tc/<n>` on each settings update.
+    /// Bandwidths outside of the exponent range are logged and leave `pll_tc/<n>` unchanged.
+    #[tree(depth = 1)]
+    pll_bandwidth: [Option<f32>; 2],
+
     /// Specifies the lockin lowpass gains.
     ///
     /// # Path
-    /// `lockin_k`
+    /// `lockin_k/<n>`
+    ///
+    /// * `<n>` specifies which demodulator to configure. `<n>` := [0, 1] (ADC0, ADC1)
+    ///
+    /// # Value
+    /// The lockin low-pass coefficients. See [`idsp::Lowpass`] for determining them. First order
+    /// filters only use the first coefficient. Replaced by the realized gains if
+    /// `lockin_tc/<n>` is not `null`.
+    #[tree(depth = 1)]
+    lockin_k: [<Lowpass<2> as Filter>::Config; 2],
+
+    /// Specifies the lockin lowpass time constant.
+    ///
+    /// # Path
+    /// `lockin_tc/<n>`
+    ///
+    /// * `<n>` specifies which demodulator to configure. `<n>` := [0, 1] (ADC0, ADC1)
+    ///
+    /// # Value
+    /// `null` or the time constant of each filter section in seconds, i.e. the inverse corner
+    /// angular frequency. Second order sections have a quality factor of one. When not `null`,
+    /// the gains are computed and replace `lockin_k/<n>` on each settings update. Time
+    /// constants shorter than two sample periods are logged and leave `lockin_k/<n>` unchanged.
+    #[tree(depth = 1)]
+    lockin_tc: [Option<f32>; 2],
+
+    /// Specifies the lockin lowpass order.
+    ///
+    /// # Path
+    /// `lockin_order/<n>`
+    ///
+    /// * `<n>` specifies which demodulator to configure. `<n>` := [0, 1] (ADC0, ADC1)
     ///
     /// # Value
-    /// The lockin low-pass coefficients. See [`idsp::Lowpass`] for determining them.
-    lockin_k: <Lowpass<2> as Filter>::Config,
+    /// 1 (first order), 2 (second order) or 4 (two second order sections). Other values select
+    /// the fourth order. The filter state is cleared when the order changes.
+    #[tree(depth = 1)]
+    lockin_order: [usize; 2],
 
     /// Specifies which harmonic to use for the lockin.
     ///
     /// # Path
-    /// `lockin_harmonic`
+    /// `lockin_harmonic/<n>`
+    ///
+    /// * `<n>` specifies which demodulator to configure. `<n>` := [0, 1] (ADC0, ADC1)
     ///
     /// # Value
     /// Harmonic index of the LO. -1 to _de_modulate the fundamental (complex conjugate)
-    lockin_harmonic: i32,
+    #[tree(depth = 1)]
+    lockin_harmonic: [i32; 2],
 
     /// Specifies the LO phase offset.
     ///
     /// # Path
-    /// `lockin_phase`
+    /// `lockin_phase/<n>`
+    ///
+    /// * `<n>` specifies which demodulator to configure. `<n>` := [0, 1] (ADC0, ADC1)
     ///
     /// # Value
     /// Demodulation LO phase offset. Units are in terms of i32, where [i32::MIN] is equivalent to
     /// -pi and [i32::MAX] is equivalent to +pi.
-    lockin_phase: i32,
+    #[tree(depth = 1)]
+    lockin_phase: [i32; 2],
 
     /// Specifies DAC output mode.
     ///
@@ -205,6 +283,103 @@ pub struct Lockin {
     #[tree(depth = 1)]
     output_conf: [Conf; 2],
 
+    /// Specifies the demodulator of the DAC output signal.
+    ///
+    /// # Path
+    /// `output_demodulator/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// One of the variants of [Demodulator] enclosed in double quotes. The demodulator output
+    /// selected by `output_conf/<n>` is taken from this demodulator.
+    #[tree(depth = 1)]
+    output_demodulator: [Demodulator; 2],
+
+    /// Configure the output IIR filters.
+    ///
+    /// # Path
+    /// `output_iir/<n>/<m>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    /// * `<m>` specifies which cascade to configure. `<m>` := [0, 1], see [OUTPUT_IIR_LENGTH]
+    ///
+    /// # Value
+    /// See [iir::Biquad]. The filters operate on the signal selected by `output_conf/<n>` in DAC
+    /// codes at the sample rate, e.g. as a PID controller acting on the demodulated quadrature.
+    #[tree(depth = 2)]
+    output_iir: [[iir::Biquad<f32>; OUTPUT_IIR_LENGTH]; 2],
+
+    /// Configure the number of active output IIR sections.
+    ///
+    /// # Path
+    /// `output_iir_sections/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The number of leading sections of `output_iir/<n>` that are processed, between 0 and
+    /// [OUTPUT_IIR_LENGTH]. With no active sections the signal is output unfiltered. Inactive
+    /// sections are skipped and their state is cleared.
+    #[tree(depth = 1)]
+    output_iir_sections: [usize; 2],
+
+    /// Add the internal modulation to the DAC output.
+    ///
+    /// # Path
+    /// `output_modulation/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// "true" or "false". When enabled, the internal modulation signal is added to the (filtered)
+    /// output signal, e.g. to apply feedback and modulation to the same actuator.
+    #[tree(depth = 1)]
+    output_modulation: [bool; 2],
+
+    /// Specifies the internal modulation.
+    ///
+    /// # Path
+    /// `modulation`
+    ///
+    /// # Value
+    /// See [signal_generator::BasicConfig#miniconf]. In [LockinMode::Internal], the modulation
+    /// frequency (quantized to the phase accumulator) is the demodulation reference. The modulation
+    /// is output through [Conf::Modulation] or `output_modulation`. If the modulation is not valid
+    /// for the sampling configuration applied at boot, it is reset to a 1 V cosine at an eighth of
+    /// the sample rate.
+    #[tree(depth = 1)]
+    modulation: signal_generator::BasicConfig,
+
+    /// Specifies the external reference timeout.
+    ///
+    /// # Path
+    /// `reference_timeout`
+    ///
+    /// # Value
+    /// The number of batches without a reference timestamp after which the external reference
+    /// is reported as absent.
+    reference_timeout: u32,
+
+    /// Specifies the PLL lock threshold.
+    ///
+    /// # Path
+    /// `lock_threshold`
+    ///
+    /// # Value
+    /// The RMS phase error in turns below which the PLL is reported as locked to a present
+    /// external reference.
+    lock_threshold: f32,
+
+    /// Assert the EEM LVDS6 output while the PLL is locked.
+    ///
+    /// # Path
+    /// `lock_output`
+    ///
+    /// # Value
+    /// "true" or "false"
+    lock_output: bool,
+
     /// Specifies the telemetry output period in seconds.
     ///
     /// # Path
@@ -222,26 +397,344 @@ pub struct Lockin {
     /// # Value
     /// See [StreamTarget#miniconf]
     stream_target: StreamTarget,
+
+    /// Specifies the streamed data.
+    ///
+    /// # Path
+    /// `stream_data`
+    ///
+    /// # Value
+    /// Any of the variants of [StreamData] enclosed in double quotes. Defaults to "AdcDac".
+    stream_data: StreamData,
+}
+
+impl Lockin {
+    /// The default modulation: a 1 V cosine at an eighth of the sample rate.
+    ///
+    /// # Args
+    /// * `sample_period` - The sample period in seconds.
+    fn default_modulation(sample_period: f32) -> signal_generator::BasicConfig {
+        signal_generator::BasicConfig {
+            frequency: 0.125 / sample_period,
+            amplitude: 1.0,
+            ..Default::default()
+        }
+    }
 }
 
 impl Default for Lockin {
     fn default() -> Self {
+        let mut i = iir::Biquad::IDENTITY;
+        i.set_min(i16::MIN as _);
+        i.set_max(i16::MAX as _);
         Self {
             afe: [Gain::G1; 2],
 
             lockin_mode: LockinMode::External,
 
             pll_tc: [21, 21], // frequency and phase settling time (log2 counter cycles)
+            pll_bandwidth: [None; 2],
 
-            lockin_k: [0x8_0000, -0x400_0000], // lockin lowpass gains
-            lockin_harmonic: -1, // Harmonic index of the LO: -1 to _de_modulate the fundamental (complex conjugate)
-            lockin_phase: 0,     // Demodulation LO phase offset
+            lockin_k: [[0x8_0000, -0x400_0000]; 2], // lockin lowpass gains
+            lockin_tc: [None; 2],
+            lockin_order: [4; 2],
+            lockin_harmonic: [-1; 2], // Harmonic index of the LO: -1 to _de_modulate the fundamental (complex conjugate)
+            lockin_phase: [0; 2],     // Demodulation LO phase offset
 
             output_conf: [Conf::InPhase, Conf::Quadrature],
+            output_demodulator: [Demodulator::Adc0; 2],
+            // The outputs are unfiltered and unmodulated.
+            output_iir: [[i; OUTPUT_IIR_LENGTH]; 2],
+            output_iir_sections: [0; 2],
+            output_modulation: [false; 2],
+            modulation: Self::default_modulation(
+                SamplingSettings::default().sample_period(),
+            ),
+            // About 10 ms at the default sample rate.
+            reference_timeout: 1 << 10,
+            lock_threshold: 0.01,
+            lock_output: false,
             // The default telemetry period in seconds.
             telemetry_period: 10,
 
             stream_target: StreamTarget::default(),
+            stream_data: StreamData::AdcDac,
+        }
+    }
+}
+
+/// A lockin with a run-time selectable lowpass order.
+pub enum LowpassLockin {
+    /// A first order lowpass.
+    First(idsp::Lockin<Lowpass<1>>),
+    /// A second order lowpass.
+    Second(idsp::Lockin<Lowpass<2>>),
+    /// Two second order lowpass sections.
+    Fourth(idsp::Lockin<Repeat<2, Lowpass<2>>>),
+}
+
+impl Default for LowpassLockin {
+    fn default() -> Self {
+        Self::Fourth(Default::default())
+    }
+}
+
+impl LowpassLockin {
+    /// Select the lowpass order. The state is cleared when the order changes.
+    ///
+    /// # Args
+    /// * `order` - The lowpass order: 1, 2 or 4. Other values select the fourth order.
+    pub fn set_order(&mut self, order: usize) {
+        let current = match self {
+            Self::First(_) => 1,
+            Self::Second(_) => 2,
+            Self::Fourth(_) => 4,
+        };
+        *self = match order {
+            _ if order == current => return,
+            1 => Self::First(Default::default()),
+            2 => Self::Second(Default::default()),
+            _ if current == 4 => return,
+            _ => Self::Fourth(Default::default()),
+        };
+    }
+
+    /// Demodulate and filter a sample.
+    ///
+    /// # Args
+    /// * `x` - The input sample.
+    /// * `phase` - The LO phase.
+    /// * `k` - The lowpass gains.
+    #[inline]
+    pub fn update(&mut self, x: i32, phase: i32, k: &[i32; 2]) -> Complex<i32> {
+        match self {
+            Self::First(lockin) => lockin.update(x, phase, &[k[0]]),
+            Self::Second(lockin) => lockin.update(x, phase, k),
+            Self::Fourth(lockin) => lockin.update(x, phase, k),
+        }
+    }
+}
+
+/// Compute the lockin lowpass gains.
+///
+/// # Args
+/// * `time_constant` - The time constant of each filter section in seconds.
+/// * `order` - The lowpass order, see [LowpassLockin::set_order].
+/// * `sample_period` - The time in seconds between samples.
+///
+/// # Returns
+/// The gains or `None` if the time constant is shorter than two sample periods.
+fn lowpass_gains(
+    time_constant: f32,
+    order: usize,
+    sample_period: f32,
+) -> Option<[i32; 2]> {
+    // The corner angular frequency in units of the sample rate.
+    let k = sample_period / time_constant;
+    if !(k > 0. && k <= 0.5) {
+        return None;
+    }
+    // Note(as): The conversions saturate.
+    Some(if order == 1 {
+        [(k * (1u64 << 32) as f32) as i32, 0]
+    } else {
+        [
+            (k * k * (1u64 << 31) as f32) as i32,
+            (-k * (1u64 << 32) as f32) as i32,
+        ]
+    })
+}
+
+/// Compute the PLL time constant exponent.
+///
+/// # Args
+/// * `bandwidth` - The loop bandwidth in Hertz.
+/// * `tick_period` - The time in seconds between timestamp counter ticks.
+///
+/// # Returns
+/// The exponent or `None` if it is outside of 1-31.
+fn pll_shift(bandwidth: f32, tick_period: f32) -> Option<u32> {
+    let shift = libm::roundf(libm::log2f(
+        1. / (2. * core::f32::consts::PI * bandwidth * tick_period),
+    ));
+    (1. ..=31.).contains(&shift).then_some(shift as u32)
+}
+
+/// The weight of each phase error in the moving average of the squared phase error.
+const PHASE_ERROR_WEIGHT: f32 = 1. / 64.;
+
+/// The variance of a uniformly distributed phase error in turns², i.e. of an unlocked PLL.
+const UNLOCKED_VARIANCE: f32 = 1. / 12.;
+
+/// Run-time external reference detection and PLL lock monitor.
+///
+/// The phase error of each reference timestamp is the phase the PLL frequency accumulates since
+/// the previous timestamp. It vanishes (modulo full turns) when the PLL is locked.
+#[derive(Copy, Clone, Debug)]
+pub struct ReferenceMonitor {
+    // The number of batches since the most recent timestamp.
+    age: u32,
+    // The most recent timestamp.
+    last: Option<i32>,
+    // The moving average of the squared phase error in turns².
+    variance: f32,
+    // The PLL frequency in phase increment per batch.
+    frequency: i32,
+    // The number of timestamp capture overflows.
+    overflows: u32,
+}
+
+impl Default for ReferenceMonitor {
+    fn default() -> Self {
+        Self {
+            age: u32::MAX,
+            last: None,
+            variance: UNLOCKED_VARIANCE,
+            frequency: 0,
+            overflows: 0,
+        }
+    }
+}
+
+/// External reference status.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct ReferenceStatus {
+    /// Whether reference timestamps were captured within the reference timeout.
+    present: bool,
+
+    /// Whether the reference is present and the RMS phase error is below the lock threshold.
+    locked: bool,
+
+    /// The estimated reference frequency in Hertz.
+    frequency: f32,
+
+    /// The RMS phase error in turns.
+    phase_error: f32,
+
+    /// The number of timestamp capture overflows since startup.
+    overflows: u32,
+}
+
+impl ReferenceMonitor {
+    /// Record a timestamp capture overflow.
+    pub fn overflow(&mut self) {
+        self.overflows = self.overflows.wrapping_add(1);
+    }
+
+    /// Update the monitor once per batch.
+    ///
+    /// # Args
+    /// * `timestamp` - The reference timestamp captured during the batch, if any.
+    /// * `frequency` - The PLL frequency in phase increment per batch.
+    /// * `batch_ticks_log2` - The log2 of the number of timestamp counter ticks per batch.
+    /// * `timeout` - The reference timeout in batches.
+    pub fn update(
+        &mut self,
+        timestamp: Option<i32>,
+        frequency: i32,
+        batch_ticks_log2: u32,
+        timeout: u32,
+    ) {
+        match timestamp {
+            Some(x) => {
+                if let (Some(last), true) = (self.last, self.age < timeout) {
+                    // The phase accumulated by the previous PLL frequency since the last
+                    // timestamp, wrapped to a turn.
+                    let error =
+                        ((x.wrapping_sub(last) as i64 * self.frequency as i64)
+                            >> batch_ticks_log2) as i32;
+                    let error = error as f32 * (1. / (1u64 << 32) as f32);
+                    self.variance +=
+                        (error * error - self.variance) * PHASE_ERROR_WEIGHT;
+                }
+                self.last = Some(x);
+                self.age = 0;
+            }
+            None => {
+                self.age = self.age.saturating_add(1);
+                if self.age >= timeout {
+                    // Start from an unlocked state when the reference reappears.
+                    self.variance = UNLOCKED_VARIANCE;
+                }
+            }
+        }
+        self.frequency = frequency;
+    }
+
+    /// Whether reference timestamps were captured within the timeout.
+    pub fn present(&self, timeout: u32) -> bool {
+        self.age < timeout
+    }
+
+    /// Whether the reference is present and the PLL is locked to it.
+    ///
+    /// # Args
+    /// * `timeout` - The reference timeout in batches.
+    /// * `threshold` - The RMS phase error lock threshold in turns.
+    pub fn locked(&self, timeout: u32, threshold: f32) -> bool {
+        self.present(timeout) && self.variance < threshold * threshold
+    }
+
+    /// The reference status.
+    ///
+    /// # Args
+    /// * `timeout` - The reference timeout in batches.
+    /// * `threshold` - The RMS phase error lock threshold in turns.
+    /// * `batch_period` - The time in seconds between batches.
+    pub fn status(
+        &self,
+        timeout: u32,
+        threshold: f32,
+        batch_period: f32,
+    ) -> ReferenceStatus {
+        ReferenceStatus {
+            present: self.present(timeout),
+            locked: self.locked(timeout, threshold),
+            frequency: self.frequency as u32 as f32
+                / ((1u64 << 32) as f32 * batch_period),
+            phase_error: libm::sqrtf(self.variance),
+            overflows: self.overflows,
+        }
+    }
+}
+
+/// Telemetry reported by the lockin application.
+///
+/// This extends the common [stabilizer::net::telemetry::Telemetry] by application-specific
+/// fields.
+#[derive(Serialize)]
+pub struct Telemetry {
+    /// Input voltage statistics over the telemetry period.
+    adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    dacs: [Statistics; 2],
+
+    /// Event counters of each channel over the telemetry period.
+    events: [Events; 2],
+
+    /// Most recent digital input assertion state.
+    digital_inputs: [bool; 2],
+
+    /// The CPU temperature in degrees Celsius.
+    cpu_temp: f32,
+
+    /// The external reference status. Only meaningful in [LockinMode::External].
+    reference: ReferenceStatus,
+}
+
+impl Telemetry {
+    fn new(
+        telemetry: stabilizer::net::telemetry::Telemetry,
+        reference: ReferenceStatus,
+    ) -> Self {
+        Self {
+            adcs: telemetry.adcs,
+            dacs: telemetry.dacs,
+            events: telemetry.events,
+            digital_inputs: telemetry.digital_inputs,
+            cpu_temp: telemetry.cpu_temp,
+            reference,
         }
     }
 }
@@ -253,24 +746,28 @@ mod app {
     #[shared]
     struct Shared {
         usb: UsbDevice,
-        network: NetworkUsers<Lockin, 2>,
+        network: NetworkUsers<Lockin, 3>,
         settings: Settings,
         active_settings: Lockin,
         telemetry: TelemetryBuffer,
+        signal_generator: signal_generator::SignalGenerator,
+        sampling: SamplingSettings,
+        reference: ReferenceMonitor,
     }
 
     #[local]
     struct Local {
-        usb_terminal: SerialTerminal<Settings, 3>,
+        usb_terminal: SerialTerminal<Settings, 4>,
         sampling_timer: SamplingTimer,
         digital_inputs: (DigitalInput0, DigitalInput1),
         timestamper: InputStamper,
+        lock_output: EemDigitalOutput0,
         afes: (AFE0, AFE1),
         adcs: (Adc0Input, Adc1Input),
         dacs: (Dac0Output, Dac1Output),
         pll: RPLL,
-        lockin: idsp::Lockin<Repeat<2, Lowpass<2>>>,
-        signal_generator: signal_generator::SignalGenerator,
+        lockin: [LowpassLockin; 2],
+        output_iir_state: [[[f32; 4]; OUTPUT_IIR_LENGTH]; 2],
         generator: FrameGenerator,
         cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
     }
@@ -280,13 +777,9 @@ mod app {
         let clock = SystemTimer::new(|| Systick::now().ticks());
 
         // Configure the microcontroller
-        let mut stabilizer = hardware::setup::setup::<Settings, 3>(
-            c.core,
-            c.device,
-            clock,
-            BATCH_SIZE,
-            SAMPLE_TICKS,
-        );
+        let mut stabilizer =
+            hardware::setup::setup::<Settings, 4>(c.core, c.device, clock);
+        let sampling = stabilizer.sampling;
 
         let mut network = NetworkUsers::new(
             stabilizer.net.stack,
@@ -299,23 +792,32 @@ mod app {
 
         let generator = network.configure_streaming(StreamFormat::AdcDacData);
 
+        // The stored modulation may not be valid for the applied sampling configuration.
+        let sample_period = sampling.sample_period();
+        let modulation = &mut stabilizer.settings.lockin.modulation;
+        let modulation_config = modulation
+            .try_into_config(sample_period, DacCode::FULL_SCALE)
+            .unwrap_or_else(|err| {
+                log::error!("Invalid modulation: {:?}. Using defaults.", err);
+                *modulation = Lockin::default_modulation(sample_period);
+                modulation
+                    .try_into_config(sample_period, DacCode::FULL_SCALE)
+                    .unwrap_or_default()
+            });
+
         let shared = Shared {
             network,
             usb: stabilizer.usb,
             telemetry: TelemetryBuffer::default(),
+            signal_generator: signal_generator::SignalGenerator::new(
+                modulation_config,
+            ),
+            sampling,
+            reference: ReferenceMonitor::default(),
             active_settings: stabilizer.settings.lockin.clone(),
             settings: stabilizer.settings,
         };
 
-        let signal_config = signal_generator::Config {
-            // Same frequency as batch size.
-            phase_increment: [1 << (32 - BATCH_SIZE_LOG2); 2],
-            // 1V Amplitude
-            amplitude: DacCode::try_from(1.0).unwrap().into(),
-            signal: signal_generator::Signal::Cosine,
-            phase_offset: 0,
-        };
-
         let mut local = Local {
             usb_terminal: stabilizer.usb_serial,
             sampling_timer: stabilizer.adc_dac_timer,
@@ -324,12 +826,13 @@ mod app {
             adcs: stabilizer.adcs,
             dacs: stabilizer.dacs,
             timestamper: stabilizer.timestamper,
+            lock_output: stabilizer.eem_gpio.lvds6,
 
-            pll: RPLL::new(SAMPLE_TICKS_LOG2 + BATCH_SIZE_LOG2),
-            lockin: idsp::Lockin::default(),
-            signal_generator: signal_generator::SignalGenerator::new(
-                signal_config,
+            pll: RPLL::new(
+                (sampling.sample_ticks_log2 + sampling.batch_size_log2) as _,
             ),
+            lockin: Default::default(),
+            output_iir_state: [[[0.; 4]; OUTPUT_IIR_LENGTH]; 2],
 
             generator,
             cpu_temp_sensor: stabilizer.temperature_sensor,
@@ -368,128 +871,275 @@ mod app {
     ///
     /// See `dual-iir` for general notes on processing time and timing.
     ///
-    /// This is an implementation of a externally (DI0) referenced PLL lockin on the ADC0 signal.
-    /// It outputs either I/Q or power/phase on DAC0/DAC1. Data is normalized to full scale.
+    /// This is an implementation of a externally (DI0) referenced PLL lockin on the ADC0 and ADC1
+    /// signals. It outputs either I/Q or power/phase of either demodulator on DAC0/DAC1. Data is
+    /// normalized to full scale.
     /// PLL bandwidth, filter bandwidth, slope, and x/y or power/phase post-filters are available.
-    #[task(binds=DMA1_STR4, shared=[active_settings, telemetry], local=[adcs, dacs, lockin, timestamper, pll, generator, signal_generator], priority=3)]
+    #[task(binds=DMA1_STR4, shared=[active_settings, telemetry, signal_generator, sampling, reference], local=[adcs, dacs, lockin, output_iir_state, timestamper, lock_output, pll, generator], priority=3)]
     #[link_section = ".itcm.process"]
     fn process(c: process::Context) {
         let process::SharedResources {
             active_settings,
             telemetry,
+            signal_generator,
+            sampling,
+            reference,
             ..
         } = c.shared;
 
         let process::LocalResources {
             timestamper,
+            lock_output,
             adcs: (adc0, adc1),
             dacs: (dac0, dac1),
             pll,
             lockin,
-            signal_generator,
+            output_iir_state,
             generator,
             ..
         } = c.local;
 
-        (active_settings, telemetry).lock(|settings, telemetry| {
-            let (reference_phase, reference_frequency) =
-                match settings.lockin_mode {
-                    LockinMode::External => {
-                        let timestamp =
-                            timestamper.latest_timestamp().unwrap_or(None); // Ignore data from timer capture overflows.
-                        let (pll_phase, pll_frequency) = pll.update(
-                            timestamp.map(|t| t as i32),
-                            settings.pll_tc[0],
-                            settings.pll_tc[1],
-                        );
-                        (pll_phase, (pll_frequency >> BATCH_SIZE_LOG2) as i32)
-                    }
-                    LockinMode::Internal => {
-                        // Reference phase and frequency are known.
-                        (1i32 << 30, 1i32 << (32 - BATCH_SIZE_LOG2))
-                    }
-                };
-
-            let sample_frequency =
-                reference_frequency.wrapping_mul(settings.lockin_harmonic);
-            let sample_phase = settings.lockin_phase.wrapping_add(
-                reference_phase.wrapping_mul(settings.lockin_harmonic),
-            );
-
-            (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
-                let adc_samples = [adc0, adc1];
-                let mut dac_samples = [dac0, dac1];
-
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-
-                let output: Complex<i32> = adc_samples[0]
-                    .iter()
-                    // Zip in the LO phase.
-                    .zip(Accu::new(sample_phase, sample_frequency))
-                    // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
-                    .map(|(&sample, phase)| {
-                        let s = (sample as i16 as i32) << 16;
-                        lockin.update(s, phase, &settings.lockin_k)
-                    })
-                    // Decimate
-                    .last()
-                    .unwrap()
-                    * 2; // Full scale assuming the 2f component is gone.
-
-                // Convert to DAC data.
-                for (channel, samples) in dac_samples.iter_mut().enumerate() {
-                    for sample in samples.iter_mut() {
-                        let value = match settings.output_conf[channel] {
-                            Conf::Magnitude => output.abs_sqr() as i32 >> 16,
-                            Conf::Phase => output.arg() >> 16,
-                            Conf::LogPower => output.log2() << 8,
-                            Conf::ReferenceFrequency => {
-                                reference_frequency >> 16
-                            }
-                            Conf::InPhase => output.re >> 16,
-                            Conf::Quadrature => output.im >> 16,
-
-                            Conf::Modulation => {
-                                signal_generator.next().unwrap() as i32
-                            }
-                        };
-
-                        *sample = DacCode::from(value as i16).0;
-                    }
-                }
-
-                // Stream the data.
-                const N: usize = BATCH_SIZE * core::mem::size_of::<i16>()
-                    / core::mem::size_of::<MaybeUninit<u8>>();
-                generator.add(|buf| {
-                    for (data, buf) in adc_samples
-                        .iter()
-                        .chain(dac_samples.iter())
-                        .zip(buf.chunks_exact_mut(N))
+        (
+            active_settings,
+            telemetry,
+            signal_generator,
+            sampling,
+            reference,
+        )
+            .lock(
+                |settings, telemetry, signal_generator, sampling, reference| {
+                    let batch_size_log2 = sampling.batch_size_log2 as u32;
+                    let (reference_phase, reference_frequency) = match settings
+                        .lockin_mode
                     {
-                        let data = unsafe {
-                            core::slice::from_raw_parts(
-                                data.as_ptr() as *const MaybeUninit<u8>,
-                                N,
+                        LockinMode::External => {
+                            let timestamp = timestamper
+                                .latest_timestamp()
+                                .unwrap_or_else(|_| {
+                                    // Ignore data from timer capture overflows.
+                                    reference.overflow();
+                                    None
+                                })
+                                .map(|t| t as i32);
+                            let (pll_phase, pll_frequency) = pll.update(
+                                timestamp,
+                                settings.pll_tc[0],
+                                settings.pll_tc[1],
+                            );
+                            reference.update(
+                                timestamp,
+                                pll_frequency,
+                                sampling.sample_ticks_log2 as u32
+                                    + batch_size_log2,
+                                settings.reference_timeout,
+                            );
+                            (
+                                pll_phase,
+                                (pll_frequency >> batch_size_log2) as i32,
+                            )
+                        }
+                        LockinMode::Internal => {
+                            // No external reference is captured.
+                            reference.update(
+                                None,
+                                0,
+                                0,
+                                settings.reference_timeout,
+                            );
+
+                            // Reference phase and frequency are those of the modulation.
+                            (
+                                signal_generator.phase().wrapping_add(1 << 30),
+                                signal_generator.frequency(),
                             )
-                        };
-                        buf.copy_from_slice(data)
+                        }
+                    };
+
+                    if settings.lock_output
+                        && reference.locked(
+                            settings.reference_timeout,
+                            settings.lock_threshold,
+                        )
+                    {
+                        lock_output.set_high();
+                    } else {
+                        lock_output.set_low();
                     }
-                    N * 4
-                });
 
-                // Update telemetry measurements.
-                telemetry.adcs =
-                    [AdcCode(adc_samples[0][0]), AdcCode(adc_samples[1][0])];
+                    (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                        let adc_samples = [adc0, adc1];
+                        let mut dac_samples = [dac0, dac1];
+
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
+
+                        let outputs: [Complex<i32>; 2] =
+                            core::array::from_fn(|i| {
+                                let harmonic = settings.lockin_harmonic[i];
+                                let sample_frequency =
+                                    reference_frequency.wrapping_mul(harmonic);
+                                let sample_phase = settings.lockin_phase[i]
+                                    .wrapping_add(
+                                        reference_phase.wrapping_mul(harmonic),
+                                    );
+
+                                lockin[i].set_order(settings.lockin_order[i]);
+
+                                adc_samples[i]
+                                    .iter()
+                                    // Zip in the LO phase.
+                                    .zip(Accu::new(
+                                        sample_phase,
+                                        sample_frequency,
+                                    ))
+                                    // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
+                                    .map(|(&sample, phase)| {
+                                        let s = (sample as i16 as i32) << 16;
+                                        lockin[i].update(
+                                            s,
+                                            phase,
+                                            &settings.lockin_k[i],
+                                        )
+                                    })
+                                    // Decimate
+                                    .last()
+                                    .unwrap()
+                                    * 2 // Full scale assuming the 2f component is gone.
+                            });
+
+                        for (state, &active) in output_iir_state
+                            .iter_mut()
+                            .zip(settings.output_iir_sections.iter())
+                        {
+                            // Keep the state of inactive sections cleared so that they start from rest
+                            // when they are activated.
+                            for state in state.iter_mut().skip(active) {
+                                *state = [0.; 4];
+                            }
+                        }
+
+                        // Convert to DAC data.
+                        for sample in 0..dac_samples[0].len() {
+                            let modulation =
+                                signal_generator.next().unwrap() as i32;
+
+                            for (channel, samples) in
+                                dac_samples.iter_mut().enumerate()
+                            {
+                                let output = outputs[settings.output_demodulator
+                                    [channel]
+                                    as usize];
+                                let mut value =
+                                    match settings.output_conf[channel] {
+                                        Conf::Magnitude => {
+                                            output.abs_sqr() as i32 >> 16
+                                        }
+                                        Conf::Phase => output.arg() >> 16,
+                                        Conf::LogPower => output.log2() << 8,
+                                        Conf::ReferenceFrequency => {
+                                            reference_frequency >> 16
+                                        }
+                                        Conf::InPhase => output.re >> 16,
+                                        Conf::Quadrature => output.im >> 16,
+
+                                        Conf::Modulation => modulation,
+                                    };
+
+                                let active =
+                                    settings.output_iir_sections[channel];
+                                if active > 0 {
+                                    // Note(as): The conversion saturates.
+                                    value = settings.output_iir[channel]
+                                        .iter()
+                                        .zip(
+                                            output_iir_state[channel]
+                                                .iter_mut(),
+                                        )
+                                        .take(active)
+                                        .fold(
+                                            value as f32,
+                                            |x, (iir, state)| {
+                                                iir.update(state, x)
+                                            },
+                                        )
+                                        as i32;
+                                }
+
+                                if settings.output_modulation[channel] {
+                                    value += modulation;
+                                }
+
+                                samples[sample] = DacCode::from(
+                                    value.clamp(i16::MIN as _, i16::MAX as _)
+                                        as i16,
+                                )
+                                .0;
+                            }
+                        }
+
+                        // Stream the data.
+                        match settings.stream_data {
+                            StreamData::Demodulated => {
+                                let data = [
+                                    outputs[0].re,
+                                    outputs[0].im,
+                                    outputs[1].re,
+                                    outputs[1].im,
+                                    reference_frequency,
+                                    reference_phase,
+                                ];
+                                generator.set_format(StreamFormat::Lockin);
+                                generator.add(|buf| {
+                                    for (value, buf) in
+                                        data.iter().zip(buf.chunks_exact_mut(4))
+                                    {
+                                        for (byte, buf) in value
+                                            .to_le_bytes()
+                                            .into_iter()
+                                            .zip(buf)
+                                        {
+                                            buf.write(byte);
+                                        }
+                                    }
+                                    data.len() * core::mem::size_of::<i32>()
+                                });
+                            }
+                            StreamData::AdcDac => {
+                                let n = adc_samples[0].len()
+                                    * core::mem::size_of::<i16>()
+                                    / core::mem::size_of::<MaybeUninit<u8>>();
+                                generator.set_format(StreamFormat::AdcDacData);
+                                generator.add(|buf| {
+                                    for (data, buf) in adc_samples
+                                        .iter()
+                                        .chain(dac_samples.iter())
+                                        .zip(buf.chunks_exact_mut(n))
+                                    {
+                                        let data = unsafe {
+                                            core::slice::from_raw_parts(
+                                                data.as_ptr()
+                                                    as *const MaybeUninit<u8>,
+                                                n,
+                                            )
+                                        };
+                                        buf.copy_from_slice(data)
+                                    }
+                                    n * 4
+                                });
+                            }
+                        }
 
-                telemetry.dacs =
-                    [DacCode(dac_samples[0][0]), DacCode(dac_samples[1][0])];
+                        // Update telemetry measurements.
+                        telemetry.add(
+                            [adc_samples[0], adc_samples[1]],
+                            [dac_samples[0], dac_samples[1]],
+                        );
 
-                // Preserve instruction and data ordering w.r.t. DMA flag access.
-                fence(Ordering::SeqCst);
-            });
-        });
+                        // Preserve instruction and data ordering w.r.t. DMA flag access.
+                        fence(Ordering::SeqCst);
+                    });
+                },
+            );
     }
 
     #[idle(shared=[settings, network, usb])]
@@ -515,12 +1165,75 @@ mod app {
         }
     }
 
-    #[task(priority = 1, local=[afes], shared=[network, settings, active_settings])]
+    #[task(priority = 1, local=[afes], shared=[network, settings, active_settings, signal_generator, sampling])]
     async fn settings_update(mut c: settings_update::Context) {
+        let (sample_period, tick_period) = c.shared.sampling.lock(|sampling| {
+            (
+                sampling.sample_period(),
+                sampling.sample_period() / sampling.sample_ticks() as f32,
+            )
+        });
+
         c.shared.settings.lock(|settings| {
+            // Compute the lockin lowpass gains and PLL time constants
+            let lockin = &mut settings.lockin;
+            for (i, (tc, k)) in lockin
+                .lockin_tc
+                .iter()
+                .zip(lockin.lockin_k.iter_mut())
+                .enumerate()
+            {
+                let Some(tc) = tc else {
+                    continue;
+                };
+                match lowpass_gains(*tc, lockin.lockin_order[i], sample_period)
+                {
+                    Some(gains) => *k = gains,
+                    None => log::error!(
+                        "Invalid lockin time constant on demodulator {}: {}",
+                        i,
+                        tc
+                    ),
+                }
+            }
+            for (i, (bandwidth, shift)) in lockin
+                .pll_bandwidth
+                .iter()
+                .zip(lockin.pll_tc.iter_mut())
+                .enumerate()
+            {
+                let Some(bandwidth) = bandwidth else {
+                    continue;
+                };
+                match pll_shift(*bandwidth, tick_period) {
+                    Some(value) => *shift = value,
+                    None => log::error!(
+                        "Invalid PLL bandwidth on loop {}: {}",
+                        i,
+                        bandwidth
+                    ),
+                }
+            }
+
             c.local.afes.0.set_gain(settings.lockin.afe[0]);
             c.local.afes.1.set_gain(settings.lockin.afe[1]);
 
+            // Update the modulation
+            match settings
+                .lockin
+                .modulation
+                .try_into_config(sample_period, DacCode::FULL_SCALE)
+            {
+                Ok(config) => {
+                    c.shared
+                        .signal_generator
+                        .lock(|generator| generator.update_waveform(config));
+                }
+                Err(err) => {
+                    log::error!("Failed to update modulation: {:?}", err)
+                }
+            }
+
             c.shared
                 .network
                 .lock(|net| net.direct_stream(settings.lockin.stream_target));
@@ -531,27 +1244,41 @@ mod app {
         });
     }
 
-    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry])]
+    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry, sampling, reference])]
     async fn telemetry(mut c: telemetry::Context) {
         loop {
             let mut telemetry: TelemetryBuffer =
-                c.shared.telemetry.lock(|telemetry| *telemetry);
+                c.shared.telemetry.lock(core::mem::take);
 
             telemetry.digital_inputs = [
                 c.local.digital_inputs.0.is_high(),
                 c.local.digital_inputs.1.is_high(),
             ];
 
-            let (gains, telemetry_period) =
+            let (gains, telemetry_period, timeout, threshold) =
                 c.shared.settings.lock(|settings| {
-                    (settings.lockin.afe, settings.lockin.telemetry_period)
+                    (
+                        settings.lockin.afe,
+                        settings.lockin.telemetry_period,
+                        settings.lockin.reference_timeout,
+                        settings.lockin.lock_threshold,
+                    )
                 });
 
+            let batch_period =
+                c.shared.sampling.lock(|sampling| sampling.batch_period());
+            let reference = c.shared.reference.lock(|reference| {
+                reference.status(timeout, threshold, batch_period)
+            });
+
             c.shared.network.lock(|net| {
-                net.telemetry.publish(&telemetry.finalize(
-                    gains[0],
-                    gains[1],
-                    c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                net.telemetry.publish(&Telemetry::new(
+                    telemetry.finalize(
+                        gains[0],
+                        gains[1],
+                        c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                    ),
+                    reference,
                 ))
             });
 
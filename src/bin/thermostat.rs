@@ -0,0 +1,1336 @@
+//! # Thermostat
+//!
+//! The `thermostat` application operates Stabilizer as a two channel temperature controller,
+//! e.g. for thermoelectric coolers (TEC).
+//!
+//! Each channel measures a thermistor in a bridge on its ADC input and drives an external
+//! voltage-controlled current source (e.g. a TEC driver) from its DAC output. The ADC input is
+//! averaged over a configurable number of batches. The average is converted to the thermistor
+//! resistance through the bridge model and to temperature through a Steinhart-Hart or beta model.
+//! A PID controller then updates the output current at the decimated rate.
+//!
+//! ## Features
+//! * Two independent channels
+//! * Configurable thermistor bridge
+//! * Steinhart-Hart and beta thermistor models
+//! * PID controller at a decimated rate configured in physical units
+//! * Output current limits
+//! * Output shutdown on thermistor faults
+//! * Relay feedback autotune of the PID gains
+//! * Telemetry in SI units
+//! * Input/Output data streaming
+//!
+//! ## Autotune
+//! The autotune replaces the PID controller of a channel by a relay around the output current at
+//! the start of the autotune. The relay switches by the configured amplitude whenever the
+//! temperature crosses the setpoint beyond the hysteresis. From the period and amplitude of the
+//! resulting temperature oscillation it computes the ultimate gain and period of the loop and
+//! applies Ziegler-Nichols PID gains to the channel. The first oscillation cycle is discarded.
+//! The PID controller continues from the relay bias and the new gains are published as settings.
+//! The autotune is started through `autotune/trigger`.
+//!
+//! ## Settings
+//! Refer to the [Thermostat] structure for documentation of run-time configurable settings for
+//! this application.
+//!
+//! ## Telemetry
+//! Refer to [Telemetry] for information about telemetry reported by this application.
+//!
+//! ## Livestreaming
+//! This application streams raw ADC and DAC data over UDP. Refer to
+//! [stabilizer::net::data_stream](../stabilizer/net/data_stream/index.html) for more information.
+#![no_std]
+#![no_main]
+
+use core::mem::MaybeUninit;
+use core::sync::atomic::{fence, Ordering};
+use serde::{Deserialize, Serialize};
+
+use rtic_monotonics::Monotonic;
+
+use fugit::ExtU32;
+use mutex_trait::prelude::*;
+
+use idsp::iir;
+
+use stabilizer::{
+    filter_design::{self, Pid},
+    hardware::{
+        self,
+        adc::{Adc0Input, Adc1Input, AdcCode},
+        afe::Gain,
+        dac::{Dac0Output, Dac1Output, DacCode},
+        hal,
+        timers::SamplingTimer,
+        DigitalInput0, DigitalInput1, SerialTerminal, SystemTimer, Systick,
+        UsbDevice, AFE0, AFE1,
+    },
+    net::{
+        data_stream::{FrameGenerator, StreamFormat, StreamTarget},
+        miniconf::Tree,
+        telemetry::{Events, Statistics, TelemetryBuffer},
+        NetworkState, NetworkUsers,
+    },
+    settings::{NetSettings, SamplingSettings},
+};
+
+/// The offset between the Celsius and the Kelvin temperature scales.
+const ZERO_CELSIUS: f32 = 273.15;
+
+/// The polling interval of the autotune task in milliseconds.
+const AUTOTUNE_POLL_MS: u32 = 100;
+
+#[derive(Clone, Debug, Tree)]
+pub struct Settings {
+    #[tree(depth = 2)]
+    pub thermostat: Thermostat,
+
+    #[tree(depth = 1)]
+    pub net: NetSettings,
+
+    #[tree(depth = 1)]
+    pub sampling: SamplingSettings,
+}
+
+impl stabilizer::settings::AppSettings for Settings {
+    fn new(net: NetSettings) -> Self {
+        Self {
+            net,
+            thermostat: Thermostat::default(),
+            sampling: SamplingSettings::default(),
+        }
+    }
+
+    fn net(&self) -> &NetSettings {
+        &self.net
+    }
+
+    fn sampling(&self) -> &SamplingSettings {
+        &self.sampling
+    }
+}
+
+impl serial_settings::Settings<3> for Settings {
+    fn reset(&mut self) {
+        *self = Self {
+            thermostat: Thermostat::default(),
+            net: NetSettings::new(self.net.mac),
+            sampling: SamplingSettings::default(),
+        }
+    }
+}
+
+/// Thermistor bridge configuration.
+///
+/// The thermistor `R` and the reference resistor `R_ref` form a divider across the excitation
+/// voltage. The divider output relative to the bridge offset is amplified onto the ADC input:
+/// `V = gain * excitation * (R / (R + R_ref) - offset)`.
+///
+/// # Miniconf
+/// `{"excitation": 2.5, "reference": 10000.0, "offset": 0.5, "gain": 1.0}`
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bridge {
+    /// The excitation voltage in volts.
+    pub excitation: f32,
+
+    /// The reference resistance in Ohm.
+    pub reference: f32,
+
+    /// The bridge offset relative to the excitation. Zero for a plain divider.
+    pub offset: f32,
+
+    /// The gain from the bridge output to the ADC input in V/V.
+    pub gain: f32,
+}
+
+impl Default for Bridge {
+    fn default() -> Self {
+        Self {
+            excitation: 2.5,
+            reference: 10e3,
+            offset: 0.5,
+            gain: 1.,
+        }
+    }
+}
+
+impl Bridge {
+    fn is_valid(&self) -> bool {
+        self.excitation.is_normal()
+            && self.gain.is_normal()
+            && self.reference > 0.
+    }
+
+    /// Compute the thermistor resistance.
+    ///
+    /// # Args
+    /// * `voltage` - The ADC input voltage.
+    ///
+    /// # Returns
+    /// The resistance in Ohm or NaN if the voltage is outside the bridge range.
+    pub fn resistance(&self, voltage: f32) -> f32 {
+        let ratio = voltage / (self.gain * self.excitation) + self.offset;
+        if ratio > 0. && ratio < 1. {
+            self.reference * ratio / (1. - ratio)
+        } else {
+            f32::NAN
+        }
+    }
+}
+
+/// Beta thermistor model.
+///
+/// `1/T = 1/t0 + ln(R/r0)/beta`
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Beta {
+    /// The resistance at the reference temperature in Ohm.
+    pub r0: f32,
+
+    /// The reference temperature in degrees Celsius.
+    pub t0: f32,
+
+    /// The beta coefficient in Kelvin.
+    pub beta: f32,
+}
+
+impl Default for Beta {
+    fn default() -> Self {
+        Self {
+            r0: 10e3,
+            t0: 25.,
+            beta: 3950.,
+        }
+    }
+}
+
+/// Steinhart-Hart thermistor model.
+///
+/// `1/T = a + b ln(R) + c ln(R)^3` with `T` in Kelvin and `R` in Ohm.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SteinhartHart {
+    /// The constant coefficient in 1/K.
+    pub a: f32,
+
+    /// The linear coefficient in 1/K.
+    pub b: f32,
+
+    /// The cubic coefficient in 1/K.
+    pub c: f32,
+}
+
+/// The thermistor model.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Thermistor {
+    /// See [Beta]
+    Beta(Beta),
+
+    /// See [SteinhartHart]
+    SteinhartHart(SteinhartHart),
+}
+
+impl Default for Thermistor {
+    fn default() -> Self {
+        Self::Beta(Beta::default())
+    }
+}
+
+impl Thermistor {
+    fn is_valid(&self) -> bool {
+        match self {
+            Self::Beta(b) => {
+                b.r0 > 0. && b.beta.is_normal() && b.t0 > -ZERO_CELSIUS
+            }
+            Self::SteinhartHart(sh) => {
+                sh.a.is_finite() && sh.b.is_finite() && sh.c.is_finite()
+            }
+        }
+    }
+
+    /// Compute the temperature.
+    ///
+    /// # Args
+    /// * `resistance` - The thermistor resistance in Ohm.
+    ///
+    /// # Returns
+    /// The temperature in degrees Celsius. Not finite if the resistance is invalid.
+    pub fn temperature(&self, resistance: f32) -> f32 {
+        let inverse = match self {
+            Self::Beta(b) => {
+                1. / (b.t0 + ZERO_CELSIUS)
+                    + libm::logf(resistance / b.r0) / b.beta
+            }
+            Self::SteinhartHart(sh) => {
+                let ln = libm::logf(resistance);
+                sh.a + sh.b * ln + sh.c * ln * ln * ln
+            }
+        };
+        1. / inverse - ZERO_CELSIUS
+    }
+}
+
+/// Autotune configuration.
+///
+/// # Miniconf
+/// `{"channel": 0, "amplitude": 0.1, "hysteresis": 0.05, "cycles": 3, "timeout": 600.0,
+/// "trigger": false}`
+#[derive(Copy, Clone, Debug, Tree, Serialize, Deserialize)]
+pub struct AutotuneConfig {
+    /// The channel to tune.
+    pub channel: usize,
+
+    /// The relay amplitude in Ampere. Its sign is the direction of the output current that
+    /// increases the temperature.
+    pub amplitude: f32,
+
+    /// The relay hysteresis in Kelvin.
+    pub hysteresis: f32,
+
+    /// The number of oscillation cycles to average.
+    pub cycles: u32,
+
+    /// The maximum duration of the autotune in seconds.
+    pub timeout: f32,
+
+    /// Set to `true` to start the autotune. It is cleared once started.
+    pub trigger: bool,
+}
+
+impl Default for AutotuneConfig {
+    fn default() -> Self {
+        Self {
+            channel: 0,
+            amplitude: 0.1,
+            hysteresis: 0.05,
+            cycles: 3,
+            timeout: 600.,
+            trigger: false,
+        }
+    }
+}
+
+/// Configuration derived from the [Thermostat] settings in hardware units.
+#[derive(Copy, Clone, Debug)]
+pub struct Config {
+    /// The number of batches per controller update.
+    batches: u32,
+    /// The controller update period in seconds.
+    period: f32,
+    /// The ADC input volts per LSB.
+    volts_per_lsb: [f32; 2],
+    /// The thermistor bridges.
+    bridge: [Bridge; 2],
+    /// The thermistor models.
+    thermistor: [Thermistor; 2],
+    /// The compiled PID controllers from temperature error in Kelvin to current in Ampere.
+    pid: [iir::Biquad<f32>; 2],
+    /// The output current limits in Ampere.
+    current_limits: [(f32, f32); 2],
+    /// The DAC LSB per Ampere.
+    drive: [f32; 2],
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            batches: 1,
+            period: 0.,
+            volts_per_lsb: [0.; 2],
+            bridge: [Bridge::default(); 2],
+            thermistor: [Thermistor::default(); 2],
+            // No output until configured.
+            pid: [iir::Biquad::from([0.; 5]); 2],
+            current_limits: [(0., 0.); 2],
+            drive: [0.; 2],
+        }
+    }
+}
+
+#[derive(Clone, Debug, Tree)]
+pub struct Thermostat {
+    /// Configure the Analog Front End (AFE) gain.
+    ///
+    /// # Path
+    /// `afe/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// Any of the variants of [Gain] enclosed in double quotes.
+    #[tree(depth = 1)]
+    afe: [Gain; 2],
+
+    /// Configure the thermistor bridge.
+    ///
+    /// # Path
+    /// `bridge/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [Bridge]
+    #[tree(depth = 1)]
+    bridge: [Bridge; 2],
+
+    /// Configure the thermistor model.
+    ///
+    /// # Path
+    /// `thermistor/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [Thermistor], e.g. `{"Beta": {"r0": 10000.0, "t0": 25.0, "beta": 3950.0}}`
+    #[tree(depth = 1)]
+    thermistor: [Thermistor; 2],
+
+    /// Enable the temperature control.
+    ///
+    /// # Path
+    /// `enable/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// "true" or "false". While disabled, the output current is zero and the PID state is
+    /// cleared.
+    #[tree(depth = 1)]
+    enable: [bool; 2],
+
+    /// Configure the temperature setpoint.
+    ///
+    /// # Path
+    /// `setpoint/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The temperature in degrees Celsius.
+    #[tree(depth = 1)]
+    setpoint: [f32; 2],
+
+    /// Configure the PID controller.
+    ///
+    /// # Path
+    /// `pid/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// See [Pid]. The controller input is the setpoint minus the temperature in Kelvin and its
+    /// output is the current in Ampere. The gains are in A/K, A/K/s, A/K·s respectively.
+    #[tree(depth = 1)]
+    pid: [Pid; 2],
+
+    /// Configure the PID controller update period.
+    ///
+    /// # Path
+    /// `pid_period`
+    ///
+    /// # Value
+    /// The period in seconds. It is rounded to a whole number of batches, at least one.
+    pid_period: f32,
+
+    /// Configure the minimum output current.
+    ///
+    /// # Path
+    /// `min_current/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The current in Ampere, not above the maximum current.
+    #[tree(depth = 1)]
+    min_current: [f32; 2],
+
+    /// Configure the maximum output current.
+    ///
+    /// # Path
+    /// `max_current/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The current in Ampere, not below the minimum current.
+    #[tree(depth = 1)]
+    max_current: [f32; 2],
+
+    /// Configure the current source transconductance.
+    ///
+    /// # Path
+    /// `transconductance/<n>`
+    ///
+    /// * `<n>` specifies which channel to configure. `<n>` := [0, 1]
+    ///
+    /// # Value
+    /// The output current per DAC output voltage in Ampere per volt. Any non-zero value.
+    #[tree(depth = 1)]
+    transconductance: [f32; 2],
+
+    /// Configure the autotune.
+    ///
+    /// # Path
+    /// `autotune`
+    ///
+    /// # Value
+    /// See [AutotuneConfig]
+    #[tree(depth = 1)]
+    autotune: AutotuneConfig,
+
+    /// Specifies the telemetry output period in seconds.
+    ///
+    /// # Path
+    /// `telemetry_period`
+    ///
+    /// # Value
+    /// Any non-zero value less than 65536.
+    telemetry_period: u16,
+
+    /// Specifies the target for data livestreaming.
+    ///
+    /// # Path
+    /// `stream_target`
+    ///
+    /// # Value
+    /// See [StreamTarget#miniconf]
+    stream_target: StreamTarget,
+}
+
+impl Default for Thermostat {
+    fn default() -> Self {
+        Self {
+            afe: [Gain::G1; 2],
+            bridge: [Bridge::default(); 2],
+            thermistor: [Thermistor::default(); 2],
+            enable: [false; 2],
+            setpoint: [25.; 2],
+            pid: [Pid::default(); 2],
+            pid_period: 0.01,
+            min_current: [-1.; 2],
+            max_current: [1.; 2],
+            transconductance: [0.1; 2],
+            autotune: AutotuneConfig::default(),
+            // The default telemetry period in seconds.
+            telemetry_period: 10,
+
+            stream_target: StreamTarget::default(),
+        }
+    }
+}
+
+impl Thermostat {
+    /// Update the derived [Config] from the settings.
+    ///
+    /// Invalid settings are logged and leave the respective configuration unchanged.
+    ///
+    /// # Args
+    /// * `config` - The configuration to update.
+    /// * `batch_period` - The time in seconds between batches.
+    fn configure(&self, config: &mut Config, batch_period: f32) {
+        if self.pid_period >= 0. {
+            // Note(as): The conversion saturates.
+            config.batches =
+                (libm::roundf(self.pid_period / batch_period) as u32).max(1);
+            config.period = config.batches as f32 * batch_period;
+        } else {
+            log::error!("Invalid PID period: {}", self.pid_period);
+        }
+
+        for i in 0..2 {
+            config.volts_per_lsb[i] =
+                AdcCode::VOLT_PER_LSB / self.afe[i].as_multiplier();
+
+            if self.bridge[i].is_valid() {
+                config.bridge[i] = self.bridge[i];
+            } else {
+                log::error!(
+                    "Invalid bridge on channel {}: {:?}",
+                    i,
+                    self.bridge[i]
+                );
+            }
+
+            if self.thermistor[i].is_valid() {
+                config.thermistor[i] = self.thermistor[i];
+            } else {
+                log::error!(
+                    "Invalid thermistor on channel {}: {:?}",
+                    i,
+                    self.thermistor[i]
+                );
+            }
+
+            let (min, max) = (self.min_current[i], self.max_current[i]);
+            if min <= max {
+                config.current_limits[i] = (min, max);
+            } else {
+                log::error!(
+                    "Invalid current limits on channel {}: {} to {}",
+                    i,
+                    min,
+                    max
+                );
+            }
+
+            let design = filter_design::Design {
+                filter: filter_design::Filter::Pid(self.pid[i]),
+                offset: 0.,
+                min: config.current_limits[i].0,
+                max: config.current_limits[i].1,
+            };
+            match design.biquad(config.period, 1.) {
+                Ok(biquad) => config.pid[i] = biquad,
+                Err(err) => log::error!(
+                    "Failed to compile PID on channel {}: {:?}",
+                    i,
+                    err
+                ),
+            }
+
+            if self.transconductance[i].is_normal() {
+                config.drive[i] =
+                    1. / (self.transconductance[i] * DacCode::VOLT_PER_LSB);
+            } else {
+                log::error!(
+                    "Invalid transconductance on channel {}: {}",
+                    i,
+                    self.transconductance[i]
+                );
+            }
+        }
+    }
+}
+
+/// Errors that abort an autotune.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AutotuneError {
+    /// The oscillation did not complete the configured cycles within the timeout.
+    Timeout,
+    /// The channel was disabled.
+    Disabled,
+    /// The thermistor measurement failed.
+    Fault,
+}
+
+/// The result of a successful autotune.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct AutotuneResult {
+    /// The tuned channel.
+    pub channel: usize,
+
+    /// The ultimate gain of the loop in A/K.
+    pub ultimate_gain: f32,
+
+    /// The ultimate period of the loop in seconds.
+    pub ultimate_period: f32,
+
+    /// The applied PID gains.
+    pub pid: Pid,
+}
+
+/// Relay feedback autotune state.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Autotune {
+    // The configuration of the active autotune.
+    config: Option<AutotuneConfig>,
+    // The controller update period in seconds.
+    period: f32,
+    // The output current at the start in Ampere.
+    bias: Option<f32>,
+    // Whether the relay is high.
+    high: bool,
+    // The number of controller updates since the start.
+    ticks: u32,
+    // The number of relay rising edges since the start.
+    rises: u32,
+    // The update of the last relay rising edge.
+    last_rise: u32,
+    // The temperature error extrema since the last relay rising edge in Kelvin.
+    extrema: (f32, f32),
+    // The sum of the cycle periods in updates.
+    period_sum: u32,
+    // The sum of the cycle peak-to-peak amplitudes in Kelvin.
+    amplitude_sum: f32,
+    // The outcome of the most recent autotune.
+    outcome: Option<Result<AutotuneResult, AutotuneError>>,
+    // The channel and the relay bias in Ampere to hand over to the PID controller.
+    handover: Option<(usize, f32)>,
+}
+
+impl Autotune {
+    /// Start an autotune.
+    ///
+    /// # Args
+    /// * `config` - The autotune configuration.
+    /// * `period` - The controller update period in seconds.
+    pub fn start(&mut self, config: &AutotuneConfig, period: f32) {
+        *self = Self {
+            config: Some(*config),
+            period,
+            ..Default::default()
+        };
+    }
+
+    /// Take the outcome of a completed autotune.
+    pub fn take_outcome(
+        &mut self,
+    ) -> Option<Result<AutotuneResult, AutotuneError>> {
+        self.outcome.take()
+    }
+
+    /// Abort an active autotune of a channel.
+    ///
+    /// # Args
+    /// * `channel` - The channel.
+    /// * `error` - The reason.
+    pub fn abort(&mut self, channel: usize, error: AutotuneError) {
+        if matches!(self.config, Some(config) if config.channel == channel) {
+            self.config = None;
+            self.outcome = Some(Err(error));
+        }
+    }
+
+    /// Take the relay bias of a channel after a successful autotune.
+    ///
+    /// # Args
+    /// * `channel` - The channel.
+    ///
+    /// # Returns
+    /// The relay bias in Ampere if the autotune of the channel completed since the last call.
+    pub fn take_handover(&mut self, channel: usize) -> Option<f32> {
+        let (tuned, bias) = self.handover?;
+        (tuned == channel).then(|| {
+            self.handover = None;
+            bias
+        })
+    }
+
+    fn finish(&mut self, config: &AutotuneConfig) {
+        self.config = None;
+        self.handover = self.bias.map(|bias| (config.channel, bias));
+        let cycles = (self.rises - 2) as f32;
+        let ultimate_period = self.period_sum as f32 * self.period / cycles;
+        let a = 0.5 * self.amplitude_sum / cycles;
+        // Describing function of a relay with hysteresis.
+        let h = config.hysteresis;
+        let a = if a > h { libm::sqrtf(a * a - h * h) } else { a };
+        let ultimate_gain = 4. * config.amplitude / (core::f32::consts::PI * a);
+
+        // Ziegler-Nichols
+        let kp = 0.6 * ultimate_gain;
+        let pid = Pid {
+            kp,
+            ki: 2. * kp / ultimate_period,
+            kd: 0.125 * kp * ultimate_period,
+            kd_limit: Some(10. * kp),
+            ..Default::default()
+        };
+
+        self.outcome = Some(Ok(AutotuneResult {
+            channel: config.channel,
+            ultimate_gain,
+            ultimate_period,
+            pid,
+        }));
+    }
+
+    /// Update the relay.
+    ///
+    /// # Args
+    /// * `channel` - The channel.
+    /// * `error` - The setpoint minus the temperature in Kelvin.
+    /// * `current` - The present output current in Ampere.
+    ///
+    /// # Returns
+    /// The relay output current in Ampere if the channel is being tuned.
+    pub fn update(
+        &mut self,
+        channel: usize,
+        error: f32,
+        current: f32,
+    ) -> Option<f32> {
+        let config = self.config.filter(|c| c.channel == channel)?;
+        let bias = *self.bias.get_or_insert(current);
+
+        self.ticks += 1;
+        if self.ticks as f32 * self.period > config.timeout {
+            self.abort(channel, AutotuneError::Timeout);
+            return None;
+        }
+
+        self.extrema = (self.extrema.0.min(error), self.extrema.1.max(error));
+
+        if !self.high && error > config.hysteresis {
+            self.high = true;
+            self.rises += 1;
+            // Discard the first cycle.
+            if self.rises > 2 {
+                self.period_sum += self.ticks - self.last_rise;
+                self.amplitude_sum += self.extrema.1 - self.extrema.0;
+            }
+            self.last_rise = self.ticks;
+            self.extrema = (error, error);
+            if self.rises > config.cycles + 1 {
+                self.finish(&config);
+                return None;
+            }
+        } else if self.high && error < -config.hysteresis {
+            self.high = false;
+        }
+
+        Some(if self.high {
+            bias + config.amplitude
+        } else {
+            bias - config.amplitude
+        })
+    }
+}
+
+/// Run-time state of a thermostat channel.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Channel {
+    // The ADC input sum over the current update period.
+    sum: i64,
+    // The most recent thermistor resistance in Ohm.
+    resistance: f32,
+    // The most recent temperature in degrees Celsius.
+    temperature: f32,
+    // The output current in Ampere.
+    current: f32,
+    // The number of updates with a thermistor fault since the last telemetry.
+    faults: u32,
+}
+
+/// Telemetry of a thermostat channel.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct ChannelTelemetry {
+    /// The most recent temperature in degrees Celsius.
+    temperature: f32,
+
+    /// The most recent thermistor resistance in Ohm.
+    resistance: f32,
+
+    /// The output current in Ampere.
+    current: f32,
+
+    /// The number of controller updates with a thermistor fault over the telemetry period. The
+    /// output current is zero during a fault.
+    faults: u32,
+}
+
+impl Channel {
+    /// Update the channel at the end of an update period.
+    ///
+    /// # Args
+    /// * `config` - The thermostat configuration.
+    /// * `channel` - The channel index.
+    /// * `enable` - Whether the temperature control is enabled.
+    /// * `setpoint` - The temperature setpoint in degrees Celsius.
+    /// * `samples` - The number of ADC samples in the update period.
+    /// * `state` - The PID state.
+    /// * `autotune` - The autotune state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn update(
+        &mut self,
+        config: &Config,
+        channel: usize,
+        enable: bool,
+        setpoint: f32,
+        samples: u32,
+        state: &mut [f32; 4],
+        autotune: &mut Autotune,
+    ) {
+        let voltage = core::mem::take(&mut self.sum) as f32
+            * config.volts_per_lsb[channel]
+            / samples as f32;
+        self.resistance = config.bridge[channel].resistance(voltage);
+        self.temperature =
+            config.thermistor[channel].temperature(self.resistance);
+        let error = setpoint - self.temperature;
+
+        let current = if !self.temperature.is_finite() {
+            self.faults = self.faults.saturating_add(1);
+            autotune.abort(channel, AutotuneError::Fault);
+            None
+        } else if !enable {
+            autotune.abort(channel, AutotuneError::Disabled);
+            None
+        } else if let Some(current) =
+            autotune.update(channel, error, self.current)
+        {
+            let (min, max) = config.current_limits[channel];
+            Some(current.clamp(min, max))
+        } else {
+            if let Some(bias) = autotune.take_handover(channel) {
+                // Continue from the relay bias without a step.
+                *state = [error, error, bias, bias];
+            }
+            Some(config.pid[channel].update(state, error))
+        };
+
+        self.current = current.unwrap_or_else(|| {
+            *state = [0.; 4];
+            0.
+        });
+    }
+
+    /// Collect the channel telemetry and restart the fault counter.
+    pub fn telemetry(&mut self) -> ChannelTelemetry {
+        ChannelTelemetry {
+            temperature: self.temperature,
+            resistance: self.resistance,
+            current: self.current,
+            faults: core::mem::take(&mut self.faults),
+        }
+    }
+}
+
+/// Telemetry reported by the thermostat application.
+///
+/// This extends the common [stabilizer::net::telemetry::Telemetry] by application-specific
+/// fields.
+#[derive(Serialize)]
+pub struct Telemetry {
+    /// Input voltage statistics over the telemetry period.
+    adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    dacs: [Statistics; 2],
+
+    /// Event counters of each channel over the telemetry period.
+    events: [Events; 2],
+
+    /// Most recent digital input assertion state.
+    digital_inputs: [bool; 2],
+
+    /// The CPU temperature in degrees Celsius.
+    cpu_temp: f32,
+
+    /// The temperature control telemetry of each channel.
+    channels: [ChannelTelemetry; 2],
+
+    /// The result of the most recent successful autotune.
+    autotune: Option<AutotuneResult>,
+}
+
+impl Telemetry {
+    fn new(
+        telemetry: stabilizer::net::telemetry::Telemetry,
+        channels: [ChannelTelemetry; 2],
+        autotune: Option<AutotuneResult>,
+    ) -> Self {
+        Self {
+            adcs: telemetry.adcs,
+            dacs: telemetry.dacs,
+            events: telemetry.events,
+            digital_inputs: telemetry.digital_inputs,
+            cpu_temp: telemetry.cpu_temp,
+            channels,
+            autotune,
+        }
+    }
+}
+
+#[rtic::app(device = stabilizer::hardware::hal::stm32, peripherals = true, dispatchers=[DCMI, JPEG, SDMMC])]
+mod app {
+    use super::*;
+
+    #[shared]
+    struct Shared {
+        usb: UsbDevice,
+        network: NetworkUsers<Thermostat, 2>,
+        settings: Settings,
+        active_settings: Thermostat,
+        config: Config,
+        telemetry: TelemetryBuffer,
+        channels: [Channel; 2],
+        tuner: Autotune,
+        autotune_result: Option<AutotuneResult>,
+    }
+
+    #[local]
+    struct Local {
+        usb_terminal: SerialTerminal<Settings, 3>,
+        sampling_timer: SamplingTimer,
+        sampling: SamplingSettings,
+        digital_inputs: (DigitalInput0, DigitalInput1),
+        afes: (AFE0, AFE1),
+        adcs: (Adc0Input, Adc1Input),
+        dacs: (Dac0Output, Dac1Output),
+        // The number of batches in the current update period.
+        decimation: u32,
+        iir_state: [[f32; 4]; 2],
+        generator: FrameGenerator,
+        cpu_temp_sensor: stabilizer::hardware::cpu_temp_sensor::CpuTempSensor,
+    }
+
+    #[init]
+    fn init(c: init::Context) -> (Shared, Local) {
+        let clock = SystemTimer::new(|| Systick::now().ticks());
+
+        // Configure the microcontroller
+        let stabilizer =
+            hardware::setup::setup::<Settings, 3>(c.core, c.device, clock);
+
+        let mut network = NetworkUsers::new(
+            stabilizer.net.stack,
+            stabilizer.net.phy,
+            clock,
+            env!("CARGO_BIN_NAME"),
+            &stabilizer.settings.net,
+            stabilizer.metadata,
+        );
+
+        let generator = network.configure_streaming(StreamFormat::AdcDacData);
+
+        let shared = Shared {
+            network,
+            usb: stabilizer.usb,
+            telemetry: TelemetryBuffer::default(),
+            channels: Default::default(),
+            tuner: Autotune::default(),
+            autotune_result: None,
+            active_settings: stabilizer.settings.thermostat.clone(),
+            config: Config::default(),
+            settings: stabilizer.settings,
+        };
+
+        let mut local = Local {
+            usb_terminal: stabilizer.usb_serial,
+            sampling_timer: stabilizer.adc_dac_timer,
+            sampling: stabilizer.sampling,
+            digital_inputs: stabilizer.digital_inputs,
+            afes: stabilizer.afes,
+            adcs: stabilizer.adcs,
+            dacs: stabilizer.dacs,
+            decimation: 0,
+            iir_state: [[0.; 4]; 2],
+            generator,
+            cpu_temp_sensor: stabilizer.temperature_sensor,
+        };
+
+        // Enable ADC/DAC events
+        local.adcs.0.start();
+        local.adcs.1.start();
+        local.dacs.0.start();
+        local.dacs.1.start();
+
+        // Spawn a settings and telemetry update for default settings.
+        settings_update::spawn().unwrap();
+        telemetry::spawn().unwrap();
+        ethernet_link::spawn().unwrap();
+        start::spawn().unwrap();
+        usb::spawn().unwrap();
+
+        (shared, local)
+    }
+
+    #[task(priority = 1, local=[sampling_timer])]
+    async fn start(c: start::Context) {
+        Systick::delay(100.millis()).await;
+        // Start sampling ADCs and DACs.
+        c.local.sampling_timer.start();
+    }
+
+    /// Main DSP processing routine.
+    ///
+    /// See `dual-iir` for general notes on processing time and timing.
+    ///
+    /// The ADC inputs are accumulated over the configured number of batches. At the end of each
+    /// update period the temperatures are computed and the PID controllers (or the autotune
+    /// relay) update the output currents. The DAC outputs hold the output currents in between.
+    #[task(binds=DMA1_STR4, shared=[active_settings, config, telemetry, channels, tuner], local=[adcs, dacs, decimation, iir_state, generator], priority=3)]
+    #[link_section = ".itcm.process"]
+    fn process(c: process::Context) {
+        let process::SharedResources {
+            active_settings,
+            config,
+            telemetry,
+            channels,
+            tuner,
+            ..
+        } = c.shared;
+
+        let process::LocalResources {
+            adcs: (adc0, adc1),
+            dacs: (dac0, dac1),
+            decimation,
+            iir_state,
+            generator,
+            ..
+        } = c.local;
+
+        (active_settings, config, telemetry, channels, tuner).lock(
+            |settings, config, telemetry, channels, tuner| {
+                (adc0, adc1, dac0, dac1).lock(|adc0, adc1, dac0, dac1| {
+                    let adc_samples = [adc0, adc1];
+                    let dac_samples = [dac0, dac1];
+                    let batch_size = adc_samples[0].len();
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+
+                    for (channel, samples) in
+                        channels.iter_mut().zip(adc_samples.iter())
+                    {
+                        channel.sum += samples
+                            .iter()
+                            .map(|&x| x as i16 as i64)
+                            .sum::<i64>();
+                    }
+
+                    *decimation += 1;
+                    if *decimation >= config.batches {
+                        let samples = *decimation * batch_size as u32;
+                        *decimation = 0;
+                        for (i, channel) in channels.iter_mut().enumerate() {
+                            channel.update(
+                                config,
+                                i,
+                                settings.enable[i],
+                                settings.setpoint[i],
+                                samples,
+                                &mut iir_state[i],
+                                tuner,
+                            );
+                        }
+                    }
+
+                    for (i, channel) in channels.iter().enumerate() {
+                        let y = channel.current * config.drive[i];
+                        if !(i16::MIN as f32..=i16::MAX as f32).contains(&y) {
+                            let events = &mut telemetry.events[i];
+                            events.dac_clip = events
+                                .dac_clip
+                                .saturating_add(batch_size as u32);
+                        }
+
+                        // Note(unsafe): The value is clamped to the i16 range.
+                        let y: i16 = unsafe {
+                            y.clamp(i16::MIN as _, i16::MAX as _)
+                                .to_int_unchecked()
+                        };
+                        dac_samples[i].fill(DacCode::from(y).0);
+                    }
+
+                    // Stream the data.
+                    let n = batch_size * core::mem::size_of::<i16>();
+                    generator.add(|buf| {
+                        for (data, buf) in adc_samples
+                            .iter()
+                            .chain(dac_samples.iter())
+                            .zip(buf.chunks_exact_mut(n))
+                        {
+                            let data = unsafe {
+                                core::slice::from_raw_parts(
+                                    data.as_ptr() as *const MaybeUninit<u8>,
+                                    n,
+                                )
+                            };
+                            buf.copy_from_slice(data)
+                        }
+                        n * 4
+                    });
+
+                    // Update telemetry measurements.
+                    telemetry.add(
+                        [adc_samples[0], adc_samples[1]],
+                        [dac_samples[0], dac_samples[1]],
+                    );
+
+                    // Preserve instruction and data ordering w.r.t. DMA flag access.
+                    fence(Ordering::SeqCst);
+                });
+            },
+        );
+    }
+
+    #[idle(shared=[settings, network, usb])]
+    fn idle(mut c: idle::Context) -> ! {
+        loop {
+            match (&mut c.shared.network, &mut c.shared.settings)
+                .lock(|net, settings| net.update(&mut settings.thermostat))
+            {
+                NetworkState::SettingsChanged => {
+                    settings_update::spawn().unwrap()
+                }
+                NetworkState::Updated => {}
+                NetworkState::NoChange => {
+                    // We can't sleep if USB is not in suspend.
+                    if c.shared.usb.lock(|usb| {
+                        usb.state()
+                            == usb_device::device::UsbDeviceState::Suspend
+                    }) {
+                        cortex_m::asm::wfi();
+                    }
+                }
+            }
+        }
+    }
+
+    #[task(priority = 1, local=[afes, sampling], shared=[network, settings, active_settings, config])]
+    async fn settings_update(mut c: settings_update::Context) {
+        let batch_period = c.local.sampling.batch_period();
+        let mut config = c.shared.config.lock(|config| *config);
+
+        c.shared.settings.lock(|settings| {
+            let thermostat = &mut settings.thermostat;
+            if thermostat.autotune.trigger {
+                thermostat.autotune.trigger = false;
+                if autotune::spawn().is_err() {
+                    log::warn!("Autotune already in progress");
+                }
+            }
+
+            thermostat.configure(&mut config, batch_period);
+
+            c.local.afes.0.set_gain(thermostat.afe[0]);
+            c.local.afes.1.set_gain(thermostat.afe[1]);
+
+            c.shared
+                .network
+                .lock(|net| net.direct_stream(thermostat.stream_target));
+
+            (&mut c.shared.active_settings, &mut c.shared.config).lock(
+                |current, current_config| {
+                    *current = thermostat.clone();
+                    *current_config = config;
+                },
+            );
+        });
+    }
+
+    #[task(priority = 1, shared=[network, settings, config, tuner, autotune_result])]
+    async fn autotune(mut c: autotune::Context) {
+        let (config, enable) = c.shared.settings.lock(|settings| {
+            (settings.thermostat.autotune, settings.thermostat.enable)
+        });
+        let period = c.shared.config.lock(|config| config.period);
+
+        let Some(&enabled) = enable.get(config.channel) else {
+            log::error!("Invalid autotune channel: {}", config.channel);
+            return;
+        };
+
+        if !enabled {
+            log::error!("Autotune requires an enabled channel");
+            return;
+        }
+
+        if config.cycles == 0 || config.amplitude == 0. {
+            log::error!("Invalid autotune configuration: {:?}", config);
+            return;
+        }
+
+        log::info!("Autotune started on channel {}", config.channel);
+        c.shared.tuner.lock(|tuner| tuner.start(&config, period));
+
+        let outcome = loop {
+            Systick::delay(AUTOTUNE_POLL_MS.millis()).await;
+            if let Some(outcome) =
+                c.shared.tuner.lock(|tuner| tuner.take_outcome())
+            {
+                break outcome;
+            }
+        };
+
+        match outcome {
+            Ok(result) => {
+                log::info!(
+                    "Autotune converged: ultimate gain {} A/K, period {} s",
+                    result.ultimate_gain,
+                    result.ultimate_period
+                );
+                c.shared.settings.lock(|settings| {
+                    settings.thermostat.pid[result.channel] = result.pid
+                });
+                c.shared.network.lock(|net| net.republish_settings());
+                c.shared
+                    .autotune_result
+                    .lock(|current| *current = Some(result));
+                // A pending settings update also applies the new gains.
+                settings_update::spawn().ok();
+            }
+            Err(err) => log::error!("Autotune failed: {:?}", err),
+        }
+    }
+
+    #[task(priority = 1, local=[digital_inputs, cpu_temp_sensor], shared=[network, settings, telemetry, channels, autotune_result])]
+    async fn telemetry(mut c: telemetry::Context) {
+        loop {
+            let mut telemetry: TelemetryBuffer =
+                c.shared.telemetry.lock(core::mem::take);
+
+            telemetry.digital_inputs = [
+                c.local.digital_inputs.0.is_high(),
+                c.local.digital_inputs.1.is_high(),
+            ];
+
+            let (gains, telemetry_period) =
+                c.shared.settings.lock(|settings| {
+                    (
+                        settings.thermostat.afe,
+                        settings.thermostat.telemetry_period,
+                    )
+                });
+
+            let channels = c.shared.channels.lock(|channels| {
+                [channels[0].telemetry(), channels[1].telemetry()]
+            });
+
+            let autotune = c.shared.autotune_result.lock(|result| *result);
+
+            c.shared.network.lock(|net| {
+                net.telemetry.publish(&Telemetry::new(
+                    telemetry.finalize(
+                        gains[0],
+                        gains[1],
+                        c.local.cpu_temp_sensor.get_temperature().unwrap(),
+                    ),
+                    channels,
+                    autotune,
+                ))
+            });
+
+            // Schedule the telemetry task in the future.
+            Systick::delay((telemetry_period as u32).secs()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[usb, settings], local=[usb_terminal])]
+    async fn usb(mut c: usb::Context) {
+        loop {
+            // Handle the USB serial terminal.
+            c.shared.usb.lock(|usb| {
+                usb.poll(&mut [c
+                    .local
+                    .usb_terminal
+                    .interface_mut()
+                    .inner_mut()]);
+            });
+
+            c.shared.settings.lock(|settings| {
+                if c.local.usb_terminal.poll(settings).unwrap() {
+                    settings_update::spawn().unwrap()
+                }
+            });
+
+            Systick::delay(10.millis()).await;
+        }
+    }
+
+    #[task(priority = 1, shared=[network])]
+    async fn ethernet_link(mut c: ethernet_link::Context) {
+        loop {
+            c.shared.network.lock(|net| net.processor.handle_link());
+            Systick::delay(1.secs()).await;
+        }
+    }
+
+    #[task(binds = ETH, priority = 1)]
+    fn eth(_: eth::Context) {
+        unsafe { hal::ethernet::interrupt_handler() }
+    }
+}
@@ -0,0 +1,425 @@
+//! IIR filter design from physical units
+//!
+//! # Design
+//! Filters are specified by their physical parameters (gains in V/V, frequencies in Hertz, times
+//! in seconds) and compiled into a single biquad section for a given sample period. This allows
+//! filters to be configured at run-time without external tooling such as the
+//! `iir_coefficients` Python script.
+//!
+//! The discretizations follow the `iir_coefficients` script: PID filters use backward
+//! differences and the second order filters use the bilinear transform (without frequency
+//! pre-warping).
+//!
+//! The designs assume a unity AFE gain and identical ADC and DAC full scales, such that a
+//! gain of 1 V/V corresponds to a gain of 1 code/code.
+use idsp::iir;
+use serde::{Deserialize, Serialize};
+
+/// Errors that can occur when compiling a filter design.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The corner frequency is not between zero and Nyquist.
+    Frequency,
+
+    /// The quality factor is not positive.
+    Quality,
+
+    /// The combination of gains is not supported by a single biquad.
+    Unsupported,
+
+    /// A gain limit is zero.
+    Limit,
+}
+
+/// A PID controller with optional second order integral and derivative terms.
+///
+/// Gains that are zero are unused. Gain limits are the magnitude ceiling of the respective term
+/// (e.g. the DC gain of the integrator or the high frequency gain of the differentiator) and
+/// must have the same sign as the gain. A limit of `null` is unlimited.
+///
+/// A single biquad supports at most two integrator and differentiator orders in total: `kii`
+/// excludes `kd` and `kdd` and `ki` excludes `kdd`.
+#[derive(Copy, Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pid {
+    /// Double integral gain in V/V/s²
+    pub kii: f32,
+
+    /// Double integral gain limit in V/V
+    pub kii_limit: Option<f32>,
+
+    /// Integral gain in V/V/s
+    pub ki: f32,
+
+    /// Integral gain limit in V/V
+    pub ki_limit: Option<f32>,
+
+    /// Proportional gain in V/V
+    pub kp: f32,
+
+    /// Derivative gain in V/V·s
+    pub kd: f32,
+
+    /// Derivative gain limit in V/V
+    pub kd_limit: Option<f32>,
+
+    /// Double derivative gain in V/V·s²
+    pub kdd: f32,
+
+    /// Double derivative gain limit in V/V
+    pub kdd_limit: Option<f32>,
+}
+
+impl Pid {
+    fn ba(&self, sample_period: f32) -> Result<[f32; 5], Error> {
+        let order = if self.kii != 0. {
+            2
+        } else if self.ki != 0. {
+            1
+        } else {
+            0
+        };
+
+        let limit = |gain: f32, limit: Option<f32>| match limit {
+            Some(0.) => Err(Error::Limit),
+            Some(limit) => Ok(gain / limit),
+            None => Ok(0.),
+        };
+        let gains = [self.kii, self.ki, self.kp, self.kd, self.kdd];
+        let limits = [
+            limit(self.kii, self.kii_limit)?,
+            limit(self.ki, self.ki_limit)?,
+            1.,
+            limit(self.kd, self.kd_limit)?,
+            limit(self.kdd, self.kdd_limit)?,
+        ];
+
+        // Backward difference kernels: 1, (1 - z^-1), (1 - z^-1)^2
+        const KERNELS: [[f32; 3]; 3] =
+            [[1., 0., 0.], [1., -1., 0.], [1., -2., 1.]];
+
+        let mut b = [0.; 3];
+        let mut a = [0.; 3];
+        for (i, kernel) in KERNELS.iter().enumerate() {
+            // The term at index `2 - order + i` is scaled by `T^(order - i)`.
+            let scale = match order as i32 - i as i32 {
+                2 => sample_period * sample_period,
+                1 => sample_period,
+                0 => 1.,
+                -1 => 1. / sample_period,
+                _ => 1. / (sample_period * sample_period),
+            };
+            let gain = gains[2 - order + i] * scale;
+            let limit = limits[2 - order + i] * scale;
+            for (j, k) in kernel.iter().enumerate() {
+                b[j] += gain * k;
+                a[j] += limit * k;
+            }
+        }
+
+        // Terms beyond the highest present order cannot be represented.
+        if gains[5 - order..].iter().any(|&g| g != 0.) {
+            return Err(Error::Unsupported);
+        }
+
+        Ok([
+            b[0] / a[0],
+            b[1] / a[0],
+            b[2] / a[0],
+            a[1] / a[0],
+            a[2] / a[0],
+        ])
+    }
+}
+
+/// A second order filter.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct SecondOrder {
+    /// The corner (or center) frequency in Hertz
+    pub frequency: f32,
+
+    /// The quality factor
+    pub q: f32,
+
+    /// The gain in V/V
+    pub gain: f32,
+}
+
+impl SecondOrder {
+    // Bilinear transform of `gain*(n0*s^2 + n1*w0*s + n2*w0^2)/(s^2 + w0/q*s + w0^2)`.
+    fn ba(
+        &self,
+        numerator: [f32; 3],
+        sample_period: f32,
+    ) -> Result<[f32; 5], Error> {
+        let f = self.frequency * sample_period;
+        if !(f > 0. && f < 0.5) {
+            return Err(Error::Frequency);
+        }
+        if self.q.is_nan() || self.q <= 0. {
+            return Err(Error::Quality);
+        }
+
+        let f = core::f32::consts::PI * f;
+        let f2 = f * f;
+        let fq = f / self.q;
+
+        let a0 = 1. + fq + f2;
+        let [n0, n1, n2] = numerator.map(|n| n * self.gain / a0);
+        Ok([
+            n0 + n1 * f + n2 * f2,
+            2. * (n2 * f2 - n0),
+            n0 - n1 * f + n2 * f2,
+            2. * (f2 - 1.) / a0,
+            (1. - fq + f2) / a0,
+        ])
+    }
+}
+
+/// The filter type and parameters.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum Filter {
+    /// See [Pid]
+    Pid(Pid),
+
+    /// Second order low-pass filter with the given DC gain.
+    Lowpass(SecondOrder),
+
+    /// Second order high-pass filter with the given high frequency gain.
+    Highpass(SecondOrder),
+
+    /// Second order notch filter with the given DC and high frequency gain.
+    Notch(SecondOrder),
+
+    /// Second order all-pass filter with the given gain.
+    Allpass(SecondOrder),
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::Pid(Pid::default())
+    }
+}
+
+/// A filter design compiling to a biquad section.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Design {
+    /// The filter type and parameters.
+    pub filter: Filter,
+
+    /// The output offset in volts.
+    pub offset: f32,
+
+    /// The minimum output in volts.
+    pub min: f32,
+
+    /// The maximum output in volts.
+    pub max: f32,
+}
+
+impl Default for Design {
+    fn default() -> Self {
+        Self {
+            filter: Filter::default(),
+            offset: 0.,
+            min: -10.24,
+            max: 10.24,
+        }
+    }
+}
+
+impl Design {
+    /// Compile the design into a biquad section.
+    ///
+    /// # Args
+    /// * `sample_period` - The time in seconds between samples.
+    /// * `lsb_per_volt` - The output codes per volt.
+    pub fn biquad(
+        &self,
+        sample_period: f32,
+        lsb_per_volt: f32,
+    ) -> Result<iir::Biquad<f32>, Error> {
+        let ba = match &self.filter {
+            Filter::Pid(pid) => pid.ba(sample_period)?,
+            Filter::Lowpass(f) => f.ba([0., 0., 1.], sample_period)?,
+            Filter::Highpass(f) => f.ba([1., 0., 0.], sample_period)?,
+            Filter::Notch(f) => f.ba([1., 0., 1.], sample_period)?,
+            Filter::Allpass(f) => f.ba([1., -1. / f.q, 1.], sample_period)?,
+        };
+
+        let mut biquad = iir::Biquad::from(ba);
+        biquad.set_u(self.offset * lsb_per_volt);
+        biquad.set_min(self.min * lsb_per_volt);
+        biquad.set_max(self.max * lsb_per_volt);
+        Ok(biquad)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SAMPLE_PERIOD: f32 = 1e-5;
+
+    fn assert_close(ba: [f32; 5], reference: [f32; 5]) {
+        for (c, r) in ba.iter().zip(reference.iter()) {
+            assert!((c - r).abs() < 1e-5, "{:?} != {:?}", ba, reference);
+        }
+    }
+
+    fn design(filter: Filter) -> [f32; 5] {
+        *Design {
+            filter,
+            ..Default::default()
+        }
+        .biquad(SAMPLE_PERIOD, 1.)
+        .unwrap()
+        .ba()
+    }
+
+    // The transfer function magnitude at the angular frequency `w` in radians per sample.
+    fn gain(ba: [f32; 5], w: f32) -> f32 {
+        let [b0, b1, b2, a1, a2] = ba;
+        let (c, s) = (libm::cosf(w), libm::sinf(w));
+        let (c2, s2) = (libm::cosf(2. * w), libm::sinf(2. * w));
+        let magnitude = |p0: f32, p1: f32, p2: f32| {
+            libm::hypotf(p0 + p1 * c + p2 * c2, p1 * s + p2 * s2)
+        };
+        magnitude(b0, b1, b2) / magnitude(1., a1, a2)
+    }
+
+    fn second_order(frequency: f32) -> SecondOrder {
+        SecondOrder {
+            frequency,
+            q: core::f32::consts::FRAC_1_SQRT_2,
+            gain: 2.,
+        }
+    }
+
+    // The reference coefficients are from the `iir_coefficients` script with
+    // `--sample-period 1e-5`. It takes gains at 1 Hz: `Ki = ki/(2π)`, `Kii = kii/(2π)²` and
+    // `Kd = 2π kd`.
+    #[test]
+    fn pi() {
+        // `iir_coefficients.py pid --Ki -159.155 --Kp -0.5`
+        let pid = Pid {
+            ki: -1e3,
+            kp: -0.5,
+            ..Default::default()
+        };
+        assert_close(design(Filter::Pid(pid)), [-0.51, 0.5, 0., -1., 0.]);
+    }
+
+    #[test]
+    fn pi_limit() {
+        // `iir_coefficients.py pid --Ki -159.155 --Ki_limit -100 --Kp -0.5`
+        let pid = Pid {
+            ki: -1e3,
+            ki_limit: Some(-100.),
+            kp: -0.5,
+            ..Default::default()
+        };
+        assert_close(
+            design(Filter::Pid(pid)),
+            [-0.509949, 0.49995, 0., -0.9999, 0.],
+        );
+    }
+
+    #[test]
+    fn pd_limit() {
+        // `iir_coefficients.py pid --Kp 2 --Kd 6.283e-4 --Kd_limit 10`
+        let pid = Pid {
+            kp: 2.,
+            kd: 1e-4,
+            kd_limit: Some(10.),
+            ..Default::default()
+        };
+        assert_close(design(Filter::Pid(pid)), [6., -5., 0., -0.5, 0.]);
+    }
+
+    #[test]
+    fn pii_limit() {
+        // `iir_coefficients.py pid --Kii 25330.3 --Kii_limit 1000 --Ki 159.155
+        // --Kp 1`
+        let pid = Pid {
+            kii: 1e6,
+            kii_limit: Some(1e3),
+            ki: 1e3,
+            kp: 1.,
+            ..Default::default()
+        };
+        assert_close(
+            design(Filter::Pid(pid)),
+            [1.0100999, -2.0099998, 0.9999999, -1.9999998, 0.9999999],
+        );
+    }
+
+    #[test]
+    fn lowpass() {
+        let ba = design(Filter::Lowpass(second_order(1e3)));
+        // The DC gain is limited by the f32 resolution of the poles close to DC.
+        assert!((gain(ba, 0.) - 2.).abs() < 1e-4);
+        assert!(gain(ba, core::f32::consts::PI) < 1e-5);
+    }
+
+    #[test]
+    fn highpass() {
+        let ba = design(Filter::Highpass(second_order(1e3)));
+        assert!(gain(ba, 0.) < 1e-5);
+        assert!((gain(ba, core::f32::consts::PI) - 2.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn notch() {
+        // `iir_coefficients.py notch --f0 1000 --Q 2 --K 1`
+        let ba = design(Filter::Notch(SecondOrder {
+            frequency: 1e3,
+            q: 2.,
+            gain: 1.,
+        }));
+        assert_close(ba, [0.98455, -1.9652169, 0.98455, -1.9652169, 0.9690999]);
+        assert!((gain(ba, 0.) - 1.).abs() < 1e-4);
+        // The bilinear transform maps the center frequency to `2 atan(π f0 T)`.
+        let w = 2. * libm::atanf(core::f32::consts::PI * 1e3 * SAMPLE_PERIOD);
+        assert!(gain(ba, w) < 1e-3);
+    }
+
+    #[test]
+    fn errors() {
+        let pid = |pid| {
+            Design {
+                filter: Filter::Pid(pid),
+                ..Default::default()
+            }
+            .biquad(SAMPLE_PERIOD, 1.)
+        };
+        assert_eq!(
+            pid(Pid {
+                kii: 1.,
+                kd: 1.,
+                ..Default::default()
+            })
+            .unwrap_err(),
+            Error::Unsupported
+        );
+        assert_eq!(
+            pid(Pid {
+                ki: 1.,
+                kdd: 1.,
+                ..Default::default()
+            })
+            .unwrap_err(),
+            Error::Unsupported
+        );
+        assert_eq!(
+            pid(Pid {
+                ki: 1.,
+                ki_limit: Some(0.),
+                ..Default::default()
+            })
+            .unwrap_err(),
+            Error::Limit
+        );
+    }
+}
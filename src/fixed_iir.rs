@@ -0,0 +1,303 @@
+//! Fixed-point biquad IIR filter
+//!
+//! # Design
+//! The filter uses integer arithmetic only. Its behavior is bit-exact and deterministic and its
+//! per-sample cost does not depend on the signal.
+//!
+//! Coefficients are signed fixed-point numbers with 24 fractional bits (resolution 2^-24). Their
+//! magnitude is limited to below 32 (see [COEFFICIENT_LIMIT]). Signals are ADC/DAC codes with 8
+//! fractional bits. Products are accumulated in 64 bits and the result is rounded once per
+//! section. The fractional signal bits allow slow integrators to accumulate errors well below
+//! one LSB.
+//!
+//! The coefficient limit bounds the accumulator: the five products of full scale `i32` signals
+//! and coefficients below 2^29 each stay below 2^60. Together with the offset (below 2^55) and the
+//! rounding term their sum can not overflow an `i64`.
+//!
+//! Filters are designed as floating point [iir::Biquad] and converted with [Biquad::try_from].
+use idsp::iir;
+
+/// The number of fractional bits of the coefficients.
+pub const COEFFICIENT_SHIFT: u32 = 24;
+
+/// The number of fractional bits of the signals.
+pub const SIGNAL_SHIFT: u32 = 8;
+
+/// The exclusive limit of the coefficient magnitudes in fixed-point (32 in floating point).
+pub const COEFFICIENT_LIMIT: i32 = 1 << 29;
+
+/// Errors that can occur when converting a floating point biquad.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// A coefficient magnitude is not below [COEFFICIENT_LIMIT].
+    Coefficient,
+}
+
+/// Convert a signal in codes to fixed-point. The result saturates.
+#[inline]
+pub fn to_fixed(x: f32) -> i32 {
+    (x * (1 << SIGNAL_SHIFT) as f32) as i32
+}
+
+/// Convert a fixed-point signal to codes.
+#[inline]
+pub fn from_fixed(y: i32) -> f32 {
+    y as f32 * (1. / (1 << SIGNAL_SHIFT) as f32)
+}
+
+/// Convert an integer code to a fixed-point signal.
+#[inline]
+pub fn from_code(code: i16) -> i32 {
+    (code as i32) << SIGNAL_SHIFT
+}
+
+/// Round a fixed-point signal to an integer code. The result is not limited to the `i16` range.
+#[inline]
+pub fn to_code(y: i32) -> i32 {
+    y.saturating_add(1 << (SIGNAL_SHIFT - 1)) >> SIGNAL_SHIFT
+}
+
+/// A fixed-point biquad section.
+///
+/// The output is computed as `y0 = u + b0*x0 + b1*x1 + b2*x2 - a1*y1 - a2*y2` and clamped to
+/// `[min, max]`, with the same coefficient convention as [iir::Biquad].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Biquad {
+    ba: [i32; 5],
+    u: i32,
+    min: i32,
+    max: i32,
+}
+
+impl Default for Biquad {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Biquad {
+    /// A unity gain filter.
+    pub const IDENTITY: Self = Self {
+        ba: [1 << COEFFICIENT_SHIFT, 0, 0, 0, 0],
+        u: 0,
+        min: i32::MIN,
+        max: i32::MAX,
+    };
+
+    /// A filter that holds its output.
+    pub const HOLD: Self = Self {
+        ba: [0, 0, 0, -(1 << COEFFICIENT_SHIFT), 0],
+        u: 0,
+        min: i32::MIN,
+        max: i32::MAX,
+    };
+
+    /// The fixed-point coefficients `[b0, b1, b2, a1, a2]`.
+    pub fn ba(&self) -> &[i32; 5] {
+        &self.ba
+    }
+
+    /// The fixed-point lower output limit.
+    pub fn min(&self) -> i32 {
+        self.min
+    }
+
+    /// The fixed-point upper output limit.
+    pub fn max(&self) -> i32 {
+        self.max
+    }
+
+    /// Whether the filter has a pole at DC, i.e. integrates.
+    pub fn is_integrator(&self) -> bool {
+        (1 << COEFFICIENT_SHIFT) + self.ba[3] as i64 + self.ba[4] as i64 == 0
+    }
+
+    /// Process a sample.
+    ///
+    /// # Args
+    /// * `xy` - The filter state `[x1, x2, y1, y2]`, fixed-point.
+    /// * `x0` - The input sample, fixed-point.
+    ///
+    /// # Returns
+    /// The output sample, fixed-point.
+    #[inline]
+    pub fn update(&self, xy: &mut [i32; 4], x0: i32) -> i32 {
+        self.update_clip(xy, x0).0
+    }
+
+    /// Process a sample and report clipping.
+    ///
+    /// # Args
+    /// * `xy` - The filter state `[x1, x2, y1, y2]`, fixed-point.
+    /// * `x0` - The input sample, fixed-point.
+    ///
+    /// # Returns
+    /// The output sample, fixed-point, and whether the unclamped output was outside of
+    /// `[min, max]`. An output exactly at a limit is not clipped.
+    #[inline]
+    pub fn update_clip(&self, xy: &mut [i32; 4], x0: i32) -> (i32, bool) {
+        let [b0, b1, b2, a1, a2] = self.ba.map(|c| c as i64);
+        let acc = ((self.u as i64) << COEFFICIENT_SHIFT)
+            + (1 << (COEFFICIENT_SHIFT - 1))
+            + b0 * x0 as i64
+            + b1 * xy[0] as i64
+            + b2 * xy[1] as i64
+            - a1 * xy[2] as i64
+            - a2 * xy[3] as i64;
+        let y = acc >> COEFFICIENT_SHIFT;
+        let y0 = y.clamp(self.min as i64, self.max as i64) as i32;
+        *xy = [x0, xy[0], y0, xy[2]];
+        (y0, y != y0 as i64)
+    }
+}
+
+impl TryFrom<&iir::Biquad<f32>> for Biquad {
+    type Error = Error;
+
+    fn try_from(biquad: &iir::Biquad<f32>) -> Result<Self, Error> {
+        const SCALE: f32 = (1u64 << COEFFICIENT_SHIFT) as f32;
+        const LIMIT: f32 = COEFFICIENT_LIMIT as f32;
+
+        let mut ba = [0; 5];
+        for (c, &f) in ba.iter_mut().zip(biquad.ba().iter()) {
+            let scaled = libm::roundf(f * SCALE);
+            if !(scaled > -LIMIT && scaled < LIMIT) {
+                return Err(Error::Coefficient);
+            }
+            *c = scaled as i32;
+        }
+
+        Ok(Self {
+            ba,
+            u: to_fixed(biquad.u()),
+            min: to_fixed(biquad.min()),
+            max: to_fixed(biquad.max()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::filter_design::{Design, Filter, SecondOrder};
+    use core::f32::consts::{FRAC_1_SQRT_2, PI};
+
+    // The corner frequency is at 1/32 of the sample rate.
+    const SAMPLE_PERIOD: f32 = 1e-6;
+    const FREQUENCY: f32 = 31.25e3;
+    const PERIOD: usize = 32;
+
+    fn lowpass(gain: f32, limit: f32) -> iir::Biquad<f32> {
+        Design {
+            filter: Filter::Lowpass(SecondOrder {
+                frequency: FREQUENCY,
+                q: FRAC_1_SQRT_2,
+                gain,
+            }),
+            offset: 0.,
+            min: -limit,
+            max: limit,
+        }
+        .biquad(SAMPLE_PERIOD, 1.)
+        .unwrap()
+    }
+
+    // Run the floating-point filter and its fixed-point conversion on the same input codes.
+    // Returns the outputs of both in codes.
+    fn run(
+        biquad: &iir::Biquad<f32>,
+        x: impl Iterator<Item = i16>,
+    ) -> (Vec<f32>, Vec<f32>) {
+        let fixed = Biquad::try_from(biquad).unwrap();
+        let mut xy = [0.; 4];
+        let mut xy_fixed = [0; 4];
+        x.map(|x| {
+            (
+                biquad.update(&mut xy, x as f32),
+                from_fixed(fixed.update(&mut xy_fixed, from_code(x))),
+            )
+        })
+        .unzip()
+    }
+
+    // The amplitude of the corner frequency component of the last period of samples.
+    fn amplitude(y: &[f32]) -> f32 {
+        let (i, q) = y[y.len() - PERIOD..].iter().enumerate().fold(
+            (0., 0.),
+            |(i, q), (n, y)| {
+                let phase = 2. * PI * n as f32 / PERIOD as f32;
+                (i + y * libm::cosf(phase), q + y * libm::sinf(phase))
+            },
+        );
+        2. / PERIOD as f32 * libm::sqrtf(i * i + q * q)
+    }
+
+    #[test]
+    fn dc_gain() {
+        let (y, y_fixed) = run(&lowpass(2., 1e4), (0..1000).map(|_| 1000));
+        assert!((y[999] - 2000.).abs() < 0.1);
+        assert!((y_fixed[999] - y[999]).abs() < 0.1);
+    }
+
+    #[test]
+    fn corner_gain() {
+        let x = (0..100 * PERIOD).map(|n| {
+            let phase = 2. * PI * (n % PERIOD) as f32 / PERIOD as f32;
+            libm::roundf(10_000. * libm::sinf(phase)) as i16
+        });
+        let (y, y_fixed) = run(&lowpass(1., 1e4), x);
+        let gain = amplitude(&y) / 10_000.;
+        let gain_fixed = amplitude(&y_fixed) / 10_000.;
+        assert!((gain - FRAC_1_SQRT_2).abs() < 1e-2);
+        assert!((gain_fixed - gain).abs() < 1e-4);
+    }
+
+    #[test]
+    fn saturation() {
+        // Steps to ±4000 codes at the output, limited to ±1000.
+        let x = (0..2000).map(|n| if n < 1000 { 1000 } else { -1000 });
+        let (y, y_fixed) = run(&lowpass(4., 1000.), x);
+        assert_eq!(y.iter().cloned().fold(f32::MIN, f32::max), 1000.);
+        assert_eq!(y.iter().cloned().fold(f32::MAX, f32::min), -1000.);
+        assert_eq!([y_fixed[999], y_fixed[1999]], [1000., -1000.]);
+        for (y, y_fixed) in y.iter().zip(y_fixed.iter()) {
+            assert!((y - y_fixed).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn clip() {
+        let biquad = Biquad {
+            min: from_code(-i16::MAX),
+            max: from_code(i16::MAX),
+            ..Biquad::IDENTITY
+        };
+        let mut xy = [0; 4];
+        assert_eq!(
+            biquad.update_clip(&mut xy, from_code(i16::MIN)),
+            (from_code(-i16::MAX), true)
+        );
+        assert_eq!(
+            biquad.update_clip(&mut xy, from_code(-i16::MAX)),
+            (from_code(-i16::MAX), false)
+        );
+        assert_eq!(
+            biquad.update_clip(&mut xy, from_code(i16::MAX)),
+            (from_code(i16::MAX), false)
+        );
+        assert_eq!(
+            biquad.update_clip(&mut xy, from_code(i16::MAX) + 1),
+            (from_code(i16::MAX), true)
+        );
+    }
+
+    #[test]
+    fn coefficient_range() {
+        let ba = |c| iir::Biquad::from([1., 0., 0., c, 0.]);
+        assert!(Biquad::try_from(&ba(31.9)).is_ok());
+        assert!(Biquad::try_from(&ba(-31.9)).is_ok());
+        assert_eq!(Biquad::try_from(&ba(32.)), Err(Error::Coefficient));
+        assert_eq!(Biquad::try_from(&ba(-32.)), Err(Error::Coefficient));
+        assert_eq!(Biquad::try_from(&ba(f32::NAN)), Err(Error::Coefficient));
+    }
+}
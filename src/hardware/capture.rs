@@ -0,0 +1,398 @@
+//! Triggered transient capture
+//!
+//! # Design
+//! Data streaming is best-effort and drops frames under load. The capture instead records a
+//! fixed number of samples of two signals into a ring buffer in on-chip RAM so that rare events
+//! can be inspected reliably after the fact.
+//!
+//! Once armed, the DSP routine continuously records into the ring buffer. After the requested
+//! pre-trigger depth has been filled, the trigger condition is evaluated for every sample. When it
+//! fires, recording continues for the remaining post-trigger samples and the buffer is then
+//! frozen and handed out as a [Record]. There are two buffers: the most recent record holds one
+//! of them until the next capture has completed in the other one, so a capture can be read out
+//! (and read out again) at leisure by a lower priority task and is not lost if the next capture
+//! is aborted.
+//!
+//! The signals recorded are selected by a probe `P` that is opaque to the capture. It is part of
+//! the [Request] and handed back to the DSP routine while the capture is active, see
+//! [Capture::probe].
+//!
+//! A [Link] is used to hand requests and records between the DSP routine and the lower priority
+//! task.
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+use serde::{Deserialize, Serialize};
+
+/// The maximum number of samples recorded per channel.
+pub const DEPTH: usize = 1 << 14;
+
+/// A capture buffer. The dimensions are `[channel][sample]`.
+pub type Buffer = [[i16; DEPTH]; 2];
+
+// The capture buffers. Data in AXI SRAM is not initialized on boot, so the contents are random.
+// The buffers are initialized when they are taken.
+#[link_section = ".axisram.capture"]
+static mut BUFFERS: MaybeUninit<[Buffer; 2]> = MaybeUninit::uninit();
+
+static TAKEN: AtomicBool = AtomicBool::new(false);
+
+/// The signal that the trigger condition is evaluated on.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Source {
+    /// Trigger as soon as the pre-trigger depth has been recorded.
+    Software,
+    /// The recorded signal of capture channel 0.
+    Channel0,
+    /// The recorded signal of capture channel 1.
+    Channel1,
+    /// The DI0 digital input.
+    Di0,
+    /// The DI1 digital input.
+    Di1,
+}
+
+/// The trigger condition.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Condition {
+    /// Trigger while the signal is beyond the level (above for rising, below for falling).
+    Level,
+    /// Trigger when the signal crosses the level in the direction of the slope.
+    Edge,
+}
+
+/// The trigger slope.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Slope {
+    /// Rising signal or high digital input.
+    Rising,
+    /// Falling signal or low digital input.
+    Falling,
+}
+
+/// A capture request.
+#[derive(Copy, Clone, Debug)]
+pub struct Request<P> {
+    /// The probe selecting the recorded signals.
+    pub probe: P,
+
+    /// The trigger source.
+    pub source: Source,
+
+    /// The trigger condition.
+    pub condition: Condition,
+
+    /// The trigger slope.
+    pub slope: Slope,
+
+    /// The trigger level in codes of the source channel. Unused for digital inputs.
+    pub level: i16,
+
+    /// The number of samples recorded before the trigger.
+    pub pre_trigger: usize,
+
+    /// The total number of samples recorded per channel.
+    pub length: usize,
+}
+
+impl<P> Request<P> {
+    /// Validate the request.
+    ///
+    /// # Returns
+    /// The request or `None` if the length exceeds the buffer [DEPTH] or the pre-trigger depth
+    /// exceeds the length.
+    pub fn validate(self) -> Option<Self> {
+        (self.length > 0
+            && self.length <= DEPTH
+            && self.pre_trigger <= self.length)
+            .then_some(self)
+    }
+}
+
+/// A completed capture.
+pub struct Record {
+    buffer: &'static mut Buffer,
+    start: usize,
+    length: usize,
+    pre_trigger: usize,
+}
+
+impl Record {
+    /// The number of samples recorded per channel.
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    /// Whether the record is empty.
+    pub fn is_empty(&self) -> bool {
+        self.length == 0
+    }
+
+    /// The number of samples recorded before the trigger.
+    pub fn pre_trigger(&self) -> usize {
+        self.pre_trigger
+    }
+
+    /// Copy recorded samples.
+    ///
+    /// # Args
+    /// * `offset` - The index of the first sample to copy, relative to the start of the record.
+    /// * `data` - The destination of the samples of each channel.
+    ///
+    /// # Returns
+    /// The number of samples copied per channel.
+    pub fn copy(&self, offset: usize, data: [&mut [i16]; 2]) -> usize {
+        let mut copied = 0;
+        for (channel, data) in self.buffer.iter().zip(data) {
+            copied = data.len().min(self.length.saturating_sub(offset));
+            for (i, value) in data[..copied].iter_mut().enumerate() {
+                *value = channel[(self.start + offset + i) % DEPTH];
+            }
+        }
+        copied
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    Idle,
+    Armed,
+    Triggered,
+}
+
+/// The transient capture state in the DSP routine.
+pub struct Capture<P> {
+    buffer: Option<&'static mut Buffer>,
+    spare: Option<&'static mut Buffer>,
+    request: Option<Request<P>>,
+    state: State,
+    index: usize,
+    recorded: usize,
+    remaining: usize,
+    previous: Option<bool>,
+    force: bool,
+}
+
+impl<P: Copy> Capture<P> {
+    /// Take the capture and its buffers.
+    ///
+    /// # Returns
+    /// The capture or `None` if it has already been taken.
+    pub fn take() -> Option<Self> {
+        if TAKEN.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+
+        // Note(unsafe): The buffers are only taken once. They are cleared in place as they are
+        // too large for the stack.
+        let [buffer, spare] = unsafe {
+            let buffers = &mut *core::ptr::addr_of_mut!(BUFFERS);
+            buffers.as_mut_ptr().write_bytes(0, 1);
+            buffers.assume_init_mut()
+        };
+
+        Some(Self {
+            buffer: Some(buffer),
+            spare: Some(spare),
+            request: None,
+            state: State::Idle,
+            index: 0,
+            recorded: 0,
+            remaining: 0,
+            previous: None,
+            force: false,
+        })
+    }
+
+    /// Whether the capture is armed or triggered and recording.
+    pub fn is_active(&self) -> bool {
+        self.state != State::Idle
+    }
+
+    /// The probe of the active capture.
+    ///
+    /// # Returns
+    /// The probe of the request or `None` if the capture is not armed or triggered.
+    pub fn probe(&self) -> Option<P> {
+        self.request
+            .as_ref()
+            .filter(|_| self.is_active())
+            .map(|request| request.probe)
+    }
+
+    /// Arm a new capture.
+    ///
+    /// # Returns
+    /// The request if both buffers are held by [Record]s and the capture could not be armed.
+    pub fn arm(&mut self, request: Request<P>) -> Option<Request<P>> {
+        if self.buffer.is_none() {
+            self.buffer = self.spare.take();
+        }
+        if self.buffer.is_none() {
+            return Some(request);
+        }
+        self.request = Some(request);
+        self.state = State::Armed;
+        self.index = 0;
+        self.recorded = 0;
+        self.remaining = request.length - request.pre_trigger;
+        self.previous = None;
+        self.force = false;
+        None
+    }
+
+    /// Trigger the armed capture as soon as the pre-trigger depth has been recorded.
+    pub fn force(&mut self) {
+        self.force = true;
+    }
+
+    /// Abort the active capture. No record is produced.
+    pub fn disarm(&mut self) {
+        self.request = None;
+        self.state = State::Idle;
+        self.force = false;
+    }
+
+    /// Return the buffer of a record for re-use.
+    pub fn restore(&mut self, record: Record) {
+        if self.buffer.is_none() {
+            self.buffer = Some(record.buffer);
+        } else {
+            self.spare = Some(record.buffer);
+        }
+    }
+
+    /// Take the completed record, if any.
+    pub fn take_record(&mut self) -> Option<Record> {
+        let request = self.request.as_ref()?;
+        if self.state != State::Idle {
+            return None;
+        }
+        let buffer = self.buffer.take()?;
+        let length = request.length;
+        let pre_trigger = request.pre_trigger;
+        self.request = None;
+        Some(Record {
+            buffer,
+            start: (self.index + DEPTH - length) % DEPTH,
+            length,
+            pre_trigger,
+        })
+    }
+
+    /// Record a sample.
+    ///
+    /// # Args
+    /// * `values` - The signal codes of each channel.
+    /// * `digital_inputs` - The DI0 and DI1 digital input states.
+    pub fn record(&mut self, values: [i16; 2], digital_inputs: [bool; 2]) {
+        let (Some(request), Some(buffer)) =
+            (self.request.as_ref(), self.buffer.as_mut())
+        else {
+            return;
+        };
+
+        match self.state {
+            State::Idle => return,
+            State::Armed => {
+                let beyond = |value: i16| match request.slope {
+                    Slope::Rising => value > request.level,
+                    Slope::Falling => value < request.level,
+                };
+                let active = match request.source {
+                    Source::Software => true,
+                    Source::Channel0 => beyond(values[0]),
+                    Source::Channel1 => beyond(values[1]),
+                    Source::Di0 => {
+                        digital_inputs[0] == (request.slope == Slope::Rising)
+                    }
+                    Source::Di1 => {
+                        digital_inputs[1] == (request.slope == Slope::Rising)
+                    }
+                };
+                let previous = self.previous.replace(active);
+                let fire = match (request.source, request.condition) {
+                    (Source::Software, _) | (_, Condition::Level) => active,
+                    (_, Condition::Edge) => active && previous == Some(false),
+                };
+                if self.recorded >= request.pre_trigger && (fire || self.force)
+                {
+                    if self.remaining == 0 {
+                        self.state = State::Idle;
+                        return;
+                    }
+                    self.state = State::Triggered;
+                }
+            }
+            State::Triggered => {}
+        }
+
+        for (channel, value) in buffer.iter_mut().zip(values) {
+            channel[self.index] = value;
+        }
+        self.index = (self.index + 1) % DEPTH;
+
+        if self.state == State::Triggered {
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.state = State::Idle;
+            }
+        } else {
+            self.recorded = self.recorded.saturating_add(1);
+        }
+    }
+}
+
+/// Requests and records exchanged between the DSP routine and a lower priority task.
+pub struct Link<P> {
+    /// The next capture to arm.
+    pub request: Option<Request<P>>,
+
+    /// Force a trigger of the armed capture.
+    pub force: bool,
+
+    /// Abort the armed capture. The most recent record is retained.
+    pub abort: bool,
+
+    /// Set when a capture has completed and its record has replaced the previous one.
+    pub completed: bool,
+
+    /// The most recently completed capture.
+    pub record: Option<Record>,
+}
+
+impl<P> Default for Link<P> {
+    fn default() -> Self {
+        Self {
+            request: None,
+            force: false,
+            abort: false,
+            completed: false,
+            record: None,
+        }
+    }
+}
+
+impl<P: Copy> Link<P> {
+    /// Exchange requests and records with the capture.
+    pub fn exchange(&mut self, capture: &mut Capture<P>) {
+        // Abort before taking the record so that no record completes after an abort.
+        if core::mem::take(&mut self.abort) {
+            capture.disarm();
+        }
+
+        if let Some(record) = capture.take_record() {
+            if let Some(previous) = self.record.replace(record) {
+                capture.restore(previous);
+            }
+            self.completed = true;
+        }
+
+        if let Some(request) = self.request.take() {
+            self.request = capture.arm(request);
+        }
+
+        if core::mem::take(&mut self.force) {
+            capture.force();
+        }
+    }
+}
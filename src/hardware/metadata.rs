@@ -1,4 +1,4 @@
-use crate::hardware::HardwareVersion;
+use crate::{hardware::HardwareVersion, settings::SamplingSettings};
 use serde::Serialize;
 
 mod build_info {
@@ -14,6 +14,8 @@ pub struct ApplicationMetadata {
     pub features: &'static str,
     pub panic_info: &'static str,
     pub hardware_version: HardwareVersion,
+    pub sample_period: f32,
+    pub batch_size: usize,
 }
 
 impl ApplicationMetadata {
@@ -24,10 +26,14 @@ impl ApplicationMetadata {
     ///
     /// # Args
     /// * `hardware_version` - The hardware version detected.
+    /// * `sampling` - The applied sampling configuration.
     ///
     /// # Returns
     /// A reference to the global metadata.
-    pub fn new(version: HardwareVersion) -> &'static ApplicationMetadata {
+    pub fn new(
+        version: HardwareVersion,
+        sampling: &SamplingSettings,
+    ) -> &'static ApplicationMetadata {
         cortex_m::singleton!(: ApplicationMetadata = ApplicationMetadata {
             firmware_version: build_info::GIT_VERSION.unwrap_or("Unspecified"),
             rust_version: build_info::RUSTC_VERSION,
@@ -35,6 +41,8 @@ impl ApplicationMetadata {
             git_dirty: build_info::GIT_DIRTY.unwrap_or(false),
             features: build_info::FEATURES_STR,
             hardware_version: version,
+            sample_period: sampling.sample_period(),
+            batch_size: sampling.batch_size(),
             panic_info: panic_persist::get_panic_message_utf8().unwrap_or("None"),
         }).unwrap()
     }
@@ -5,6 +5,7 @@ pub use stm32h7xx_hal as hal;
 
 pub mod adc;
 pub mod afe;
+pub mod capture;
 pub mod cpu_temp_sensor;
 pub mod dac;
 pub mod delay;
@@ -13,6 +14,7 @@ mod eeprom;
 pub mod flash;
 pub mod input_stamper;
 pub mod metadata;
+pub mod network_analyzer;
 pub mod platform;
 pub mod pounder;
 pub mod setup;
@@ -155,6 +157,7 @@ impl serde::Serialize for HardwareVersion {
     }
 }
 
+#[cfg(not(test))]
 #[inline(never)]
 #[panic_handler]
 fn panic(info: &core::panic::PanicInfo) -> ! {
@@ -0,0 +1,251 @@
+//! Swept-sine network analyzer
+//!
+//! # Design
+//! The network analyzer measures a transfer function one frequency point at a time. For each
+//! point, a sine excitation is generated and two signals (the reference and the response) are
+//! demodulated at the excitation frequency. After a settling period, the demodulated signals are
+//! accumulated over an integer number of excitation periods. The complex ratio of the response
+//! and reference amplitudes is the transfer function at that frequency.
+//!
+//! The excitation and demodulation run in the DSP routine. A [Link] is used to hand measurement
+//! requests and results between the DSP routine and a lower priority task that steps through the
+//! frequency points and reports the results.
+use serde::Serialize;
+
+/// A single network analyzer measurement request.
+#[derive(Copy, Clone, Debug)]
+pub struct Measurement {
+    /// The excitation frequency tuning word (phase increment per sample).
+    pub ftw: i32,
+
+    /// The excitation amplitude in DAC codes.
+    pub amplitude: i16,
+
+    /// The number of samples to wait before accumulating.
+    pub settle: u32,
+
+    /// The number of samples to accumulate.
+    pub samples: u32,
+}
+
+impl Measurement {
+    /// Construct a measurement request in physical units.
+    ///
+    /// # Args
+    /// * `frequency` - The excitation frequency in Hertz.
+    /// * `amplitude` - The excitation amplitude in DAC codes.
+    /// * `settle_time` - The settling time in seconds.
+    /// * `integration_time` - The minimum integration time in seconds. The integration is extended
+    ///   to an integer number of excitation periods.
+    /// * `sample_period` - The time in seconds between samples.
+    ///
+    /// # Returns
+    /// The measurement or `None` if the frequency is not between zero and Nyquist.
+    pub fn new(
+        frequency: f32,
+        amplitude: i16,
+        settle_time: f32,
+        integration_time: f32,
+        sample_period: f32,
+    ) -> Option<Self> {
+        const FULL_TURN: f32 = (1u64 << 32) as _;
+        let turns_per_sample = frequency * sample_period;
+        if !(turns_per_sample > 0. && turns_per_sample < 0.5) {
+            return None;
+        }
+
+        let periods =
+            libm::ceilf(integration_time / sample_period * turns_per_sample)
+                .max(1.);
+
+        Some(Self {
+            ftw: (turns_per_sample * FULL_TURN) as i32,
+            amplitude,
+            settle: (settle_time / sample_period) as u32,
+            samples: libm::roundf(periods / turns_per_sample) as u32,
+        })
+    }
+}
+
+/// Accumulated demodulation results of a measurement.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Demodulation {
+    /// The demodulated reference signal (in-phase, quadrature).
+    pub reference: [i64; 2],
+
+    /// The demodulated response signal (in-phase, quadrature).
+    pub response: [i64; 2],
+
+    /// The accumulated response power.
+    pub power: i64,
+
+    /// The number of accumulated samples.
+    pub count: u32,
+}
+
+/// A measured transfer function point.
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Point {
+    /// The excitation frequency in Hertz.
+    pub frequency: f32,
+
+    /// The magnitude of the response relative to the reference.
+    pub magnitude: f32,
+
+    /// The phase of the response relative to the reference in turns.
+    pub phase: f32,
+
+    /// The fraction of the response power at the excitation frequency, between 0 and 1.
+    pub coherence: f32,
+}
+
+impl Demodulation {
+    /// Compute the transfer function point.
+    ///
+    /// # Args
+    /// * `frequency` - The excitation frequency in Hertz.
+    /// * `reference_scale` - The reference signal units per code, e.g. volts per LSB.
+    /// * `response_scale` - The response signal units per code.
+    pub fn point(
+        &self,
+        frequency: f32,
+        reference_scale: f32,
+        response_scale: f32,
+    ) -> Point {
+        let reference = [
+            self.reference[0] as f32 * reference_scale,
+            self.reference[1] as f32 * reference_scale,
+        ];
+        let response = [
+            self.response[0] as f32 * response_scale,
+            self.response[1] as f32 * response_scale,
+        ];
+
+        // response / reference
+        let norm = reference[0] * reference[0] + reference[1] * reference[1];
+        let ratio = [
+            (response[0] * reference[0] + response[1] * reference[1]) / norm,
+            (response[1] * reference[0] - response[0] * reference[1]) / norm,
+        ];
+
+        // The demodulated amplitudes are scaled by 2^15 by the local oscillator. A pure sine
+        // response results in a coherence of unity.
+        let iq = [self.response[0] as f32, self.response[1] as f32];
+        let amplitude = iq[0] * iq[0] + iq[1] * iq[1];
+        let coherence = 2. * amplitude
+            / (self.power as f32 * self.count as f32 * (1u32 << 30) as f32);
+
+        Point {
+            frequency,
+            magnitude: libm::sqrtf(ratio[0] * ratio[0] + ratio[1] * ratio[1]),
+            phase: libm::atan2f(ratio[1], ratio[0])
+                * core::f32::consts::FRAC_1_PI
+                / 2.,
+            coherence,
+        }
+    }
+}
+
+/// The run-time network analyzer excitation and demodulation.
+#[derive(Debug, Default)]
+pub struct NetworkAnalyzer {
+    measurement: Option<Measurement>,
+    phase: i32,
+    // The local oscillator (cosine, sine) at the current sample.
+    lo: (i32, i32),
+    settle: u32,
+    remaining: u32,
+    accumulator: Demodulation,
+    result: Option<Demodulation>,
+}
+
+impl NetworkAnalyzer {
+    /// Start a new measurement, aborting any measurement in progress.
+    pub fn start(&mut self, measurement: Measurement) {
+        *self = Self {
+            measurement: Some(measurement),
+            lo: (i32::MAX, 0),
+            settle: measurement.settle,
+            remaining: measurement.samples,
+            ..Default::default()
+        };
+    }
+
+    /// Whether a measurement is in progress.
+    pub fn is_active(&self) -> bool {
+        self.measurement.is_some()
+    }
+
+    /// The excitation at the current sample in DAC codes.
+    #[inline]
+    pub fn excitation(&self) -> i16 {
+        self.measurement.map_or(0, |measurement| {
+            ((measurement.amplitude as i32 * (self.lo.1 >> 16)) >> 15) as _
+        })
+    }
+
+    /// Demodulate the current sample and advance to the next sample.
+    ///
+    /// # Args
+    /// * `reference` - The reference signal code at the current sample.
+    /// * `response` - The response signal code at the current sample.
+    #[inline]
+    pub fn demodulate(&mut self, reference: i16, response: i16) {
+        let Some(measurement) = self.measurement else {
+            return;
+        };
+
+        if self.settle > 0 {
+            self.settle -= 1;
+        } else {
+            let lo = [self.lo.0 >> 16, -(self.lo.1 >> 16)];
+            let acc = &mut self.accumulator;
+            for (i, lo) in lo.into_iter().enumerate() {
+                acc.reference[i] += (reference as i32 * lo) as i64;
+                acc.response[i] += (response as i32 * lo) as i64;
+            }
+            acc.power += (response as i32 * response as i32) as i64;
+            acc.count += 1;
+
+            self.remaining -= 1;
+            if self.remaining == 0 {
+                self.result = Some(self.accumulator);
+                self.measurement = None;
+                self.lo = (i32::MAX, 0);
+                return;
+            }
+        }
+
+        self.phase = self.phase.wrapping_add(measurement.ftw);
+        self.lo = idsp::cossin(self.phase);
+    }
+
+    /// Take the result of the most recently completed measurement.
+    pub fn take_result(&mut self) -> Option<Demodulation> {
+        self.result.take()
+    }
+}
+
+/// Hand-over of measurement requests and results between the DSP routine and a lower priority
+/// task.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Link {
+    /// The next measurement to start.
+    pub request: Option<Measurement>,
+
+    /// The result of the most recently completed measurement.
+    pub result: Option<Demodulation>,
+}
+
+impl Link {
+    /// Exchange requests and results with the network analyzer.
+    pub fn exchange(&mut self, analyzer: &mut NetworkAnalyzer) {
+        if let Some(result) = analyzer.take_result() {
+            self.result = Some(result);
+        }
+
+        if let Some(request) = self.request.take() {
+            analyzer.start(request);
+        }
+    }
+}
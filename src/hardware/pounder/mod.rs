@@ -2,7 +2,7 @@ use self::attenuators::AttenuatorInterface;
 
 use super::hal;
 use crate::hardware::{shared_adc::AdcChannel, I2c1Proxy};
-use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::blocking::{i2c::WriteRead, spi::Transfer};
 use enum_iterator::Sequence;
 use serde::{Deserialize, Serialize};
 
@@ -341,6 +341,20 @@ enum IoExpander {
 }
 
 impl IoExpander {
+    /// I2C address of the PCA9539 with both address pins low.
+    const PCA9539_ADDRESS: u8 = 0x74;
+
+    /// Check whether either of the GPIO extender population options responds.
+    fn detect(i2c: &I2c1Proxy) -> bool {
+        let mut mcp23017 =
+            mcp230xx::Mcp230xx::new_default(i2c.clone()).unwrap();
+        mcp23017.read(0).is_ok()
+            || i2c
+                .clone()
+                .write_read(Self::PCA9539_ADDRESS, &[0], &mut [0])
+                .is_ok()
+    }
+
     fn new(i2c: I2c1Proxy) -> Self {
         // Population option on Pounder v1.2 and later.
         let mut mcp23017 =
@@ -426,6 +440,14 @@ pub struct PounderDevices {
 }
 
 impl PounderDevices {
+    /// Check whether Pounder is attached by probing its GPIO extender.
+    ///
+    /// Args:
+    /// * `i2c` - A Proxy to I2C1.
+    pub fn detect(i2c: &I2c1Proxy) -> bool {
+        IoExpander::detect(i2c)
+    }
+
     /// Construct and initialize pounder-specific hardware.
     ///
     /// Args:
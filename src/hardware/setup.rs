@@ -15,7 +15,7 @@ use stm32h7xx_hal::{
 
 use smoltcp_nal::smoltcp;
 
-use crate::settings::{AppSettings, NetSettings};
+use crate::settings::{AppSettings, NetSettings, SamplingSettings};
 
 use super::{
     adc, afe, cpu_temp_sensor::CpuTempSensor, dac, delay, design_parameters,
@@ -26,6 +26,9 @@ use super::{
     SerialTerminal, SystemTimer, Systick, UsbDevice, AFE0, AFE1,
 };
 
+#[cfg(feature = "pounder")]
+use super::pounder;
+
 const NUM_TCP_SOCKETS: usize = 4;
 const NUM_UDP_SOCKETS: usize = 1;
 const NUM_SOCKETS: usize = NUM_UDP_SOCKETS + NUM_TCP_SOCKETS;
@@ -109,6 +112,17 @@ pub struct EemGpioDevices {
     pub lvds7: EemDigitalOutput1,
 }
 
+/// The available Pounder-specific hardware interfaces.
+#[cfg(feature = "pounder")]
+pub struct PounderDevices {
+    pub pounder: pounder::PounderDevices,
+    pub dds_output: pounder::dds_output::DdsOutput,
+    /// The DDS clock ADC sample timestamper. `None` if the sampling configuration does not
+    /// support it.
+    #[cfg(not(feature = "pounder_v1_0"))]
+    pub timestamper: Option<pounder::timestamp::Timestamper>,
+}
+
 /// The available hardware interfaces on Stabilizer.
 pub struct StabilizerDevices<
     C: serial_settings::Settings<Y> + 'static,
@@ -118,8 +132,12 @@ pub struct StabilizerDevices<
     pub afes: (AFE0, AFE1),
     pub adcs: (adc::Adc0Input, adc::Adc1Input),
     pub dacs: (dac::Dac0Output, dac::Dac1Output),
-    pub gpio_dac_spi: GpioDacSpi,
+    /// The frontend offset DAC SPI. `None` if Pounder is present and uses SPI1.
+    pub gpio_dac_spi: Option<GpioDacSpi>,
     pub cpu_dac1: CpuDacOutput1,
+    /// The Pounder devices, if Pounder is present.
+    #[cfg(feature = "pounder")]
+    pub pounder: Option<PounderDevices>,
     pub timestamper: InputStamper,
     pub adc_dac_timer: timers::SamplingTimer,
     pub timestamp_timer: timers::TimestampTimer,
@@ -130,6 +148,8 @@ pub struct StabilizerDevices<
     pub usb: UsbDevice,
     pub metadata: &'static ApplicationMetadata,
     pub settings: C,
+    /// The applied sampling configuration.
+    pub sampling: SamplingSettings,
 }
 
 #[link_section = ".sram3.eth"]
@@ -194,8 +214,9 @@ fn load_itcm() {
 /// * `core` - The cortex-m peripherals.
 /// * `device` - The microcontroller peripherals to be configured.
 /// * `clock` - A `SystemTimer` implementing `Clock`.
-/// * `batch_size` - The size of each ADC/DAC batch.
-/// * `sample_ticks` - The number of timer ticks between each sample.
+///
+/// The ADC/DAC batch size and sample period are taken from the [SamplingSettings] loaded from
+/// flash.
 ///
 /// # Returns
 /// stabilizer where `stabilizer` is a `StabilizerDevices` structure containing all
@@ -204,8 +225,6 @@ pub fn setup<C, const Y: usize>(
     mut core: stm32h7xx_hal::stm32::CorePeripherals,
     device: stm32h7xx_hal::stm32::Peripherals,
     clock: SystemTimer,
-    batch_size: usize,
-    sample_ticks: u32,
 ) -> StabilizerDevices<C, Y>
 where
     C: serial_settings::Settings<Y>
@@ -315,13 +334,55 @@ where
     let dma_streams =
         hal::dma::dma::StreamsTuple::new(device.DMA1, ccdr.peripheral.DMA1);
 
-    // Verify that batch period does not exceed RTIC Monotonic timer period.
-    assert!(
-        (batch_size as u32 * sample_ticks) as f32
-            * design_parameters::TIMER_PERIOD
-            * (super::MONOTONIC_FREQUENCY as f32)
-            < 1.
-    );
+    // The settings are loaded before configuring the sampling timers and the ADC/DAC DMA as they
+    // contain the sampling configuration.
+    let mut eeprom_i2c = {
+        let sda = gpiof.pf0.into_alternate().set_open_drain();
+        let scl = gpiof.pf1.into_alternate().set_open_drain();
+        device.I2C2.i2c(
+            (scl, sda),
+            100.kHz(),
+            ccdr.peripheral.I2C2,
+            &ccdr.clocks,
+        )
+    };
+
+    let mac_addr = smoltcp::wire::EthernetAddress(eeprom::read_eui48(
+        &mut eeprom_i2c,
+        &mut delay,
+    ));
+    log::info!("EUI48: {}", mac_addr);
+
+    let (flash, mut settings) = {
+        let mut flash = {
+            let (_, flash_bank2) = device.FLASH.split();
+            super::flash::Flash(flash_bank2.unwrap())
+        };
+
+        let mut settings = C::new(NetSettings::new(mac_addr));
+        crate::settings::SerialSettingsPlatform::load(
+            &mut settings,
+            &mut flash,
+        );
+        (flash, settings)
+    };
+
+    // Verify that the sampling configuration is supported by the hardware.
+    let sampling = {
+        let sampling = *settings.sampling();
+        if let Err(err) = sampling.validate() {
+            log::error!(
+                "Invalid sampling settings {:?}: {}. Using defaults.",
+                sampling,
+                err
+            );
+            SamplingSettings::default()
+        } else {
+            sampling
+        }
+    };
+    let batch_size = sampling.batch_size();
+    let sample_ticks = sampling.sample_ticks();
 
     // Configure timer 2 to trigger conversions for the ADC
     let mut sampling_timer = {
@@ -562,7 +623,57 @@ where
         (dac0, dac1)
     };
 
-    let gpio_dac_spi = {
+    // Pounder is detected through its I2C GPIO expander.
+    #[cfg(feature = "pounder")]
+    let pounder_i2c = {
+        let sda = gpiob.pb7.into_alternate().set_open_drain();
+        let scl = gpiob.pb8.into_alternate().set_open_drain();
+        let i2c1 = device.I2C1.i2c(
+            (scl, sda),
+            400.kHz(),
+            ccdr.peripheral.I2C1,
+            &ccdr.clocks,
+        );
+        let i2c = shared_bus::new_atomic_check!(super::I2c1 = i2c1)
+            .unwrap()
+            .acquire_i2c();
+        if pounder::PounderDevices::detect(&i2c) {
+            log::info!("Found Pounder");
+            Some(i2c)
+        } else {
+            None
+        }
+    };
+
+    #[cfg(not(feature = "pounder"))]
+    let pounder_i2c: Option<super::I2c1Proxy> = None;
+
+    // SPI1 is routed to the EEM connector. It drives the AD5541 frontend offset DAC unless Pounder
+    // is present, which uses it for its attenuators.
+    #[cfg_attr(not(feature = "pounder"), allow(unused_variables))]
+    let (gpio_dac_spi, attenuator_spi) = if pounder_i2c.is_some() {
+        log::info!("SPI1 is used by Pounder, the offset DAC is unavailable");
+
+        let mosi = gpiod.pd7.into_alternate().speed(Speed::VeryHigh);
+        let miso = gpioa.pa6.into_alternate().speed(Speed::VeryHigh);
+        let sck = gpiog.pg11.into_alternate().speed(Speed::VeryHigh);
+
+        let config = hal::spi::Config::new(hal::spi::Mode {
+            polarity: hal::spi::Polarity::IdleHigh,
+            phase: hal::spi::Phase::CaptureOnSecondTransition,
+        });
+
+        // The maximum frequency of this SPI must be limited due to capacitance on the MISO line
+        // causing a long RC decay.
+        let spi: hal::spi::Spi<_, _, u8> = device.SPI1.spi(
+            (sck, miso, mosi),
+            config,
+            5.MHz(),
+            ccdr.peripheral.SPI1,
+            &ccdr.clocks,
+        );
+        (None, Some(spi))
+    } else {
         let mosi = gpiod.pd7.into_alternate().speed(Speed::VeryHigh);
         let sck = gpiog.pg11.into_alternate().speed(Speed::VeryHigh);
         let nss = gpiog.pg10.into_alternate().speed(Speed::VeryHigh);
@@ -578,13 +689,14 @@ where
         })
         .communication_mode(hal::spi::CommunicationMode::Transmitter);
 
-        device.SPI1.spi(
+        let spi: GpioDacSpi = device.SPI1.spi(
             (sck, hal::spi::NoMiso, mosi, nss),
             config,
             design_parameters::AD5541_DAC_SCK_MAX.convert(),
             ccdr.peripheral.SPI1,
             &ccdr.clocks,
-        )
+        );
+        (Some(spi), None)
     };
 
     let afes = {
@@ -617,17 +729,6 @@ where
         (di0, di1)
     };
 
-    let mut eeprom_i2c = {
-        let sda = gpiof.pf0.into_alternate().set_open_drain();
-        let scl = gpiof.pf1.into_alternate().set_open_drain();
-        device.I2C2.i2c(
-            (scl, sda),
-            100.kHz(),
-            ccdr.peripheral.I2C2,
-            &ccdr.clocks,
-        )
-    };
-
     let metadata = {
         // Read the hardware version pins.
         let hardware_version = {
@@ -644,27 +745,7 @@ where
             )
         };
 
-        ApplicationMetadata::new(hardware_version)
-    };
-
-    let mac_addr = smoltcp::wire::EthernetAddress(eeprom::read_eui48(
-        &mut eeprom_i2c,
-        &mut delay,
-    ));
-    log::info!("EUI48: {}", mac_addr);
-
-    let (flash, mut settings) = {
-        let mut flash = {
-            let (_, flash_bank2) = device.FLASH.split();
-            super::flash::Flash(flash_bank2.unwrap())
-        };
-
-        let mut settings = C::new(NetSettings::new(mac_addr));
-        crate::settings::SerialSettingsPlatform::load(
-            &mut settings,
-            &mut flash,
-        );
-        (flash, settings)
+        ApplicationMetadata::new(hardware_version, &sampling)
     };
 
     let network_devices = {
@@ -826,7 +907,8 @@ where
     fp_led_2.set_low();
     fp_led_3.set_low();
 
-    let (_adc1, _adc2, adc3) = {
+    #[cfg_attr(not(feature = "pounder"), allow(unused_variables))]
+    let (adc1, adc2, adc3) = {
         let (mut adc1, mut adc2) = hal::adc::adc12(
             device.ADC1,
             device.ADC2,
@@ -877,6 +959,159 @@ where
         .calibrate_buffer(&mut delay)
         .enable();
 
+    #[cfg(feature = "pounder")]
+    let pounder = pounder_i2c.map(|i2c| {
+        let pounder_devices = pounder::PounderDevices::new(
+            i2c,
+            // Note(unwrap): SPI1 is configured for the attenuators if Pounder is present.
+            attenuator_spi.unwrap(),
+            (
+                adc1.create_channel(gpiof.pf11.into_analog()),
+                adc2.create_channel(gpiof.pf14.into_analog()),
+            ),
+            (
+                adc3.create_channel(gpiof.pf3.into_analog()),
+                adc3.create_channel(gpiof.pf4.into_analog()),
+            ),
+        )
+        .unwrap();
+
+        let ad9959 = {
+            let qspi_interface = {
+                // Instantiate the QUADSPI pins and peripheral interface.
+                let qspi_pins = {
+                    let _ncs =
+                        gpioc.pc11.into_alternate::<9>().speed(Speed::VeryHigh);
+
+                    let clk = gpiob.pb2.into_alternate().speed(Speed::VeryHigh);
+                    let io0 = gpioe.pe7.into_alternate().speed(Speed::VeryHigh);
+                    let io1 = gpioe.pe8.into_alternate().speed(Speed::VeryHigh);
+                    let io2 = gpioe.pe9.into_alternate().speed(Speed::VeryHigh);
+                    let io3 =
+                        gpioe.pe10.into_alternate().speed(Speed::VeryHigh);
+
+                    (clk, io0, io1, io2, io3)
+                };
+
+                let qspi = hal::xspi::Qspi::bank2(
+                    device.QUADSPI,
+                    qspi_pins,
+                    design_parameters::POUNDER_QSPI_FREQUENCY.convert(),
+                    &ccdr.clocks,
+                    ccdr.peripheral.QSPI,
+                );
+
+                pounder::QspiInterface::new(qspi).unwrap()
+            };
+
+            #[cfg(not(feature = "pounder_v1_0"))]
+            let reset_pin = gpiog.pg6.into_push_pull_output();
+            #[cfg(feature = "pounder_v1_0")]
+            let reset_pin = gpioa.pa0.into_push_pull_output();
+
+            let mut io_update = gpiog.pg7.into_push_pull_output();
+
+            let mut ad9959 = ad9959::Ad9959::new(
+                qspi_interface,
+                reset_pin,
+                &mut io_update,
+                &mut delay,
+                ad9959::Mode::FourBitSerial,
+                design_parameters::DDS_REF_CLK.to_Hz() as f32,
+                design_parameters::DDS_MULTIPLIER,
+            )
+            .unwrap();
+
+            assert!(ad9959.self_test().unwrap());
+
+            (ad9959, io_update)
+        };
+
+        let dds_output = {
+            let (ad9959, io_update) = ad9959;
+
+            // IO_Update is generated by the high resolution timer from here on.
+            let _io_update =
+                io_update.into_alternate::<2>().speed(Speed::VeryHigh);
+
+            let mut hrtimer = pounder::hrtimer::HighResTimerE::new(
+                device.HRTIM_TIME,
+                device.HRTIM_MASTER,
+                device.HRTIM_COMMON,
+                ccdr.clocks,
+                ccdr.peripheral.HRTIM,
+            );
+
+            // IO_Update occurs after a fixed delay from the QSPI write. Note that the timer is
+            // triggered after the QSPI write, which can take approximately 120nS, so there is
+            // additional margin.
+            hrtimer.configure_single_shot(
+                pounder::hrtimer::Channel::Two,
+                design_parameters::POUNDER_IO_UPDATE_DELAY,
+                design_parameters::POUNDER_IO_UPDATE_DURATION,
+            );
+
+            // Each batch can update the DDS at most once.
+            if sampling.batch_period()
+                < design_parameters::POUNDER_IO_UPDATE_DELAY
+            {
+                log::warn!("Batch period too short for a DDS update per batch");
+            }
+
+            let (qspi, mode) = ad9959.freeze();
+            pounder::dds_output::DdsOutput::new(qspi, hrtimer, mode)
+        };
+
+        #[cfg(not(feature = "pounder_v1_0"))]
+        let timestamper = {
+            // The timestamp timer is clocked by the DDS SYNC_CLK divided by 4.
+            let tick_frequency = design_parameters::DDS_SYSTEM_CLK.to_Hz()
+                / design_parameters::DDS_SYNC_CLK_DIV as u32
+                / 4;
+
+            // The timer overflows once per batch.
+            let period = (sample_ticks as u64 * batch_size as u64)
+                * tick_frequency as u64
+                / design_parameters::TIMER_FREQUENCY.to_Hz() as u64;
+
+            if batch_size <= 8 && (1..=1 << 16).contains(&period) {
+                let etr_pin = gpioa.pa0.into_alternate();
+
+                let tim8 = device.TIM8.timer(
+                    1.kHz(),
+                    ccdr.peripheral.TIM8,
+                    &ccdr.clocks,
+                );
+                let mut timestamp_timer =
+                    timers::PounderTimestampTimer::new(tim8);
+                timestamp_timer.set_external_clock(timers::Prescaler::Div4);
+                timestamp_timer.start();
+                timestamp_timer.set_period_ticks((period - 1) as u16);
+                let tim8_channels = timestamp_timer.channels();
+
+                Some(pounder::timestamp::Timestamper::new(
+                    timestamp_timer,
+                    tim8_channels.ch1,
+                    &mut sampling_timer,
+                    etr_pin,
+                    batch_size,
+                ))
+            } else {
+                log::warn!(
+                    "Sampling configuration does not support DDS timestamping"
+                );
+                None
+            }
+        };
+
+        PounderDevices {
+            pounder: pounder_devices,
+            dds_output,
+            #[cfg(not(feature = "pounder_v1_0"))]
+            timestamper,
+        }
+    });
+
     let eem_gpio = EemGpioDevices {
         lvds4: gpiod.pd1.into_floating_input(),
         lvds5: gpiod.pd2.into_floating_input(),
@@ -955,6 +1190,8 @@ where
                 ),
                 storage: flash,
                 metadata,
+                app_commands: &[],
+                pending_command: None,
                 _settings_marker: core::marker::PhantomData,
             },
             input_buffer,
@@ -970,6 +1207,8 @@ where
         dacs,
         gpio_dac_spi,
         cpu_dac1,
+        #[cfg(feature = "pounder")]
+        pounder,
         temperature_sensor: CpuTempSensor::new(
             adc3.create_channel(hal::adc::Temperature::new()),
         ),
@@ -983,6 +1222,7 @@ where
         usb: usb_device,
         metadata,
         settings,
+        sampling,
     };
 
     // info!("Version {} {}", build_info::PKG_VERSION, build_info::GIT_VERSION.unwrap());
@@ -177,6 +177,22 @@ impl SignalGenerator {
     pub fn clear_phase_accumulator(&mut self) {
         self.phase_accumulator = 0;
     }
+
+    /// The phase of the next sample including the phase offset.
+    pub fn phase(&self) -> i32 {
+        self.phase_accumulator
+            .wrapping_add(self.config.phase_offset)
+    }
+
+    /// The mean phase increment per sample, i.e. the frequency tuning word of the signal
+    /// fundamental.
+    pub fn frequency(&self) -> i32 {
+        let [first, second] = self.config.phase_increment.map(|p| p as i64);
+        // The harmonic mean of the increments of both half periods.
+        (2 * first * second)
+            .checked_div(first + second)
+            .unwrap_or_default() as i32
+    }
 }
 
 impl core::iter::Iterator for SignalGenerator {
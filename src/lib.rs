@@ -1,6 +1,8 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![cfg_attr(feature = "nightly", feature(core_intrinsics))]
 
+pub mod filter_design;
+pub mod fixed_iir;
 pub mod hardware;
 pub mod net;
 pub mod settings;
@@ -125,9 +125,22 @@ pub enum StreamFormat {
     /// ```
     AdcDacData = 1,
 
-    /// Streamed data in FLS (fiber length stabilization) format. See the FLS application for
-    /// detailed definition.
+    /// Streamed data in FLS (fiber length stabilization) format: the demodulated beat note, the
+    /// unwrapped phase and the DDS output tuning words of both channels of each batch. See the
+    /// FLS application for the detailed definition.
     Fls = 2,
+
+    /// Streamed data contains the lockin demodulation of each batch as `i32` in little-endian
+    /// format: the in-phase and quadrature outputs of the ADC0 and ADC1 demodulators (full scale
+    /// `i32`), the reference frequency (phase increment per sample, `1 << 32` is the sample rate)
+    /// and the reference phase at the first sample of the batch (`1 << 32` is a full turn).
+    ///
+    /// # Example
+    /// Each batch takes the following form:
+    /// ```
+    /// <I0> <Q0> <I1> <Q1> <frequency> <phase>
+    /// ```
+    Lockin = 3,
 }
 
 /// Configure streaming on a device.
@@ -246,6 +259,25 @@ impl FrameGenerator {
         self.format = format.into();
     }
 
+    /// Change the format of the stream at run-time.
+    ///
+    /// # Note:
+    /// Frames only contain batches of a single format. The current frame is sent before batches
+    /// of a new format are added.
+    ///
+    /// # Args
+    /// * `format` - The format of subsequent batches.
+    pub fn set_format(&mut self, format: impl Into<u8>) {
+        let format = format.into();
+        if format != self.format {
+            self.format = format;
+            if let Some(frame) = self.current_frame.take() {
+                // Note(unwrap): The queue is at least as large as the frame buffer count.
+                self.queue.enqueue(frame).unwrap();
+            }
+        }
+    }
+
     /// Add a batch to the current stream frame.
     ///
     /// # Args
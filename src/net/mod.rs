@@ -179,6 +179,13 @@ where
         }
     }
 
+    /// Republish all settings, e.g. after the application changed them.
+    pub fn republish_settings(&mut self) {
+        if self.miniconf.dump(None).is_err() {
+            log::warn!("Failed to republish settings");
+        }
+    }
+
     /// Update and process all of the network users state.
     ///
     /// # Returns
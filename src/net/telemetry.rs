@@ -5,11 +5,12 @@
 //! using standard JSON format.
 //!
 //! In order to report ADC/DAC codes generated during the DSP routines, a telemetry buffer is
-//! employed to track the latest codes. Converting these codes to SI units would result in
-//! repetitive and unnecessary calculations within the DSP routine, slowing it down and limiting
-//! sampling frequency. Instead, the raw codes are stored and the telemetry is generated as
-//! required immediately before transmission. This ensures that any slower computation required
-//! for unit conversion can be off-loaded to lower priority tasks.
+//! employed to accumulate statistics of the codes over the telemetry period. Converting these
+//! codes to SI units would result in repetitive and unnecessary calculations within the DSP
+//! routine, slowing it down and limiting sampling frequency. Instead, integer sums of the raw
+//! codes are accumulated and the telemetry is generated as required immediately before
+//! transmission. This ensures that any slower computation required for unit conversion can be
+//! off-loaded to lower priority tasks.
 use crate::hardware::metadata::ApplicationMetadata;
 use heapless::String;
 use minimq::{DeferredPublication, Publication};
@@ -18,6 +19,9 @@ use serde::Serialize;
 use super::NetworkReference;
 use crate::hardware::{adc::AdcCode, afe::Gain, dac::DacCode, SystemTimer};
 
+/// ADC codes with a magnitude at or above this value are counted as near full scale (95 %).
+const ADC_NEAR_FULL_SCALE: u16 = (i16::MAX as u32 * 95 / 100) as u16;
+
 /// Default metadata message if formatting errors occur.
 const DEFAULT_METADATA: &str = "{\"message\":\"Truncated: See USB terminal\"}";
 
@@ -34,18 +38,122 @@ pub struct TelemetryClient {
     metadata: &'static ApplicationMetadata,
 }
 
-/// The telemetry buffer is used for storing sample values during execution.
+/// Sample statistics accumulated in codes during execution.
+///
+/// # Note
+/// Accumulation stops after `u32::MAX` samples (about 1.5 hours at the highest sample rate) to
+/// prevent the sums from overflowing.
+#[derive(Copy, Clone, Debug)]
+pub struct StatisticsBuffer {
+    count: u32,
+    sum: i64,
+    sum_squares: u64,
+    min: i16,
+    max: i16,
+}
+
+impl Default for StatisticsBuffer {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            sum: 0,
+            sum_squares: 0,
+            min: i16::MAX,
+            max: i16::MIN,
+        }
+    }
+}
+
+impl StatisticsBuffer {
+    /// Accumulate a sample.
+    #[inline]
+    pub fn add(&mut self, code: i16) {
+        if self.count == u32::MAX {
+            return;
+        }
+        let x = code as i32;
+        self.count += 1;
+        self.sum += x as i64;
+        self.sum_squares += (x * x) as u32 as u64;
+        self.min = self.min.min(code);
+        self.max = self.max.max(code);
+    }
+
+    /// Convert the accumulated codes to statistics in SI units.
+    ///
+    /// # Args
+    /// * `scale` - The SI units per code, e.g. volts per LSB.
+    pub fn finalize(&self, scale: f32) -> Statistics {
+        if self.count == 0 {
+            return Statistics::default();
+        }
+        let count = self.count as f32;
+        Statistics {
+            count: self.count,
+            mean: self.sum as f32 / count * scale,
+            min: self.min as f32 * scale,
+            max: self.max as f32 * scale,
+            rms: libm::sqrtf(self.sum_squares as f32 / count) * scale.abs(),
+        }
+    }
+}
+
+/// Sample statistics over a telemetry period in SI units.
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct Statistics {
+    /// The number of samples.
+    pub count: u32,
+
+    /// The mean value.
+    pub mean: f32,
+
+    /// The minimum value.
+    pub min: f32,
+
+    /// The maximum value.
+    pub max: f32,
+
+    /// The root-mean-square value.
+    pub rms: f32,
+}
+
+/// Per-channel event counters accumulated over a telemetry period.
+///
+/// # Note
+/// Counters that do not apply to an application remain zero. Counters saturate at `u32::MAX`.
+#[derive(Copy, Clone, Debug, Default, Serialize)]
+pub struct Events {
+    /// The number of samples where a filter output was clamped to its output limits.
+    pub saturation: u32,
+
+    /// The number of samples where the output of an integrating filter was clamped, preventing
+    /// integrator windup.
+    pub windup: u32,
+
+    /// The number of ADC samples near full scale.
+    pub adc_near_full_scale: u32,
+
+    /// The number of DAC samples that were clipped to the DAC range.
+    pub dac_clip: u32,
+}
+
+/// The telemetry buffer is used for accumulating sample statistics during execution.
 ///
 /// # Note
 /// These values can be converted to SI units immediately before reporting to save processing time.
 /// This allows for the DSP process to continually update the values without incurring significant
 /// run-time overhead during conversion to SI units.
-#[derive(Copy, Clone)]
+///
+/// The buffer should be taken (and thereby reset) when reporting such that the statistics cover
+/// one telemetry period.
+#[derive(Copy, Clone, Default)]
 pub struct TelemetryBuffer {
-    /// The latest input sample on ADC0/ADC1.
-    pub adcs: [AdcCode; 2],
-    /// The latest output code on DAC0/DAC1.
-    pub dacs: [DacCode; 2],
+    /// The input sample statistics on ADC0/ADC1.
+    pub adcs: [StatisticsBuffer; 2],
+    /// The output code statistics on DAC0/DAC1.
+    pub dacs: [StatisticsBuffer; 2],
+    /// The event counters of channel 0/1.
+    pub events: [Events; 2],
     /// The latest digital input states during processing.
     pub digital_inputs: [bool; 2],
 }
@@ -57,11 +165,14 @@ pub struct TelemetryBuffer {
 /// overhead.
 #[derive(Serialize)]
 pub struct Telemetry {
-    /// Most recent input voltage measurement.
-    pub adcs: [f32; 2],
+    /// Input voltage statistics over the telemetry period.
+    pub adcs: [Statistics; 2],
+
+    /// Output voltage statistics over the telemetry period.
+    pub dacs: [Statistics; 2],
 
-    /// Most recent output voltage.
-    pub dacs: [f32; 2],
+    /// Event counters of channel 0/1 over the telemetry period.
+    pub events: [Events; 2],
 
     /// Most recent digital input assertion state.
     pub digital_inputs: [bool; 2],
@@ -70,17 +181,33 @@ pub struct Telemetry {
     pub cpu_temp: f32,
 }
 
-impl Default for TelemetryBuffer {
-    fn default() -> Self {
-        Self {
-            adcs: [AdcCode(0), AdcCode(0)],
-            dacs: [DacCode(0), DacCode(0)],
-            digital_inputs: [false, false],
+impl TelemetryBuffer {
+    /// Accumulate a batch of ADC and DAC samples.
+    ///
+    /// # Args
+    /// * `adcs` - The ADC0/ADC1 sample batches.
+    /// * `dacs` - The DAC0/DAC1 code batches.
+    #[inline]
+    pub fn add(&mut self, adcs: [&[u16]; 2], dacs: [&[u16]; 2]) {
+        for ((stats, events), samples) in
+            self.adcs.iter_mut().zip(self.events.iter_mut()).zip(adcs)
+        {
+            for &code in samples {
+                let code = AdcCode(code).into();
+                stats.add(code);
+                if i16::unsigned_abs(code) >= ADC_NEAR_FULL_SCALE {
+                    events.adc_near_full_scale =
+                        events.adc_near_full_scale.saturating_add(1);
+                }
+            }
+        }
+        for (stats, samples) in self.dacs.iter_mut().zip(dacs) {
+            for &code in samples {
+                stats.add(DacCode(code).into());
+            }
         }
     }
-}
 
-impl TelemetryBuffer {
     /// Convert the telemetry buffer to finalized, SI-unit telemetry for reporting.
     ///
     /// # Args
@@ -91,13 +218,19 @@ impl TelemetryBuffer {
     /// # Returns
     /// The finalized telemetry structure that can be serialized and reported.
     pub fn finalize(self, afe0: Gain, afe1: Gain, cpu_temp: f32) -> Telemetry {
-        let in0_volts = Into::<f32>::into(self.adcs[0]) / afe0.as_multiplier();
-        let in1_volts = Into::<f32>::into(self.adcs[1]) / afe1.as_multiplier();
-
         Telemetry {
             cpu_temp,
-            adcs: [in0_volts, in1_volts],
-            dacs: [self.dacs[0].into(), self.dacs[1].into()],
+            adcs: [
+                self.adcs[0]
+                    .finalize(AdcCode::VOLT_PER_LSB / afe0.as_multiplier()),
+                self.adcs[1]
+                    .finalize(AdcCode::VOLT_PER_LSB / afe1.as_multiplier()),
+            ],
+            dacs: [
+                self.dacs[0].finalize(DacCode::VOLT_PER_LSB),
+                self.dacs[1].finalize(DacCode::VOLT_PER_LSB),
+            ],
+            events: self.events,
             digital_inputs: self.digital_inputs,
         }
     }
@@ -139,23 +272,52 @@ impl TelemetryClient {
     /// # Args
     /// * `telemetry` - The telemetry to report
     pub fn publish<T: Serialize>(&mut self, telemetry: &T) {
-        let mut topic: String<128> = self.prefix.try_into().unwrap();
-        topic.push_str("/telemetry").unwrap();
-
-        self.mqtt
-            .client()
-            .publish(
-                minimq::DeferredPublication::new(|buf| {
-                    serde_json_core::to_slice(telemetry, buf)
-                })
-                .topic(&topic)
-                .finish()
-                .unwrap(),
-            )
+        self.publish_to("telemetry", telemetry)
+    }
+
+    /// Publish data on an arbitrary topic below the device prefix over MQTT
+    ///
+    /// # Note
+    /// Data is reported in a "best-effort" fashion. Failure to transmit data will cause it to be
+    /// silently dropped.
+    ///
+    /// # Args
+    /// * `topic` - The topic to publish to, relative to the device prefix.
+    /// * `data` - The data to report
+    pub fn publish_to<T: Serialize>(&mut self, topic: &str, data: &T) {
+        self.try_publish_to(topic, data)
             .map_err(|e| log::error!("Telemetry publishing error: {:?}", e))
             .ok();
     }
 
+    /// Attempt to publish data on an arbitrary topic below the device prefix over MQTT
+    ///
+    /// # Note
+    /// Unlike [TelemetryClient::publish_to], failure to transmit is reported to the caller, e.g.
+    /// to retry transfers that must not lose data.
+    ///
+    /// # Args
+    /// * `topic` - The topic to publish to, relative to the device prefix.
+    /// * `data` - The data to report
+    pub fn try_publish_to<T: Serialize>(
+        &mut self,
+        topic: &str,
+        data: &T,
+    ) -> Result<(), impl core::fmt::Debug> {
+        let mut full_topic: String<128> = self.prefix.try_into().unwrap();
+        full_topic.push('/').unwrap();
+        full_topic.push_str(topic).unwrap();
+
+        self.mqtt.client().publish(
+            minimq::DeferredPublication::new(|buf| {
+                serde_json_core::to_slice(data, buf)
+            })
+            .topic(&full_topic)
+            .finish()
+            .unwrap(),
+        )
+    }
+
     /// Update the telemetry client
     ///
     /// # Note
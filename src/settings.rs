@@ -22,7 +22,10 @@
 //!    settings values
 //! 3. Unknown/unneeded settings values in flash can be actively ignored, facilitating simple flash
 //!    storage sharing.
-use crate::hardware::{flash::Flash, metadata::ApplicationMetadata, platform};
+use crate::hardware::{
+    design_parameters, flash::Flash, metadata::ApplicationMetadata, platform,
+    MONOTONIC_FREQUENCY,
+};
 use core::fmt::Write;
 use embassy_futures::block_on;
 use embedded_io::Write as EioWrite;
@@ -67,12 +70,87 @@ impl NetSettings {
     }
 }
 
+/// Settings for the ADC/DAC sampling and batch processing.
+///
+/// # Note
+/// These settings are only applied at boot. Store them to flash and reboot for changes to take
+/// effect. Settings that depend on the sample period (e.g. signal generator or demodulation
+/// frequencies) are validated against the applied sampling configuration and may have to be
+/// adjusted after changing it.
+#[derive(Copy, Clone, Debug, Tree)]
+pub struct SamplingSettings {
+    /// The logarithm of the number of 100 MHz timer ticks between samples. The default of 7
+    /// corresponds to 128 ticks or 1.28 µs per sample (781.25 kHz). Valid values are 7 to 15.
+    pub sample_ticks_log2: u8,
+
+    /// The logarithm of the number of samples in each batch process. The default of 3 corresponds
+    /// to 8 samples per batch. Valid values are 0 to 5. Smaller batches reduce latency at the
+    /// expense of processing overhead.
+    pub batch_size_log2: u8,
+}
+
+impl Default for SamplingSettings {
+    fn default() -> Self {
+        Self {
+            sample_ticks_log2: 7,
+            batch_size_log2: 3,
+        }
+    }
+}
+
+impl SamplingSettings {
+    /// The number of timer ticks between samples.
+    pub fn sample_ticks(&self) -> u32 {
+        1 << self.sample_ticks_log2
+    }
+
+    /// The number of samples in each batch.
+    pub fn batch_size(&self) -> usize {
+        1 << self.batch_size_log2
+    }
+
+    /// The time between samples in seconds.
+    pub fn sample_period(&self) -> f32 {
+        self.sample_ticks() as f32 * design_parameters::TIMER_PERIOD
+    }
+
+    /// The time between batches in seconds.
+    pub fn batch_period(&self) -> f32 {
+        self.sample_period() * self.batch_size() as f32
+    }
+
+    /// Check that the sampling configuration is supported by the hardware.
+    pub fn validate(&self) -> Result<(), &'static str> {
+        // The shadow sampling timer period is limited to 16 bits.
+        if !(7..=15).contains(&self.sample_ticks_log2) {
+            return Err("Sample ticks out of range");
+        }
+
+        // Check the logarithm first as `batch_size()` overflows for large values.
+        if self.batch_size_log2 as u32
+            > design_parameters::MAX_SAMPLE_BUFFER_SIZE.ilog2()
+        {
+            return Err("Batch size exceeds sample buffer size");
+        }
+
+        // The batch period must not exceed the RTIC monotonic timer period.
+        if self.batch_period() * MONOTONIC_FREQUENCY as f32 >= 1. {
+            return Err("Batch period exceeds monotonic timer period");
+        }
+
+        Ok(())
+    }
+}
+
 pub trait AppSettings {
     /// Construct the settings given known network settings.
     fn new(net: NetSettings) -> Self;
 
     /// Get the network settings from the application settings.
     fn net(&self) -> &NetSettings;
+
+    /// Get the sampling settings from the application settings.
+    fn sampling(&self) -> &SamplingSettings;
 }
 
 #[derive(
@@ -111,6 +189,22 @@ pub struct SerialSettingsPlatform<C, const Y: usize> {
 
     /// Metadata associated with the application
     pub metadata: &'static ApplicationMetadata,
+
+    /// Application-specific platform commands.
+    pub app_commands: &'static [&'static str],
+
+    /// The most recent application-specific command that has not been handled yet.
+    pub pending_command: Option<&'static str>,
+}
+
+impl<C, const Y: usize> SerialSettingsPlatform<C, Y> {
+    /// Take the pending application-specific platform command.
+    ///
+    /// # Returns
+    /// The command as one of the `app_commands`, if any command was issued since the last call.
+    pub fn take_command(&mut self) -> Option<&'static str> {
+        self.pending_command.take()
+    }
 }
 
 impl<C, const Y: usize> SerialSettingsPlatform<C, Y>
@@ -246,12 +340,23 @@ where
                 )
                 .unwrap();
             }
-            _ => {
-                writeln!(
+            cmd => {
+                if let Some(&cmd) =
+                    self.app_commands.iter().find(|&&app_cmd| app_cmd == cmd)
+                {
+                    self.pending_command = Some(cmd);
+                    return;
+                }
+
+                write!(
                     self.interface_mut(),
-                    "Invalid platform command: `{cmd}` not in [`dfu`, `reboot`, `service`]"
+                    "Invalid platform command: `{cmd}` not in [`dfu`, `reboot`, `service`"
                 )
                 .ok();
+                for app_cmd in self.app_commands {
+                    write!(self.interface_mut(), ", `{app_cmd}`").ok();
+                }
+                writeln!(self.interface_mut(), "]").ok();
             }
         }
     }